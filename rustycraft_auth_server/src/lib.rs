@@ -0,0 +1,11 @@
+pub mod listener;
+pub mod packets;
+pub mod session;
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate anyhow;
+
+/// Client builds accepted by the grunt login: 3.3.5a.
+pub const SUPPORTED_BUILDS: &[u16] = &[12340];
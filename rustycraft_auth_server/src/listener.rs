@@ -0,0 +1,30 @@
+use crate::session::AuthSession;
+use rustycraft_common::shutdown::Shutdown;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+pub struct AuthListener {
+    pub bind_address: SocketAddr,
+}
+
+impl AuthListener {
+    /// Accepts connections until `shutdown` fires. Sessions keep a clone of `shutdown`
+    /// until they are closed, so the caller can wait for them to drain.
+    pub async fn run_forever(self, mut shutdown: Shutdown) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.bind_address).await?;
+
+        info!(target: "AuthListener", "Auth server listening on: {}", self.bind_address);
+
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.recv() => break,
+            };
+            if let Ok((stream, _)) = accepted {
+                tokio::spawn(AuthSession::new(stream)?.handle(shutdown.clone()));
+            }
+        }
+        info!(target: "AuthListener", "Auth server stopped accepting connections");
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+use rustycraft_auth_server::listener::AuthListener;
+use rustycraft_common::config;
+use rustycraft_common::shutdown::{self, ShutdownController};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let _ = rustycraft_logging::init_logging();
+    let config = config::init()?;
+    let shutdown = ShutdownController::new();
+    if let Some(metrics_bind_address) = config.auth.metrics_bind_address {
+        let mut metrics_shutdown = shutdown.subscribe();
+        tokio::spawn(rustycraft_metrics::serve(
+            metrics_bind_address,
+            async move { metrics_shutdown.recv().await },
+        )?);
+    }
+    let listener = AuthListener {
+        bind_address: config.auth.bind_address,
+    };
+    let mut listener = tokio::spawn(listener.run_forever(shutdown.subscribe()));
+    tokio::select! {
+        result = shutdown::wait_for_signal() => result?,
+        result = &mut listener => result??,
+    };
+    log::info!("Shutting down, draining connections");
+    if !shutdown
+        .shutdown(Duration::from_secs(config.auth.shutdown_timeout_secs))
+        .await
+    {
+        log::warn!("Some connections did not close in time");
+    }
+    Ok(())
+}
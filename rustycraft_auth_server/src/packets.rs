@@ -0,0 +1,349 @@
+use deku::prelude::*;
+use rustycraft_common::srp6::{G, KEY_LENGTH, N};
+
+/// Sent after the salt in both challenges. Clients do not check it without a version patch.
+pub const VERSION_CHALLENGE: [u8; 16] = [
+    0xBA, 0xA3, 0x1E, 0x99, 0xA0, 0x0B, 0x21, 0x57, 0xFC, 0x37, 0x3F, 0xB3, 0x69, 0xCD, 0xD2, 0xF1,
+];
+/// Shown as the realm list footer by the client.
+const REALM_LIST_FOOTER: u16 = 0x0010;
+/// Realm flags the grunt realm list understands, `SPECIFYBUILD` is left out on purpose.
+const REALM_FLAGS_MASK: u32 = 0xFB;
+
+fn parse_string(input: Vec<u8>) -> Result<String, DekuError> {
+    String::from_utf8(input).map_err(|e| DekuError::Parse(e.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(type = "u8")]
+#[repr(u8)]
+pub enum AuthCommand {
+    LogonChallenge = 0x00,
+    LogonProof = 0x01,
+    ReconnectChallenge = 0x02,
+    ReconnectProof = 0x03,
+    RealmList = 0x10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DekuWrite)]
+#[deku(type = "u8")]
+#[repr(u8)]
+pub enum AuthResult {
+    Success = 0x00,
+    Banned = 0x03,
+    UnknownAccount = 0x04,
+    IncorrectPassword = 0x05,
+    DbBusy = 0x08,
+    VersionInvalid = 0x09,
+    Suspended = 0x0C,
+}
+
+/// Body of `AUTH_LOGON_CHALLENGE` and `AUTH_RECONNECT_CHALLENGE`, after the command byte.
+#[derive(Debug, DekuRead)]
+pub struct LogonChallenge {
+    pub protocol_version: u8,
+    /// Bytes following this field.
+    #[deku(endian = "little")]
+    pub size: u16,
+    pub game_name: [u8; 4],
+    pub version: [u8; 3],
+    #[deku(endian = "little")]
+    pub build: u16,
+    pub platform: [u8; 4],
+    pub os: [u8; 4],
+    pub country: [u8; 4],
+    #[deku(endian = "little")]
+    pub timezone_bias: u32,
+    pub ip: [u8; 4],
+    _username_len: u8,
+    #[deku(count = "_username_len", map = "parse_string")]
+    pub username: String,
+}
+
+impl LogonChallenge {
+    /// `protocol_version` and `size`.
+    pub const HEADER_SIZE: usize = 3;
+}
+
+#[derive(Debug, DekuRead)]
+pub struct LogonProof {
+    pub public_a: [u8; KEY_LENGTH],
+    pub client_proof: [u8; 20],
+    pub crc_hash: [u8; 20],
+    pub number_of_keys: u8,
+    pub security_flags: u8,
+}
+
+impl LogonProof {
+    pub const SIZE: usize = 74;
+}
+
+#[derive(Debug, DekuRead)]
+pub struct ReconnectProof {
+    pub client_data: [u8; 16],
+    pub client_proof: [u8; 20],
+    pub client_checksum: [u8; 20],
+    pub number_of_keys: u8,
+}
+
+impl ReconnectProof {
+    pub const SIZE: usize = 57;
+}
+
+/// `REALM_LIST` carries 4 unused bytes.
+pub const REALM_LIST_REQUEST_SIZE: usize = 4;
+
+#[derive(Debug, DekuWrite)]
+pub struct LogonChallengeBody {
+    public_b: [u8; KEY_LENGTH],
+    g_len: u8,
+    g: u8,
+    n_len: u8,
+    n: [u8; KEY_LENGTH],
+    salt: [u8; KEY_LENGTH],
+    version_challenge: [u8; 16],
+    security_flags: u8,
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct LogonChallengeResponse {
+    command: AuthCommand,
+    protocol_version: u8,
+    result: AuthResult,
+    body: Option<LogonChallengeBody>,
+}
+
+impl LogonChallengeResponse {
+    pub fn new(public_b: [u8; KEY_LENGTH], salt: [u8; KEY_LENGTH]) -> LogonChallengeResponse {
+        LogonChallengeResponse {
+            command: AuthCommand::LogonChallenge,
+            protocol_version: 0,
+            result: AuthResult::Success,
+            body: Some(LogonChallengeBody {
+                public_b,
+                g_len: 1,
+                g: G,
+                n_len: KEY_LENGTH as u8,
+                n: N,
+                salt,
+                version_challenge: VERSION_CHALLENGE,
+                security_flags: 0,
+            }),
+        }
+    }
+
+    pub fn failure(result: AuthResult) -> LogonChallengeResponse {
+        LogonChallengeResponse {
+            command: AuthCommand::LogonChallenge,
+            protocol_version: 0,
+            result,
+            body: None,
+        }
+    }
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct LogonProofBody {
+    server_proof: [u8; 20],
+    #[deku(endian = "little")]
+    account_flags: u32,
+    #[deku(endian = "little")]
+    survey_id: u32,
+    #[deku(endian = "little")]
+    login_flags: u16,
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct LogonProofResponse {
+    command: AuthCommand,
+    result: AuthResult,
+    body: Option<LogonProofBody>,
+    /// Sent instead of the body on failure.
+    failure_padding: Option<[u8; 2]>,
+}
+
+impl LogonProofResponse {
+    /// `ACCOUNT_FLAG_PROPASS`, the only flag 3.3.5a clients act upon.
+    const ACCOUNT_FLAGS: u32 = 0x0080_0000;
+
+    pub fn new(server_proof: [u8; 20]) -> LogonProofResponse {
+        LogonProofResponse {
+            command: AuthCommand::LogonProof,
+            result: AuthResult::Success,
+            body: Some(LogonProofBody {
+                server_proof,
+                account_flags: Self::ACCOUNT_FLAGS,
+                survey_id: 0,
+                login_flags: 0,
+            }),
+            failure_padding: None,
+        }
+    }
+
+    pub fn failure(result: AuthResult) -> LogonProofResponse {
+        LogonProofResponse {
+            command: AuthCommand::LogonProof,
+            result,
+            body: None,
+            failure_padding: Some([3, 0]),
+        }
+    }
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct ReconnectChallengeBody {
+    challenge: [u8; 16],
+    version_challenge: [u8; 16],
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct ReconnectChallengeResponse {
+    command: AuthCommand,
+    result: AuthResult,
+    body: Option<ReconnectChallengeBody>,
+}
+
+impl ReconnectChallengeResponse {
+    pub fn new(challenge: [u8; 16]) -> ReconnectChallengeResponse {
+        ReconnectChallengeResponse {
+            command: AuthCommand::ReconnectChallenge,
+            result: AuthResult::Success,
+            body: Some(ReconnectChallengeBody {
+                challenge,
+                version_challenge: VERSION_CHALLENGE,
+            }),
+        }
+    }
+
+    pub fn failure(result: AuthResult) -> ReconnectChallengeResponse {
+        ReconnectChallengeResponse {
+            command: AuthCommand::ReconnectChallenge,
+            result,
+            body: None,
+        }
+    }
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct ReconnectProofResponse {
+    command: AuthCommand,
+    result: AuthResult,
+    #[deku(endian = "little")]
+    login_flags: u16,
+}
+
+impl Default for ReconnectProofResponse {
+    fn default() -> Self {
+        ReconnectProofResponse::new()
+    }
+}
+
+impl ReconnectProofResponse {
+    pub fn new() -> ReconnectProofResponse {
+        ReconnectProofResponse {
+            command: AuthCommand::ReconnectProof,
+            result: AuthResult::Success,
+            login_flags: 0,
+        }
+    }
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct RealmInfo {
+    realm_type: u8,
+    locked: u8,
+    flags: u8,
+    /// NUL terminated.
+    name: Vec<u8>,
+    /// `host:port`, NUL terminated.
+    address: Vec<u8>,
+    #[deku(endian = "little")]
+    population: f32,
+    characters: u8,
+    timezone: u8,
+    id: u8,
+}
+
+impl RealmInfo {
+    pub fn new(id: u8, name: &str, address: &str, flags: u32) -> RealmInfo {
+        let c_string = |s: &str| s.bytes().chain(std::iter::once(0)).collect::<Vec<u8>>();
+        RealmInfo {
+            realm_type: 0,
+            locked: 0,
+            flags: (flags & REALM_FLAGS_MASK) as u8,
+            name: c_string(name),
+            address: c_string(address),
+            population: 0.0,
+            characters: 0,
+            timezone: 1,
+            id,
+        }
+    }
+
+    fn size(&self) -> usize {
+        3 + self.name.len() + self.address.len() + 4 + 3
+    }
+}
+
+#[derive(Debug, DekuWrite)]
+pub struct RealmListResponse {
+    command: AuthCommand,
+    /// Bytes following this field.
+    #[deku(endian = "little")]
+    size: u16,
+    #[deku(endian = "little")]
+    unused: u32,
+    #[deku(endian = "little")]
+    count: u16,
+    realms: Vec<RealmInfo>,
+    #[deku(endian = "little")]
+    footer: u16,
+}
+
+impl RealmListResponse {
+    pub fn new(realms: Vec<RealmInfo>) -> RealmListResponse {
+        let size = 4 + 2 + realms.iter().map(RealmInfo::size).sum::<usize>() + 2;
+        RealmListResponse {
+            command: AuthCommand::RealmList,
+            size: size as u16,
+            unused: 0,
+            count: realms.len() as u16,
+            realms,
+            footer: REALM_LIST_FOOTER,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let mut challenge = vec![0x08, 0x24, 0x00];
+        challenge.extend(b"WoW\0");
+        challenge.extend([3, 3, 5]);
+        challenge.extend(12340u16.to_le_bytes());
+        challenge.extend(b"68x\0niW\0SUne");
+        challenge.extend(60u32.to_le_bytes());
+        challenge.extend([127, 0, 0, 1]);
+        challenge.push(6);
+        challenge.extend(b"PLAYER");
+        let (_, challenge) = LogonChallenge::from_bytes((&challenge, 0)).unwrap();
+        assert_eq!(challenge.build, 12340);
+        assert_eq!(challenge.username, "PLAYER");
+
+        let failure = LogonProofResponse::failure(AuthResult::UnknownAccount);
+        assert_eq!(failure.to_bytes().unwrap(), vec![0x01, 0x04, 0x03, 0x00]);
+        let response = LogonChallengeResponse::new([1; KEY_LENGTH], [2; KEY_LENGTH]);
+        assert_eq!(response.to_bytes().unwrap().len(), 119);
+
+        let list = RealmListResponse::new(vec![RealmInfo::new(1, "Rusty", "127.0.0.1:8085", 0)]);
+        let bytes = list.to_bytes().unwrap();
+        assert_eq!(
+            u16::from_le_bytes([bytes[1], bytes[2]]) as usize,
+            bytes.len() - 3
+        );
+        assert_eq!(&bytes[bytes.len() - 2..], &[0x10, 0x00]);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::packets::{
+    AuthCommand, AuthResult, LogonChallenge, LogonChallengeResponse, LogonProof,
+    LogonProofResponse, RealmInfo, RealmListResponse, ReconnectChallengeResponse, ReconnectProof,
+    ReconnectProofResponse, REALM_LIST_REQUEST_SIZE,
+};
+use crate::SUPPORTED_BUILDS;
+use deku::prelude::*;
+use rustycraft_common::accounts::AccountInfo;
+use rustycraft_common::config;
+use rustycraft_common::shutdown::Shutdown;
+use rustycraft_common::srp6::{self, Srp6Server, KEY_LENGTH};
+use rustycraft_common::{LegacySession, Realm};
+use rustycraft_database::redis::RedisClient;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Longest username the client can type.
+const MAX_CHALLENGE_SIZE: u16 = 0x100;
+
+/// An account that was sent a logon challenge and has yet to prove its password.
+struct PendingLogon {
+    account: AccountInfo,
+    build: u16,
+    srp: Srp6Server,
+}
+
+enum State {
+    Connected,
+    LogonChallenged(Box<PendingLogon>),
+    ReconnectChallenged {
+        username: String,
+        session: LegacySession,
+        challenge: [u8; 16],
+    },
+    Authenticated,
+    /// A proof failed, the connection is closed once the answer is sent.
+    Closed,
+}
+
+pub struct AuthSession {
+    addr: SocketAddr,
+    redis: RedisClient,
+    socket: TcpStream,
+    state: State,
+}
+
+impl AuthSession {
+    pub fn new(socket: TcpStream) -> anyhow::Result<AuthSession> {
+        Ok(AuthSession {
+            addr: socket.peer_addr()?,
+            redis: RedisClient::new(&config::get().redis.url)?,
+            socket,
+            state: State::Connected,
+        })
+    }
+
+    pub async fn handle(mut self, mut shutdown: Shutdown) -> anyhow::Result<()> {
+        let connected = rustycraft_metrics::SESSIONS.with_label_values(&["auth"]);
+        connected.inc();
+        let result = self.serve(&mut shutdown).await;
+        connected.dec();
+        if let Err(e) = &result {
+            debug!(target: "AuthSession", "[{:?}] Connection closed: {}", self.addr, e);
+        }
+        result
+    }
+
+    async fn serve(&mut self, shutdown: &mut Shutdown) -> anyhow::Result<()> {
+        loop {
+            let command = tokio::select! {
+                command = self.socket.read_u8() => command?,
+                _ = shutdown.recv() => break,
+            };
+            let (_, command) = AuthCommand::from_bytes((&[command], 0))?;
+            let state = std::mem::replace(&mut self.state, State::Connected);
+            self.state = match (command, state) {
+                (AuthCommand::LogonChallenge, State::Connected) => {
+                    let challenge = self.read_challenge().await?;
+                    self.logon_challenge(challenge).await?
+                }
+                (AuthCommand::LogonProof, State::LogonChallenged(pending)) => {
+                    let proof = LogonProof::from_bytes((&self.read(LogonProof::SIZE).await?, 0))?.1;
+                    self.logon_proof(proof, *pending).await?
+                }
+                (AuthCommand::ReconnectChallenge, State::Connected) => {
+                    let challenge = self.read_challenge().await?;
+                    self.reconnect_challenge(challenge).await?
+                }
+                (
+                    AuthCommand::ReconnectProof,
+                    State::ReconnectChallenged {
+                        username,
+                        session,
+                        challenge,
+                    },
+                ) => {
+                    let proof =
+                        ReconnectProof::from_bytes((&self.read(ReconnectProof::SIZE).await?, 0))?.1;
+                    self.reconnect_proof(proof, username, session, challenge)
+                        .await?
+                }
+                (AuthCommand::RealmList, State::Authenticated) => {
+                    self.read(REALM_LIST_REQUEST_SIZE).await?;
+                    self.realm_list().await?;
+                    State::Authenticated
+                }
+                (command, _) => bail!("Unexpected {:?}", command),
+            };
+            if let State::Closed = self.state {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self, size: usize) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0; size];
+        self.socket.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    async fn read_challenge(&mut self) -> anyhow::Result<LogonChallenge> {
+        let mut data = self.read(LogonChallenge::HEADER_SIZE).await?;
+        let size = u16::from_le_bytes([data[1], data[2]]);
+        if size > MAX_CHALLENGE_SIZE {
+            bail!("Challenge of {} bytes is too long", size);
+        }
+        data.extend(self.read(size as usize).await?);
+        Ok(LogonChallenge::from_bytes((&data, 0))?.1)
+    }
+
+    async fn send<T: DekuContainerWrite>(&mut self, packet: T) -> anyhow::Result<()> {
+        self.socket.write_all(&packet.to_bytes()?).await?;
+        Ok(())
+    }
+
+    /// Answers a failed login and keeps the connection open for another attempt.
+    async fn reject_logon(&mut self, result: AuthResult) -> anyhow::Result<State> {
+        rustycraft_metrics::HANDSHAKE_FAILURES
+            .with_label_values(&["auth"])
+            .inc();
+        self.send(LogonChallengeResponse::failure(result)).await?;
+        Ok(State::Connected)
+    }
+
+    /// The account, or the result to answer with when it may not log in.
+    async fn find_account(
+        &mut self,
+        username: &str,
+    ) -> anyhow::Result<Result<AccountInfo, AuthResult>> {
+        let account = match self
+            .redis
+            .peek::<AccountInfo>(&username.to_lowercase())
+            .await?
+        {
+            Some(account) => account,
+            None => return Ok(Err(AuthResult::UnknownAccount)),
+        };
+        if let Some(ban) = account.active_ban() {
+            info!(target: "AuthSession", "[{:?}] Banned account {}: {}", self.addr, account.account_name, ban.reason);
+            return Ok(Err(match ban.expires_at {
+                Some(_) => AuthResult::Suspended,
+                None => AuthResult::Banned,
+            }));
+        }
+        Ok(Ok(account))
+    }
+
+    async fn logon_challenge(&mut self, challenge: LogonChallenge) -> anyhow::Result<State> {
+        debug!(target: "AuthSession", "[{:?}] Logon challenge: {:?}", self.addr, challenge);
+        if !SUPPORTED_BUILDS.contains(&challenge.build) {
+            return self.reject_logon(AuthResult::VersionInvalid).await;
+        }
+        let account = match self.find_account(&challenge.username).await? {
+            Ok(account) => account,
+            Err(result) => return self.reject_logon(result).await,
+        };
+        if account.srp6_verifier.is_empty() {
+            warn!(target: "AuthSession", "[{:?}] Account {} has no SRP6 verifier, its password must be set again",
+                self.addr, account.account_name);
+            return self.reject_logon(AuthResult::UnknownAccount).await;
+        }
+        let srp = Srp6Server::new(
+            &challenge.username,
+            &account.srp6_salt,
+            &account.srp6_verifier,
+        );
+        let mut salt = [0; KEY_LENGTH];
+        salt.copy_from_slice(srp.salt());
+        self.send(LogonChallengeResponse::new(*srp.public_b(), salt))
+            .await?;
+        Ok(State::LogonChallenged(Box::new(PendingLogon {
+            account,
+            build: challenge.build,
+            srp,
+        })))
+    }
+
+    async fn logon_proof(
+        &mut self,
+        proof: LogonProof,
+        pending: PendingLogon,
+    ) -> anyhow::Result<State> {
+        let PendingLogon {
+            account,
+            build,
+            srp,
+        } = pending;
+        let session_proof = match srp.verify(&proof.public_a, &proof.client_proof) {
+            Some(session_proof) => session_proof,
+            None => {
+                info!(target: "AuthSession", "[{:?}] Wrong password for {}", self.addr, account.account_name);
+                rustycraft_metrics::HANDSHAKE_FAILURES
+                    .with_label_values(&["auth"])
+                    .inc();
+                self.send(LogonProofResponse::failure(AuthResult::UnknownAccount))
+                    .await?;
+                return Ok(State::Closed);
+            }
+        };
+        let session = LegacySession {
+            account_name: account.account_name.clone(),
+            session_key: session_proof.session_key.to_vec(),
+            build,
+        };
+        self.redis.set(&account.account_name, &session).await?;
+        self.send(LogonProofResponse::new(session_proof.server_proof))
+            .await?;
+        info!(target: "AuthSession", "[{:?}] {} logged in", self.addr, account.account_name);
+        Ok(State::Authenticated)
+    }
+
+    async fn reconnect_challenge(&mut self, challenge: LogonChallenge) -> anyhow::Result<State> {
+        let session = match self.find_account(&challenge.username).await? {
+            Ok(account) => {
+                self.redis
+                    .peek::<LegacySession>(&account.account_name)
+                    .await?
+            }
+            Err(_) => None,
+        };
+        let session = match session {
+            Some(session) if SUPPORTED_BUILDS.contains(&challenge.build) => session,
+            _ => {
+                rustycraft_metrics::HANDSHAKE_FAILURES
+                    .with_label_values(&["auth"])
+                    .inc();
+                self.send(ReconnectChallengeResponse::failure(
+                    AuthResult::UnknownAccount,
+                ))
+                .await?;
+                return Ok(State::Connected);
+            }
+        };
+        let challenge_data = rand::random();
+        self.send(ReconnectChallengeResponse::new(challenge_data))
+            .await?;
+        Ok(State::ReconnectChallenged {
+            username: challenge.username,
+            session,
+            challenge: challenge_data,
+        })
+    }
+
+    async fn reconnect_proof(
+        &mut self,
+        proof: ReconnectProof,
+        username: String,
+        session: LegacySession,
+        challenge: [u8; 16],
+    ) -> anyhow::Result<State> {
+        let expected = srp6::reconnect_proof(
+            &username,
+            &proof.client_data,
+            &challenge,
+            &session.session_key,
+        );
+        if expected != proof.client_proof {
+            info!(target: "AuthSession", "[{:?}] Wrong reconnect proof for {}", self.addr, session.account_name);
+            rustycraft_metrics::HANDSHAKE_FAILURES
+                .with_label_values(&["auth"])
+                .inc();
+            return Ok(State::Closed);
+        }
+        self.send(ReconnectProofResponse::new()).await?;
+        info!(target: "AuthSession", "[{:?}] {} reconnected", self.addr, session.account_name);
+        Ok(State::Authenticated)
+    }
+
+    async fn realm_list(&mut self) -> anyhow::Result<()> {
+        let realms = Realm::list(&self.redis)
+            .await?
+            .iter()
+            .enumerate()
+            .map(|(idx, realm)| {
+                let address = format!("{}:{}", realm.address, realm.port);
+                RealmInfo::new(idx as u8 + 1, &realm.name, &address, realm.flags)
+            })
+            .collect();
+        self.send(RealmListResponse::new(realms)).await
+    }
+}
@@ -0,0 +1,507 @@
+use axum::async_trait;
+use axum::extract::{extractor_middleware, Extension, FromRequest, Path, Query, RequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use log::{error, info};
+use rustls::ServerConfig;
+use rustycraft_common::accounts::{unix_now, AccountInfo, Ban};
+use rustycraft_common::audit::{AuditLog, AuditRecord};
+use rustycraft_common::hotfixes::{Hotfix, HotfixStore};
+use rustycraft_common::sessions::SessionRegistry;
+use rustycraft_common::shutdown::Shutdown;
+use rustycraft_common::telemetry::{TelemetryRecord, TelemetryStore};
+use rustycraft_common::{config, Realm};
+use rustycraft_database::redis::{escape_pattern, RedisClient};
+use rustycraft_protocol::rpc_responses::WowRpcResponse;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Authenticated HTTPS API for game masters, bound separately from the login form.
+pub struct AdminServiceHandler {
+    pub bind_address: SocketAddr,
+    pub tls_context: ServerConfig,
+    pub token: String,
+    pub bnet_sessions: Arc<SessionRegistry>,
+    pub world_sessions: Arc<SessionRegistry>,
+}
+
+struct Context {
+    redis: RedisClient,
+    telemetry: TelemetryStore,
+    hotfixes: HotfixStore,
+    audit: AuditLog,
+    token: String,
+    bnet_sessions: Arc<SessionRegistry>,
+    world_sessions: Arc<SessionRegistry>,
+}
+
+/// Every admin error is reported as `{"error": "..."}`.
+struct AdminError(StatusCode, String);
+
+type AdminResult<T> = Result<Json<T>, AdminError>;
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({ "error": self.1 }));
+        (self.0, body).into_response()
+    }
+}
+
+impl From<anyhow::Error> for AdminError {
+    fn from(e: anyhow::Error) -> Self {
+        error!(target: "AdminService", "Request failed: {}", e);
+        AdminError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal error".to_owned(),
+        )
+    }
+}
+
+fn not_found(what: &str) -> AdminError {
+    AdminError(StatusCode::NOT_FOUND, format!("{} not found", what))
+}
+
+/// The client login form caps passwords at 16 characters.
+fn check_password(password: &str) -> Result<(), AdminError> {
+    if password.is_empty() || password.len() > 16 {
+        return Err(AdminError(
+            StatusCode::BAD_REQUEST,
+            "Password must be 1 to 16 characters long".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects requests without `Authorization: Bearer <admin.token>`.
+struct RequireToken;
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for RequireToken {
+    type Rejection = AdminError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let context = req
+            .extensions()
+            .and_then(|extensions| extensions.get::<Arc<Context>>())
+            .ok_or_else(|| anyhow::anyhow!("Admin context is missing"))?;
+        let provided = req
+            .headers()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if constant_time_eq(provided.as_bytes(), context.token.as_bytes()) {
+            Ok(RequireToken)
+        } else {
+            Err(AdminError(
+                StatusCode::UNAUTHORIZED,
+                "Invalid token".to_owned(),
+            ))
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Serialize, Debug)]
+struct AccountView {
+    account_name: String,
+    created_at: u64,
+    ban: Option<Ban>,
+    online_bnet: bool,
+    online_world: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct AccountSearch {
+    search: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NewAccount {
+    account_name: String,
+    password: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BanRequest {
+    reason: String,
+    /// Permanent when unset.
+    duration_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PasswordReset {
+    password: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TelemetryQuery {
+    account: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuditQuery {
+    account: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HotfixRequest {
+    /// Removes the record from the clients when unset.
+    payload: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Debug)]
+struct SessionView {
+    account_name: String,
+    session_id: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OnlineSessions {
+    bnet: Vec<SessionView>,
+    world: Vec<SessionView>,
+}
+
+impl Context {
+    async fn account(&self, account_name: &str) -> Result<AccountInfo, AdminError> {
+        self.redis
+            .peek::<AccountInfo>(&account_name.to_lowercase())
+            .await?
+            .ok_or_else(|| not_found("Account"))
+    }
+
+    async fn view(&self, account: AccountInfo) -> Result<AccountView, AdminError> {
+        let online_bnet = self
+            .bnet_sessions
+            .active_session(&account.account_name)
+            .await?;
+        let online_world = self
+            .world_sessions
+            .active_session(&account.account_name)
+            .await?;
+        Ok(AccountView {
+            online_bnet: online_bnet.is_some(),
+            online_world: online_world.is_some(),
+            account_name: account.account_name,
+            created_at: account.created_at,
+            ban: account.ban,
+        })
+    }
+
+    /// Kicks the account from both servers, wherever it is logged in.
+    async fn kick_account(&self, account_name: &str, reason: WowRpcResponse) -> anyhow::Result<()> {
+        for sessions in [&self.bnet_sessions, &self.world_sessions] {
+            if let Some(session_id) = sessions.active_session(account_name).await? {
+                sessions.kick(&session_id, reason).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn list_accounts(
+    Extension(context): Extension<Arc<Context>>,
+    Query(query): Query<AccountSearch>,
+) -> AdminResult<Vec<AccountView>> {
+    let pattern = match query.search {
+        Some(search) => format!("*{}*", escape_pattern(&search.to_lowercase())),
+        None => "*".to_owned(),
+    };
+    let mut names = context.redis.keys::<AccountInfo>(&pattern).await?;
+    names.sort();
+    let mut accounts = Vec::with_capacity(names.len());
+    for name in names {
+        if let Some(account) = context.redis.peek::<AccountInfo>(&name).await? {
+            accounts.push(context.view(account).await?);
+        }
+    }
+    Ok(Json(accounts))
+}
+
+async fn create_account(
+    Extension(context): Extension<Arc<Context>>,
+    Json(req): Json<NewAccount>,
+) -> Result<(StatusCode, Json<AccountView>), AdminError> {
+    if req.account_name.is_empty() {
+        return Err(AdminError(
+            StatusCode::BAD_REQUEST,
+            "Account name must not be empty".to_owned(),
+        ));
+    }
+    check_password(&req.password)?;
+    let account = AccountInfo::new(req.account_name.to_lowercase(), &req.password);
+    if !context
+        .redis
+        .set_if_absent(&account.account_name, &account)
+        .await?
+    {
+        return Err(AdminError(
+            StatusCode::CONFLICT,
+            "Account already exists".to_owned(),
+        ));
+    }
+    info!(target: "AdminService", "Created account {}", account.account_name);
+    Ok((StatusCode::CREATED, Json(context.view(account).await?)))
+}
+
+async fn get_account(
+    Extension(context): Extension<Arc<Context>>,
+    Path(account_name): Path<String>,
+) -> AdminResult<AccountView> {
+    let account = context.account(&account_name).await?;
+    Ok(Json(context.view(account).await?))
+}
+
+async fn ban_account(
+    Extension(context): Extension<Arc<Context>>,
+    Path(account_name): Path<String>,
+    Json(req): Json<BanRequest>,
+) -> AdminResult<AccountView> {
+    let mut account = context.account(&account_name).await?;
+    let banned_at = unix_now();
+    account.ban = Some(Ban {
+        reason: req.reason,
+        banned_at,
+        expires_at: req.duration_secs.map(|duration| banned_at + duration),
+    });
+    context.redis.set(&account.account_name, &account).await?;
+    context
+        .kick_account(
+            &account.account_name,
+            WowRpcResponse::BattlenetAccountBanned,
+        )
+        .await?;
+    info!(target: "AdminService", "Banned {}: {:?}", account.account_name, account.ban);
+    Ok(Json(context.view(account).await?))
+}
+
+async fn unban_account(
+    Extension(context): Extension<Arc<Context>>,
+    Path(account_name): Path<String>,
+) -> AdminResult<AccountView> {
+    let mut account = context.account(&account_name).await?;
+    account.ban = None;
+    context.redis.set(&account.account_name, &account).await?;
+    info!(target: "AdminService", "Unbanned {}", account.account_name);
+    Ok(Json(context.view(account).await?))
+}
+
+async fn reset_password(
+    Extension(context): Extension<Arc<Context>>,
+    Path(account_name): Path<String>,
+    Json(req): Json<PasswordReset>,
+) -> AdminResult<AccountView> {
+    check_password(&req.password)?;
+    let mut account = context.account(&account_name).await?;
+    account.set_password(&req.password);
+    context.redis.set(&account.account_name, &account).await?;
+    info!(target: "AdminService", "Reset the password of {}", account.account_name);
+    Ok(Json(context.view(account).await?))
+}
+
+async fn list_sessions(Extension(context): Extension<Arc<Context>>) -> AdminResult<OnlineSessions> {
+    let view = |sessions: Vec<(String, String)>| {
+        sessions
+            .into_iter()
+            .map(|(account_name, session_id)| SessionView {
+                account_name,
+                session_id,
+            })
+            .collect()
+    };
+    Ok(Json(OnlineSessions {
+        bnet: view(context.bnet_sessions.active_sessions().await?),
+        world: view(context.world_sessions.active_sessions().await?),
+    }))
+}
+
+async fn kick_session(
+    Extension(context): Extension<Arc<Context>>,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    for sessions in [&context.bnet_sessions, &context.world_sessions] {
+        let active = sessions.active_sessions().await?;
+        if active.iter().any(|(_, active_id)| *active_id == session_id) {
+            sessions.kick(&session_id, WowRpcResponse::AdminKick).await?;
+            info!(target: "AdminService", "Kicked session {}", session_id);
+            return Ok(StatusCode::NO_CONTENT);
+        }
+    }
+    Err(not_found("Session"))
+}
+
+async fn list_realms(Extension(context): Extension<Arc<Context>>) -> AdminResult<Vec<Realm>> {
+    let mut realms = Vec::new();
+    for id in context.redis.keys::<Realm>("*").await? {
+        if let Some(realm) = context.redis.peek::<Realm>(&id).await? {
+            realms.push(realm);
+        }
+    }
+    realms.sort_by_key(|realm| realm.id);
+    Ok(Json(realms))
+}
+
+async fn get_realm(
+    Extension(context): Extension<Arc<Context>>,
+    Path(id): Path<u32>,
+) -> AdminResult<Realm> {
+    let realm = context
+        .redis
+        .peek::<Realm>(&id.to_string())
+        .await?
+        .ok_or_else(|| not_found("Realm"))?;
+    Ok(Json(realm))
+}
+
+async fn put_realm(
+    Extension(context): Extension<Arc<Context>>,
+    Path(id): Path<u32>,
+    Json(mut realm): Json<Realm>,
+) -> AdminResult<Realm> {
+    realm.id = id;
+    context.redis.set(&id.to_string(), &realm).await?;
+    info!(target: "AdminService", "Updated realm {}: {:?}", id, realm);
+    Ok(Json(realm))
+}
+
+async fn delete_realm(
+    Extension(context): Extension<Arc<Context>>,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, AdminError> {
+    context
+        .redis
+        .peek::<Realm>(&id.to_string())
+        .await?
+        .ok_or_else(|| not_found("Realm"))?;
+    context.redis.delete::<Realm>(&id.to_string()).await?;
+    info!(target: "AdminService", "Deleted realm {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_hotfixes(Extension(context): Extension<Arc<Context>>) -> AdminResult<Vec<Hotfix>> {
+    Ok(Json(context.hotfixes.list().await?))
+}
+
+/// Takes effect for sessions logging in afterwards.
+async fn put_hotfix(
+    Extension(context): Extension<Arc<Context>>,
+    Path((table_hash, record_id)): Path<(u32, i32)>,
+    Json(req): Json<HotfixRequest>,
+) -> AdminResult<Hotfix> {
+    let hotfix = context
+        .hotfixes
+        .put(table_hash, record_id, req.payload)
+        .await?;
+    info!(target: "AdminService", "Pushed hotfix {} for record {} of table {:08X}", hotfix.push_id, record_id, table_hash);
+    Ok(Json(hotfix))
+}
+
+async fn delete_hotfix(
+    Extension(context): Extension<Arc<Context>>,
+    Path((table_hash, record_id)): Path<(u32, i32)>,
+) -> Result<StatusCode, AdminError> {
+    if !context.hotfixes.delete(table_hash, record_id).await? {
+        return Err(not_found("Hotfix"));
+    }
+    info!(target: "AdminService", "Deleted hotfix for record {} of table {:08X}", record_id, table_hash);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_telemetry(
+    Extension(context): Extension<Arc<Context>>,
+    Query(query): Query<TelemetryQuery>,
+) -> AdminResult<Vec<TelemetryRecord>> {
+    let account = query.account.map(|account| account.to_lowercase());
+    let records = context
+        .telemetry
+        .query(
+            account.as_deref(),
+            query.kind.as_deref(),
+            query.limit.unwrap_or(100),
+        )
+        .await?;
+    Ok(Json(records))
+}
+
+async fn list_audit(
+    Extension(context): Extension<Arc<Context>>,
+    Query(query): Query<AuditQuery>,
+) -> AdminResult<Vec<AuditRecord>> {
+    let account = query.account.map(|account| account.to_lowercase());
+    let records = context
+        .audit
+        .query(account.as_deref(), query.limit.unwrap_or(100))
+        .await?;
+    Ok(Json(records))
+}
+
+impl AdminServiceHandler {
+    pub async fn serve(self, mut shutdown: Shutdown) -> anyhow::Result<()> {
+        let state = Arc::new(Context {
+            redis: RedisClient::new(&config::get().redis.url)?,
+            telemetry: TelemetryStore::new()?,
+            hotfixes: HotfixStore::new()?,
+            audit: AuditLog::new()?,
+            token: self.token,
+            bnet_sessions: self.bnet_sessions,
+            world_sessions: self.world_sessions,
+        });
+        let router = Router::new()
+            .route("/admin/accounts", get(list_accounts).post(create_account))
+            .route("/admin/accounts/:account_name", get(get_account))
+            .route(
+                "/admin/accounts/:account_name/ban",
+                post(ban_account).delete(unban_account),
+            )
+            .route(
+                "/admin/accounts/:account_name/password",
+                post(reset_password),
+            )
+            .route("/admin/sessions", get(list_sessions))
+            .route("/admin/sessions/:session_id", delete(kick_session))
+            .route("/admin/telemetry", get(list_telemetry))
+            .route("/admin/audit", get(list_audit))
+            .route("/admin/realms", get(list_realms))
+            .route(
+                "/admin/realms/:id",
+                get(get_realm).put(put_realm).delete(delete_realm),
+            )
+            .route("/admin/hotfixes", get(list_hotfixes))
+            .route(
+                "/admin/hotfixes/:table_hash/:record_id",
+                put(put_hotfix).delete(delete_hotfix),
+            )
+            .layer(extractor_middleware::<RequireToken>())
+            .layer(Extension(state));
+        let handle = Handle::new();
+        let shutdown_handle = handle.clone();
+        let timeout = Duration::from_secs(config::get().bnet.shutdown_timeout_secs);
+        tokio::spawn(async move {
+            shutdown.recv().await;
+            shutdown_handle.graceful_shutdown(Some(timeout));
+        });
+        info!(target: "AdminService", "Listening on address: {:?}", self.bind_address);
+        axum_server::bind_rustls(
+            self.bind_address,
+            RustlsConfig::from_config(Arc::new(self.tls_context)),
+        )
+        .handle(handle)
+        .serve(router.into_make_service())
+        .await?;
+        Ok(())
+    }
+}
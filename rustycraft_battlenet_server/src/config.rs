@@ -1 +1 @@
-
+pub use rustycraft_common::config::{get, init, BnetConfig, Config};
@@ -1,7 +1,9 @@
+pub mod admin;
 pub mod config;
 mod realmlist;
 pub mod services;
 pub mod socket_manager;
+pub mod tls;
 mod utils;
 pub mod web_handler;
 mod web_models;
@@ -13,20 +15,40 @@ extern crate log;
 
 use crate::socket_manager::{SessionHandler, SocketEvents};
 use rustls::{Certificate, PrivateKey};
-use rustls_pemfile::{certs, rsa_private_keys};
+use rustls_pemfile::{certs, read_all, Item};
+use rustycraft_common::sessions::SessionRegistry;
+use rustycraft_common::shutdown::Shutdown;
+use rustycraft_database::redis::RedisClient;
 use rustycraft_protocol::bgs::protocol::account::v1::AccountService;
 use rustycraft_protocol::bgs::protocol::authentication::v1::AuthenticationService;
 use rustycraft_protocol::bgs::protocol::connection::v1::ConnectionService;
+use rustycraft_protocol::bgs::protocol::connection::v1::DisconnectNotification;
 use rustycraft_protocol::bgs::protocol::game_utilities::v1::GameUtilitiesService;
 use rustycraft_protocol::bgs::protocol::{Header, NoData};
-use rustycraft_protocol::rpc_responses::WowRpcResponse;
+use rustycraft_protocol::builds::ClientBuild;
 use rustycraft_protocol::messages::{LoggingAttributes, OutgoingMessage, RawMessage};
+use rustycraft_protocol::router::Router;
+use rustycraft_protocol::rpc_responses::WowRpcResponse;
 use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{Receiver, Sender};
-use rustycraft_database::redis::RedisClient;
+
+lazy_static! {
+    static ref ROUTER: Router<Server> = {
+        let mut router = Router::new();
+        router
+            .add(<Server as ConnectionService>::route())
+            .add(<Server as AuthenticationService>::route())
+            .add(<Server as AccountService>::route())
+            .add(<Server as GameUtilitiesService>::route());
+        router
+    };
+}
 
 pub enum State {
     Created,
@@ -35,20 +57,33 @@ pub enum State {
     Stopped,
 }
 
-pub fn load_certs(path: &str) -> anyhow::Result<Vec<Certificate>> {
+pub fn load_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
     Ok(certs(&mut BufReader::new(File::open(path)?))
         .map(|mut c| c.drain(..).map(Certificate).collect())?)
 }
 
-pub fn load_keys(path: &str) -> anyhow::Result<Vec<PrivateKey>> {
-    Ok(rsa_private_keys(&mut BufReader::new(File::open(path)?))
-        .map(|mut c| c.drain(..).map(PrivateKey).collect())?)
+/// Reads every PKCS#1 RSA, PKCS#8 and SEC1 EC private key from a PEM file.
+pub fn load_keys(path: &Path) -> anyhow::Result<Vec<PrivateKey>> {
+    Ok(read_all(&mut BufReader::new(File::open(path)?))?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .collect())
 }
 
 pub struct Server {
     token: u8,
     addr: SocketAddr,
+    router: Arc<Router<Server>>,
+    build: &'static ClientBuild,
     redis: RedisClient,
+    sessions: Arc<SessionRegistry>,
+    account_name: Option<String>,
+    session_id: Option<String>,
+    kicks_tx: Sender<WowRpcResponse>,
+    kicks_rx: Receiver<WowRpcResponse>,
     ticket: Option<String>,
     server_secret: Vec<u8>,
     client_secret: Vec<u8>,
@@ -57,8 +92,13 @@ pub struct Server {
 }
 
 impl Server {
-    async fn handle_error(&mut self, error: WowRpcResponse) -> Result<(), SendError<SocketEvents>> {
+    async fn handle_error(
+        &mut self,
+        token: u32,
+        error: WowRpcResponse,
+    ) -> Result<(), SendError<SocketEvents>> {
         let headers = Header {
+            token,
             status: Some(error as u32),
             ..Default::default()
         };
@@ -69,6 +109,74 @@ impl Server {
         self.tx.send(SocketEvents::Send(msg.encode(true))).await?;
         Ok(())
     }
+
+    /// Makes this connection the active bnet session of the account, kicking any previous one.
+    async fn start_session(&mut self, account_name: String) -> Result<(), WowRpcResponse> {
+        let session_id = self
+            .sessions
+            .register(&account_name, self.kicks_tx.clone())
+            .await
+            .map_err(|_| WowRpcResponse::ServiceFailureSession)?;
+        self.account_name = Some(account_name);
+        self.session_id = Some(session_id);
+        Ok(())
+    }
+
+    async fn end_session(&mut self) {
+        if let (Some(account_name), Some(session_id)) = (&self.account_name, &self.session_id) {
+            if let Err(e) = self.sessions.unregister(account_name, session_id).await {
+                error!(target: "Server", "[{:?}] Failed to release session: {}", self.addr, e);
+            }
+        }
+        self.session_id = None;
+    }
+
+    async fn disconnect(&mut self, reason: WowRpcResponse) {
+        let notification = DisconnectNotification {
+            error_code: reason as u32,
+            reason: None,
+        };
+        if let Err(e) = ConnectionService::force_disconnect(self, notification).await {
+            error!(target: "Server", "[{:?}] Failed to send ForceDisconnect: {:?}", self.addr, e);
+        }
+    }
+
+    async fn serve(&mut self, shutdown: &mut Shutdown) -> Result<(), SendError<SocketEvents>> {
+        loop {
+            let msg = tokio::select! {
+                msg = self.rx.recv() => msg,
+                Some(reason) = self.kicks_rx.recv() => {
+                    info!(target: "Server", "[{:?}] Session kicked: {:?}", self.addr, reason);
+                    self.disconnect(reason).await;
+                    break;
+                }
+                _ = shutdown.recv() => {
+                    self.disconnect(WowRpcResponse::ServerShuttingDown).await;
+                    break;
+                }
+            };
+            let message = match msg {
+                Some(message) => message,
+                None => break,
+            };
+            let token = message.headers.token;
+            let router = Arc::clone(&self.router);
+            match router.dispatch(self, message).await {
+                Ok(data) => self.tx.send(SocketEvents::Send(data)).await?,
+                Err(WowRpcResponse::RpcNotImplemented) => {
+                    self.handle_error(token, WowRpcResponse::RpcNotImplemented)
+                        .await?
+                }
+                Err(e) => {
+                    log!(target: "Server", e.severity().level(), "[{:?}] Request failed, closing session: {}", self.addr, e);
+                    self.handle_error(token, e).await?;
+                    break;
+                }
+            };
+            self.token += 1;
+        }
+        Ok(())
+    }
 }
 
 impl LoggingAttributes for Server {
@@ -79,11 +187,24 @@ impl LoggingAttributes for Server {
 
 #[async_trait::async_trait]
 impl SessionHandler for Server {
-    fn new(addr: SocketAddr, rx: Receiver<RawMessage>, tx: Sender<SocketEvents>) -> Self {
+    fn new(
+        addr: SocketAddr,
+        rx: Receiver<RawMessage>,
+        tx: Sender<SocketEvents>,
+        sessions: Arc<SessionRegistry>,
+    ) -> Self {
+        let (kicks_tx, kicks_rx) = mpsc::channel(1);
         Server {
             token: 0,
             addr,
-            redis: RedisClient::new().unwrap(),
+            router: Arc::new(ROUTER.clone()),
+            build: ClientBuild::latest(),
+            redis: RedisClient::new(&config::get().redis.url).unwrap(),
+            sessions,
+            account_name: None,
+            session_id: None,
+            kicks_tx,
+            kicks_rx,
             ticket: None,
             server_secret: Vec::new(),
             client_secret: Vec::new(),
@@ -92,36 +213,12 @@ impl SessionHandler for Server {
         }
     }
 
-    async fn handle(mut self) -> Result<(), SendError<SocketEvents>> {
-        loop {
-            let msg = self.rx.recv().await;
-            let response = match msg {
-                Some(message) => match message.headers.service_hash {
-                    Some(<Self as ConnectionService>::ORIGINAL_HASH) => {
-                        ConnectionService::dispatch(&mut self, message).await
-                    }
-                    Some(<Self as AuthenticationService>::ORIGINAL_HASH) => {
-                        AuthenticationService::dispatch(&mut self, message).await
-                    }
-                    Some(<Self as AccountService>::ORIGINAL_HASH) => {
-                        AccountService::dispatch(&mut self, message).await
-                    }
-                    Some(<Self as GameUtilitiesService>::ORIGINAL_HASH) => {
-                        GameUtilitiesService::dispatch(&mut self, message).await
-                    }
-                    _ => Err(WowRpcResponse::NotImplemented),
-                },
-                None => break,
-            };
-            match response {
-                Ok(data) => self.tx.send(SocketEvents::Send(data)).await?,
-                Err(e) => {
-                    self.handle_error(e).await?;
-                    break;
-                }
-            };
-            self.token += 1;
-        }
-        Ok(())
+    async fn handle(mut self, mut shutdown: Shutdown) -> Result<(), SendError<SocketEvents>> {
+        let connected = rustycraft_metrics::SESSIONS.with_label_values(&["bnet"]);
+        connected.inc();
+        let result = self.serve(&mut shutdown).await;
+        self.end_session().await;
+        connected.dec();
+        result
     }
 }
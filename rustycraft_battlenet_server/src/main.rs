@@ -1,24 +1,84 @@
+use rustycraft_battlenet_server::admin::AdminServiceHandler;
+use rustycraft_battlenet_server::config;
 use rustycraft_battlenet_server::socket_manager::SocketManager;
+use rustycraft_battlenet_server::tls::CertStore;
 use rustycraft_battlenet_server::web_handler::WebServiceHandler;
-use rustycraft_battlenet_server::{load_certs, load_keys, Server};
+use rustycraft_battlenet_server::Server;
+use rustycraft_common::sessions::{SessionKind, SessionRegistry};
+use rustycraft_common::shutdown::{self, ShutdownController};
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let _ = rustycraft_logging::init_logging();
-    let certs = load_certs("./authserver.cert.pem")?;
-    let mut keys = load_keys("./authserver.key.pem")?;
-    let tls_context = rustls::ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(certs, keys.remove(0))
-        .unwrap();
+    let config = config::init()?;
+    let certs = CertStore::new(
+        config.bnet.cert_path.as_deref(),
+        config.bnet.key_path.as_deref(),
+        dev_hostnames(&config.bnet.login_url),
+    )?;
+    let tls_context = certs.server_config();
 
-    let session_manager = SocketManager::builder().build(tls_context.clone())?;
+    let sessions = SessionRegistry::new(SessionKind::Bnet).await?;
+    let mut session_manager_builder = SocketManager::builder();
+    session_manager_builder
+        .set_bind_address(config.bnet.bind_address)
+        .set_session_registry(sessions.clone());
+    let session_manager = session_manager_builder.build(tls_context.clone())?;
     let a = WebServiceHandler {
-        bind_address: "0.0.0.0:9990",
+        bind_address: config.bnet.web_bind_address,
         tls_context: tls_context.clone(),
     };
-    tokio::spawn(a.serve());
-    session_manager.run_forever::<Server>().await?;
+    let shutdown = ShutdownController::new();
+    if let Some(metrics_bind_address) = config.bnet.metrics_bind_address {
+        let mut metrics_shutdown = shutdown.subscribe();
+        tokio::spawn(rustycraft_metrics::serve(metrics_bind_address, async move {
+            metrics_shutdown.recv().await
+        })?);
+    }
+    tokio::spawn(certs.watch(
+        Duration::from_secs(config.bnet.tls_reload_interval_secs),
+        shutdown.subscribe(),
+    ));
+    tokio::spawn(a.serve(shutdown.subscribe()));
+    match &config.admin.token {
+        Some(token) => {
+            let admin = AdminServiceHandler {
+                bind_address: config.admin.bind_address,
+                tls_context: tls_context.clone(),
+                token: token.clone(),
+                bnet_sessions: sessions,
+                world_sessions: SessionRegistry::new(SessionKind::World).await?,
+            };
+            tokio::spawn(admin.serve(shutdown.subscribe()));
+        }
+        None => log::info!("admin.token is not set, the admin API is disabled"),
+    }
+    let mut listener = tokio::spawn(session_manager.run_forever::<Server>(shutdown.subscribe()));
+    tokio::select! {
+        result = shutdown::wait_for_signal() => result?,
+        result = &mut listener => result??,
+    };
+    log::info!("Shutting down, draining connections");
+    if !shutdown
+        .shutdown(Duration::from_secs(config.bnet.shutdown_timeout_secs))
+        .await
+    {
+        log::warn!("Some connections did not close in time");
+    }
     Ok(())
 }
+
+/// Names the self-signed certificate is issued for: localhost and the host of the login form.
+fn dev_hostnames(login_url: &str) -> Vec<String> {
+    let mut hostnames = vec!["localhost".to_owned(), "127.0.0.1".to_owned()];
+    let host = login_url
+        .split("://")
+        .nth(1)
+        .and_then(|rest| rest.split(&['/', ':'][..]).next())
+        .unwrap_or_default();
+    if !host.is_empty() && !hostnames.iter().any(|h| h == host) {
+        hostnames.push(host.to_owned());
+    }
+    hostnames
+}
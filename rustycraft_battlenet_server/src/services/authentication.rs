@@ -1,5 +1,6 @@
-use crate::{Server, SocketEvents};
+use crate::{config, Server, SocketEvents};
 use log::debug;
+use rustycraft_common::LoginTicket;
 use rustycraft_protocol::bgs::protocol::authentication::v1::{
     AuthenticationListener, AuthenticationService, LogonRequest, LogonResult,
     VerifyWebCredentialsRequest,
@@ -18,7 +19,7 @@ impl AuthenticationService for Server {
         let request = ChallengeExternalRequest {
             request_token: None,
             payload_type: Some("web_auth_url".to_owned()),
-            payload: Some(b"https://127.0.0.1:9990/bnetserver/login/".to_vec()),
+            payload: Some(config::get().bnet.login_url.as_bytes().to_vec()),
         };
         self.on_external_challenge(request).await?;
         Ok(NoData::default())
@@ -28,10 +29,18 @@ impl AuthenticationService for Server {
         &mut self,
         request: VerifyWebCredentialsRequest,
     ) -> Result<NoData, WowRpcResponse> {
-        debug!(
-            "{:?}",
-            String::from_utf8(request.web_credentials.unwrap()).unwrap()
-        );
+        let login_ticket = request
+            .web_credentials
+            .map(String::from_utf8)
+            .ok_or(WowRpcResponse::LogonInvalidAuthToken)?
+            .map_err(|_| WowRpcResponse::LogonInvalidAuthToken)?;
+        debug!("{:?}", login_ticket);
+        let ticket: LoginTicket = self
+            .redis
+            .get(&login_ticket)
+            .await
+            .map_err(|_| WowRpcResponse::LogonInvalidAuthToken)?;
+        self.start_session(ticket.account_name).await?;
         let logon_result = LogonResult {
             error_code: WowRpcResponse::Ok as u32,
             account_id: Some(EntityId {
@@ -1,6 +1,6 @@
-use crate::Server;
+use crate::{Header, OutgoingMessage, Server, SocketEvents};
 use rustycraft_protocol::bgs::protocol::connection::v1::{
-    ConnectRequest, ConnectResponse, ConnectionService, DisconnectRequest,
+    ConnectRequest, ConnectResponse, ConnectionService, DisconnectNotification, DisconnectRequest,
 };
 use rustycraft_protocol::bgs::protocol::{NoData, NoResponse};
 use rustycraft_protocol::rpc_responses::WowRpcResponse;
@@ -11,11 +11,35 @@ impl ConnectionService for Server {
         &mut self,
         request: ConnectRequest,
     ) -> Result<ConnectResponse, WowRpcResponse> {
+        if let Some(bind_request) = &request.bind_request {
+            for service in &bind_request.imported_service {
+                if !self.router.bind(service.id, service.hash) {
+                    warn!(target: "ConnectionService", "[{:?}] Cannot bind unknown service {:#x}", self.addr, service.hash);
+                }
+            }
+        }
         let mut response = ConnectResponse::get_default();
         response.use_bindless_rpc = request.use_bindless_rpc;
         response.client_id = request.client_id;
         Ok(response)
     }
+    async fn force_disconnect(
+        &mut self,
+        request: DisconnectNotification,
+    ) -> Result<NoResponse, WowRpcResponse> {
+        let headers = Header {
+            method_id: Some(Self::FORCE_DISCONNECT as u32),
+            token: self.token as u32,
+            service_hash: Some(<Self as ConnectionService>::ORIGINAL_HASH),
+            ..Default::default()
+        };
+        let mut msg = OutgoingMessage {
+            headers,
+            message: Some(request),
+        };
+        self.tx.send(SocketEvents::Send(msg.encode(false))).await?;
+        Ok(NoResponse::default())
+    }
     async fn keep_alive(&mut self, _: NoData) -> Result<NoResponse, WowRpcResponse> {
         Ok(NoResponse::default())
     }
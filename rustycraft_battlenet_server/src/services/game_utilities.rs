@@ -8,12 +8,13 @@ use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use prost::Message;
 use rand::Rng;
-use rustycraft_common::Account;
+use rustycraft_common::{Account, Realm};
 use rustycraft_protocol::bgs::protocol::game_utilities::v1::{
     ClientRequest, ClientResponse, GameUtilitiesService, GetAllValuesForAttributeRequest,
     GetAllValuesForAttributeResponse,
 };
 use rustycraft_protocol::bgs::protocol::{Attribute, Variant};
+use rustycraft_protocol::builds::ClientBuild;
 use rustycraft_protocol::rpc_responses::WowRpcResponse;
 use rustycraft_protocol::rpc_responses::WowRpcResponse::NotImplemented;
 use serde::Serialize;
@@ -41,6 +42,13 @@ fn extract_json_from_blob(blob: Vec<u8>) -> String {
 }
 
 impl Server {
+    /// Realms configured through the admin API, or the fallback realm when there are none.
+    async fn realms(&self) -> Result<Vec<Realm>, WowRpcResponse> {
+        Realm::list(&self.redis)
+            .await
+            .map_err(|_| WowRpcResponse::Internal)
+    }
+
     async fn handle_realm_list_ticket_request(
         &mut self,
         request: ClientRequest,
@@ -55,6 +63,12 @@ impl Server {
             extract_json_from_blob(b.blob_value.as_ref().unwrap().to_vec());
         let abiba: RealmListTicketClientInformation =
             serde_json::from_str(&param_client_info_blob_ready).unwrap();
+        let version = &abiba.info.version;
+        self.build = ClientBuild::find(version.version_build).ok_or_else(|| {
+            warn!(target: "GameUtilitiesService", "[{:?}] Unsupported client build {}.{}.{}.{}", self.addr,
+                version.version_major, version.version_minor, version.version_revision, version.version_build);
+            WowRpcResponse::BadVersion
+        })?;
         self.client_secret = abiba.info.secret;
         Ok(ClientResponse {
             attribute: vec![Attribute {
@@ -85,34 +99,41 @@ impl Server {
         &mut self,
         request: ClientRequest,
     ) -> Result<ClientResponse, WowRpcResponse> {
+        let realms = self.realms().await?;
         let rl = RealmListUpdates {
-            updates: vec![RealmState {
-                update: Some(RealmEntry {
-                    wow_realm_address: 1024,
-                    cfg_timezones_id: 1,
-                    population_state: 1,
-                    cfg_categories_id: 1,
-                    version: ClientVersion {
-                        version_major: 9,
-                        version_minor: 2,
-                        version_revision: 0,
-                        version_build: 43206,
-                    },
-                    cfg_realms_id: 1,
-                    flags: 0,
-                    name: "ABOBA".to_string(),
-                    cfg_configs_id: 1,
-                    cfg_languages_id: 1,
-                }),
-                deleting: false,
-            }],
+            updates: realms
+                .iter()
+                .map(|realm| RealmState {
+                    update: Some(RealmEntry {
+                        wow_realm_address: realm.id,
+                        cfg_timezones_id: 1,
+                        population_state: 1,
+                        cfg_categories_id: 1,
+                        version: ClientVersion {
+                            version_major: self.build.major,
+                            version_minor: self.build.minor,
+                            version_revision: self.build.revision,
+                            version_build: self.build.build,
+                        },
+                        cfg_realms_id: 1,
+                        flags: realm.flags,
+                        name: realm.name.clone(),
+                        cfg_configs_id: 1,
+                        cfg_languages_id: realm.locale,
+                    }),
+                    deleting: false,
+                })
+                .collect(),
         };
 
         let cc = RealmCharacterCountList {
-            counts: vec![RealmCharacterCountEntry {
-                wow_realm_address: 1024,
-                count: 0,
-            }],
+            counts: realms
+                .iter()
+                .map(|realm| RealmCharacterCountEntry {
+                    wow_realm_address: realm.id,
+                    count: 0,
+                })
+                .collect(),
         };
 
         Ok(ClientResponse {
@@ -153,20 +174,31 @@ impl Server {
         &mut self,
         request: ClientRequest,
     ) -> Result<ClientResponse, WowRpcResponse> {
+        let realm_address = request
+            .get_param("Param_RealmAddress")
+            .and_then(|param| param.uint_value);
+        let realms = self.realms().await?;
+        let realm = realms
+            .iter()
+            .find(|realm| Some(realm.id as u64) == realm_address)
+            .unwrap_or(&realms[0]);
         let resp = RealmListServerIpAddresses {
             families: vec![RealmIpAddressFamily {
                 family: 1,
                 addresses: vec![IpAddress {
-                    ip: "127.0.0.1".to_string(),
-                    port: 9900,
+                    ip: realm.address.clone(),
+                    port: realm.port as u32,
                 }],
             }],
         };
+        let account_name = self.account_name.clone().ok_or(WowRpcResponse::Denied)?;
         let ticket = uuid::Uuid::new_v4().to_string();
         let server_secret = rand::thread_rng().gen::<[u8; 32]>().to_vec();
         let acc_data = Account {
+            account_name,
             server_secret: server_secret.clone(),
             client_secret: self.client_secret.clone(),
+            build: self.build.build,
         };
         self.redis.set(&ticket.clone(), &acc_data).await.unwrap();
         Ok(ClientResponse {
@@ -224,28 +256,17 @@ impl GameUtilitiesService for Server {
         &mut self,
         request: ClientRequest,
     ) -> Result<ClientResponse, WowRpcResponse> {
-        if request
-            .get_param("Command_RealmListTicketRequest_v1_b9")
-            .is_some()
-        {
-            self.handle_realm_list_ticket_request(request).await
-        } else if request
-            .get_param("Command_LastCharPlayedRequest_v1_b9")
-            .is_some()
-        {
-            self.handle_last_char_played_request(request).await
-        } else if request
-            .get_param("Command_RealmListRequest_v1_b9")
-            .is_some()
-        {
-            self.handle_realm_list_request(request).await
-        } else if request
-            .get_param("Command_RealmJoinRequest_v1_b9")
-            .is_some()
-        {
-            self.handle_realm_join_request(request).await
-        } else {
-            Err(NotImplemented)
+        let command = request
+            .attribute
+            .iter()
+            .find_map(|attr| ClientBuild::parse_command(&attr.name))
+            .map(|(name, _)| name.to_owned());
+        match command.as_deref() {
+            Some("RealmListTicketRequest") => self.handle_realm_list_ticket_request(request).await,
+            Some("LastCharPlayedRequest") => self.handle_last_char_played_request(request).await,
+            Some("RealmListRequest") => self.handle_realm_list_request(request).await,
+            Some("RealmJoinRequest") => self.handle_realm_join_request(request).await,
+            _ => Err(NotImplemented),
         }
     }
 
@@ -1,6 +1,8 @@
 use bytes::{Bytes, BytesMut};
 use prost::Message;
 use rustls::ServerConfig;
+use rustycraft_common::sessions::SessionRegistry;
+use rustycraft_common::shutdown::Shutdown;
 use rustycraft_protocol::bgs::protocol::Header;
 use rustycraft_protocol::rpc_responses::WowRpcResponse;
 use rustycraft_protocol::messages::RawMessage;
@@ -19,26 +21,47 @@ pub enum SocketEvents {
     Send(Bytes),
 }
 
-pub struct SocketManagerBuilder {}
+pub struct SocketManagerBuilder {
+    bind_address: Option<SocketAddr>,
+    sessions: Option<Arc<SessionRegistry>>,
+}
 
 impl SocketManagerBuilder {
+    pub fn set_bind_address(&mut self, bind_address: SocketAddr) -> &mut Self {
+        self.bind_address = Some(bind_address);
+        self
+    }
+
+    pub fn set_session_registry(&mut self, sessions: Arc<SessionRegistry>) -> &mut Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
     pub fn build(self, tls_context: ServerConfig) -> anyhow::Result<SocketManager> {
-        let bind_address = "0.0.0.0:1119";
         Ok(SocketManager {
-            bind_address,
+            bind_address: self
+                .bind_address
+                .ok_or_else(|| anyhow::anyhow!("Bind address did not set"))?,
             tls_context,
+            sessions: self
+                .sessions
+                .ok_or_else(|| anyhow::anyhow!("Session registry did not set"))?,
         })
     }
 }
 
 pub struct SocketManager {
-    bind_address: &'static str,
+    bind_address: SocketAddr,
     tls_context: ServerConfig,
+    sessions: Arc<SessionRegistry>,
 }
 
 impl SocketManager {
     pub fn builder() -> SocketManagerBuilder {
-        SocketManagerBuilder {}
+        SocketManagerBuilder {
+            bind_address: None,
+            sessions: None,
+        }
     }
 
     pub async fn handle_connection(
@@ -46,6 +69,7 @@ impl SocketManager {
         socket: TlsStream<TcpStream>,
         response_ch: mpsc::Sender<RawMessage>,
         mut request_ch: mpsc::Receiver<SocketEvents>,
+        _shutdown: Shutdown,
     ) -> Result<(), WowRpcResponse> {
         debug!(target: "SocketManager", "New connection from peer: {}", addr);
         let (mut sock_reader, mut sock_writer) = split(socket);
@@ -92,28 +116,49 @@ impl SocketManager {
         Ok(())
     }
 
-    pub async fn run_forever<T>(self) -> anyhow::Result<()>
+    /// Accepts connections until `shutdown` fires. Every spawned connection keeps
+    /// a clone of `shutdown`, so the caller can wait for them to drain.
+    pub async fn run_forever<T>(self, mut shutdown: Shutdown) -> anyhow::Result<()>
     where
         T: SessionHandler,
     {
         let acceptor = TlsAcceptor::from(Arc::new(self.tls_context.clone()));
-        let listener = TcpListener::bind(self.bind_address).await.unwrap();
+        let listener = TcpListener::bind(self.bind_address).await?;
 
         info!(target: "SocketManager", "Auth server listening on: {}", self.bind_address);
 
         loop {
-            if let Ok((stream, peer_addr)) = listener.accept().await {
-                let tls_stream = acceptor.accept(stream).await.ok();
-                if let Some(tls_stream) = tls_stream {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.recv() => break,
+            };
+            if let Ok((stream, peer_addr)) = accepted {
+                let tls_stream = acceptor.accept(stream).await;
+                if let Err(e) = &tls_stream {
+                    debug!(target: "SocketManager", "TLS handshake with {} failed: {}", peer_addr, e);
+                    rustycraft_metrics::HANDSHAKE_FAILURES
+                        .with_label_values(&["bnet"])
+                        .inc();
+                }
+                if let Ok(tls_stream) = tls_stream {
                     let (req_tx, req_rx) = mpsc::channel(1024);
                     let (resp_tx, resp_rx) = mpsc::channel(1024);
                     tokio::spawn(Self::handle_connection(
-                        peer_addr, tls_stream, resp_tx, req_rx,
+                        peer_addr,
+                        tls_stream,
+                        resp_tx,
+                        req_rx,
+                        shutdown.clone(),
                     ));
-                    tokio::spawn(T::new(peer_addr, resp_rx, req_tx).handle());
+                    tokio::spawn(
+                        T::new(peer_addr, resp_rx, req_tx, self.sessions.clone())
+                            .handle(shutdown.clone()),
+                    );
                 }
             }
         }
+        info!(target: "SocketManager", "Auth server stopped accepting connections");
+        Ok(())
     }
 }
 
@@ -123,6 +168,7 @@ pub trait SessionHandler: 'static {
         addr: SocketAddr,
         rx: mpsc::Receiver<RawMessage>,
         tx: mpsc::Sender<SocketEvents>,
+        sessions: Arc<SessionRegistry>,
     ) -> Self;
-    async fn handle(mut self) -> Result<(), SendError<SocketEvents>>;
+    async fn handle(mut self, shutdown: Shutdown) -> Result<(), SendError<SocketEvents>>;
 }
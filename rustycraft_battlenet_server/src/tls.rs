@@ -0,0 +1,162 @@
+use crate::{load_certs, load_keys};
+use anyhow::{anyhow, bail, Context};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustycraft_common::shutdown::Shutdown;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Certificate shared by every TLS listener. Listeners built from [CertStore::server_config]
+/// resolve the certificate per handshake, so [CertStore::reload] applies to new connections
+/// without a restart.
+pub struct CertStore {
+    files: Option<(PathBuf, PathBuf)>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertStore {
+    /// Loads `cert_path`/`key_path`, or generates a self-signed certificate for
+    /// `hostnames` when no files are configured.
+    pub fn new(
+        cert_path: Option<&Path>,
+        key_path: Option<&Path>,
+        hostnames: Vec<String>,
+    ) -> anyhow::Result<Arc<CertStore>> {
+        let (files, current) = match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => (
+                Some((cert_path.to_owned(), key_path.to_owned())),
+                load_certified_key(cert_path, key_path)?,
+            ),
+            _ => {
+                warn!(target: "CertStore", "No certificate configured, using a self-signed one for {:?}", hostnames);
+                (None, self_signed(hostnames)?)
+            }
+        };
+        Ok(Arc::new(CertStore {
+            files,
+            current: RwLock::new(current),
+        }))
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone())
+    }
+
+    /// Re-reads the certificate files. The previous certificate stays in use on error.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        if let Some((cert_path, key_path)) = &self.files {
+            let key = load_certified_key(cert_path, key_path)?;
+            *self.current.write().unwrap() = key;
+            info!(target: "CertStore", "Reloaded certificate from {}", cert_path.display());
+        }
+        Ok(())
+    }
+
+    /// Reloads the certificate on SIGHUP and whenever the files change, until `shutdown` fires.
+    pub async fn watch(
+        self: Arc<Self>,
+        interval: Duration,
+        mut shutdown: Shutdown,
+    ) -> anyhow::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let poll = !interval.is_zero() && self.files.is_some();
+        let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+        let mut last_modified = self.modified();
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = hangup.recv() => info!(target: "CertStore", "Received SIGHUP"),
+                _ = ticker.tick(), if poll => {
+                    let modified = self.modified();
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                }
+            }
+            if let Err(e) = self.reload() {
+                error!(target: "CertStore", "Failed to reload certificate: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.files
+            .iter()
+            .flat_map(|(cert_path, key_path)| [cert_path, key_path])
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn certified_key(certs: Vec<Certificate>, key: &PrivateKey) -> anyhow::Result<Arc<CertifiedKey>> {
+    if certs.is_empty() {
+        bail!("No certificate found");
+    }
+    let key = any_supported_type(key).map_err(|_| anyhow!("Unsupported private key type"))?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> anyhow::Result<Arc<CertifiedKey>> {
+    let certs =
+        load_certs(cert_path).with_context(|| format!("Failed to read {}", cert_path.display()))?;
+    let key = load_keys(key_path)
+        .with_context(|| format!("Failed to read {}", key_path.display()))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No private key found in {}", key_path.display()))?;
+    certified_key(certs, &key)
+}
+
+fn self_signed(hostnames: Vec<String>) -> anyhow::Result<Arc<CertifiedKey>> {
+    let cert = rcgen::generate_simple_self_signed(hostnames)?;
+    certified_key(
+        vec![Certificate(cert.serialize_der()?)],
+        &PrivateKey(cert.serialize_private_key_der()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_pkcs8_and_ec_keys() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        // rcgen writes ECDSA P-256 keys as PKCS#8.
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let store = CertStore::new(Some(&cert_path), Some(&key_path), vec![]).unwrap();
+        let before = store.current.read().unwrap().clone();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        store.reload().unwrap();
+        let after = store.current.read().unwrap().clone();
+        assert_ne!(after.cert, before.cert);
+
+        std::fs::write(&key_path, "garbage").unwrap();
+        assert!(store.reload().is_err());
+        assert_eq!(store.current.read().unwrap().cert, after.cert);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -2,22 +2,30 @@ use crate::utils::Http1Header;
 use crate::web_models::battlenet::json::login::{
     AuthenticationState, FormInput, FormInputs, FormType, LoginForm, LoginResult,
 };
-use axum::extract::Extension;
-use axum::http::header::HeaderName;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, ContentLengthLimit, Extension, Path};
+use axum::http::StatusCode;
+use axum::http::header::{HeaderName, AUTHORIZATION, COOKIE, SET_COOKIE};
 use axum::http::{HeaderMap, HeaderValue};
 use axum::response::{Headers, IntoResponse};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use axum_server::tls_rustls::RustlsConfig;
-use axum_server::HttpConfig;
-use log::{debug, info};
+use axum_server::{Handle, HttpConfig};
+use log::{debug, error, info};
 use rustls::ServerConfig;
+use rustycraft_common::shutdown::Shutdown;
+use rustycraft_common::accounts::AccountInfo;
+use rustycraft_common::telemetry::{TelemetryEvent, TelemetryRecord, TelemetryStore};
+use rustycraft_common::{config, LoginTicket};
+use rustycraft_database::redis::{RedisClient, Storable};
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct WebServiceHandler {
-    pub bind_address: &'static str,
+    pub bind_address: SocketAddr,
     pub tls_context: ServerConfig,
 }
 
@@ -28,11 +36,36 @@ lazy_static! {
     );
 }
 
-pub struct Context {}
+/// Largest telemetry report accepted, real ones are a few hundred bytes.
+const MAX_TELEMETRY_BYTES: u64 = 16 * 1024;
+/// Cookie set on login, naming the [WebSession] of the client.
+const SESSION_COOKIE: &str = "web_session";
+const WEB_SESSION_TTL_SECS: usize = 24 * 60 * 60;
+
+/// Outlives the login ticket, which the bnet server takes on logon, so the client can still
+/// authenticate its telemetry reports.
+#[derive(Serialize, Deserialize, Debug)]
+struct WebSession {
+    account_name: String,
+}
+
+impl Storable for WebSession {
+    fn key_prefix() -> &'static str {
+        "web_session"
+    }
+}
+
+pub struct Context {
+    redis: RedisClient,
+    telemetry: TelemetryStore,
+}
 
 impl Context {
-    pub fn new() -> Self {
-        Context {}
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Context {
+            redis: RedisClient::new(&config::get().redis.url)?,
+            telemetry: TelemetryStore::new()?,
+        })
     }
 }
 
@@ -64,42 +97,199 @@ async fn get_logon(headers: HeaderMap) -> impl IntoResponse {
     (Headers(vec![CONTENT_TYPE_HEADERS.clone()]), Json(resp))
 }
 
-pub async fn post_logon(Json(req): Json<LoginForm>, headers: HeaderMap) -> impl IntoResponse {
+fn login_error(error_code: &str, error_message: &str) -> LoginResult {
+    LoginResult {
+        authentication_state: AuthenticationState::Login,
+        error_code: Some(error_code.to_owned()),
+        error_message: Some(error_message.to_owned()),
+        url: None,
+        login_ticket: None,
+    }
+}
+
+/// Checks the credentials and issues a login ticket. Accounts are created through the admin API,
+/// unknown ones fail like a wrong password.
+async fn login(context: &Context, account_name: String, password: &str) -> anyhow::Result<LoginResult> {
+    match context.redis.peek::<AccountInfo>(&account_name).await? {
+        Some(account) if account.check_password(password) => {
+            if account.active_ban().is_some() {
+                return Ok(login_error("ACCOUNT_BANNED", "This account has been banned."));
+            }
+        }
+        _ => {
+            return Ok(login_error("INVALID_ACCOUNT_OR_CREDENTIALS", "The username or password is incorrect."));
+        }
+    }
+    // The session cookie shares the id of the ticket, see post_logon.
+    let login_ticket = uuid::Uuid::new_v4();
+    let session = WebSession {
+        account_name: account_name.clone(),
+    };
+    context
+        .redis
+        .set_expiring(&login_ticket.to_string(), &session, WEB_SESSION_TTL_SECS)
+        .await?;
+    let ticket = LoginTicket { account_name };
+    context.redis.set(&login_ticket.to_string(), &ticket).await?;
+    Ok(LoginResult {
+        authentication_state: AuthenticationState::Done,
+        error_code: None,
+        error_message: None,
+        url: None,
+        login_ticket: Some(login_ticket),
+    })
+}
+
+pub async fn post_logon(
+    Extension(context): Extension<Arc<Context>>,
+    Json(req): Json<LoginForm>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     debug!("{:?}", req);
     debug!("{:?}", headers);
-    (
-        Headers(vec![CONTENT_TYPE_HEADERS.clone()]),
-        Json(LoginResult {
-            authentication_state: AuthenticationState::Done,
-            error_code: None,
-            error_message: None,
-            url: None,
-            login_ticket: Some(uuid::Uuid::new_v4()),
-        }),
-    )
+    let input = |input_id: &str| {
+        req.inputs
+            .iter()
+            .find(|input| input.input_id == input_id)
+            .map(|input| input.value.clone())
+    };
+    let result = match (input("account_name"), input("password")) {
+        (Some(account_name), Some(password)) => {
+            match login(&context, account_name.to_lowercase(), &password).await {
+                Ok(result) => result,
+                Err(e) => {
+                    error!(target: "WebServiceHandler", "Failed to log in: {}", e);
+                    login_error("UNABLE_TO_DECODE", "There was an internal error while connecting to Battle.net. Please try again later.")
+                }
+            }
+        }
+        _ => login_error("UNABLE_TO_DECODE", "There was an internal error while connecting to Battle.net. Please try again later."),
+    };
+    let mut headers = vec![CONTENT_TYPE_HEADERS.clone()];
+    if let Some(login_ticket) = result.login_ticket {
+        let cookie = format!(
+            "{}={}; Max-Age={}; Path=/bnetserver/; Secure; HttpOnly",
+            SESSION_COOKIE, login_ticket, WEB_SESSION_TTL_SECS
+        );
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            headers.push((SET_COOKIE, cookie));
+        }
+    }
+    (Headers(headers), Json(result))
+}
+
+/// Account of the request, from an unused login ticket sent as `Authorization: Bearer <ticket>`
+/// or from the session cookie set on login.
+async fn authenticate(context: &Context, headers: &HeaderMap) -> anyhow::Result<Option<String>> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(ticket) = bearer {
+        let ticket = context.redis.peek::<LoginTicket>(ticket).await?;
+        return Ok(ticket.map(|ticket| ticket.account_name));
+    }
+    match session_cookie(headers) {
+        Some(session) => Ok(context
+            .redis
+            .peek::<WebSession>(session)
+            .await?
+            .map(|session| session.account_name)),
+        None => Ok(None),
+    }
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+}
+
+/// Accepts a protobuf telemetry report named by the last path segment, e.g. `WorldLoadFailed`,
+/// from a logged in client. The realm is the one carried in the report, if any.
+pub async fn post_telemetry(
+    Extension(context): Extension<Arc<Context>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(message_name): Path<String>,
+    headers: HeaderMap,
+    ContentLengthLimit(body): ContentLengthLimit<Bytes, MAX_TELEMETRY_BYTES>,
+) -> StatusCode {
+    let account_name = match authenticate(&context, &headers).await {
+        Ok(Some(account_name)) => account_name,
+        Ok(None) => {
+            debug!(target: "WebServiceHandler", "Rejected unauthenticated telemetry from {}", peer);
+            return StatusCode::UNAUTHORIZED;
+        }
+        Err(e) => {
+            error!(target: "WebServiceHandler", "Failed to authenticate telemetry: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let event = match TelemetryEvent::decode(&message_name, &body) {
+        Ok(event) => event,
+        Err(e) => {
+            debug!(target: "WebServiceHandler", "Rejected telemetry from {}: {}", peer, e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    let record = TelemetryRecord::new(peer, Some(account_name), None, event);
+    match context.telemetry.record(&record).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            error!(target: "WebServiceHandler", "Failed to store telemetry: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
 }
 
 impl WebServiceHandler {
-    pub async fn serve(self) {
+    pub async fn serve(self, mut shutdown: Shutdown) {
         let config = HttpConfig::new()
             .http1_only(true)
             .http2_only(false)
             .max_buf_size(8192)
             .build();
-        let state = Arc::new(Context::new());
+        let state = Arc::new(Context::new().unwrap());
         let router = Router::new()
             .route("/bnetserver/login/", get(get_logon))
             .route("/bnetserver/login/", post(post_logon))
+            .route("/bnetserver/telemetry/:message_name", post(post_telemetry))
             .layer(Extension(state));
-        let addr = SocketAddr::from_str(self.bind_address).unwrap();
+        let addr = self.bind_address;
         info!(target: "WebServiceHandler", "Listening on address: {:?}", addr);
+        let handle = Handle::new();
+        let shutdown_handle = handle.clone();
+        let timeout = Duration::from_secs(config::get().bnet.shutdown_timeout_secs);
+        tokio::spawn(async move {
+            shutdown.recv().await;
+            shutdown_handle.graceful_shutdown(Some(timeout));
+        });
         axum_server::bind_rustls(
             addr,
             RustlsConfig::from_config(Arc::new(self.tls_context.clone())),
         )
+        .handle(handle)
         .http_config(config)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr, _>())
         .await
         .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_cookie(&headers), None);
+        headers.append(COOKIE, HeaderValue::from_static("lang=en; web_sessionx=1"));
+        assert_eq!(session_cookie(&headers), None);
+        headers.append(COOKIE, HeaderValue::from_static("a=b; web_session=1234; c=d"));
+        assert_eq!(session_cookie(&headers), Some("1234"));
+    }
+}
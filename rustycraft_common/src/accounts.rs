@@ -0,0 +1,117 @@
+use crate::srp6;
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use rustycraft_database::redis::Storable;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Persistent account record, keyed by the lowercased account name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountInfo {
+    pub account_name: String,
+    /// Argon2 hash in PHC string format, salt and cost parameters included. Empty for accounts
+    /// stored before passwords were hashed with argon2, they can't log in until it is reset.
+    #[serde(default)]
+    pub password_phc: String,
+    /// SRP6 salt and verifier for legacy grunt logins. Empty until the password is set again
+    /// for accounts created before these were stored.
+    #[serde(default)]
+    pub srp6_salt: Vec<u8>,
+    #[serde(default)]
+    pub srp6_verifier: Vec<u8>,
+    pub created_at: u64,
+    pub ban: Option<Ban>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    pub reason: String,
+    pub banned_at: u64,
+    /// `None` for a permanent ban.
+    pub expires_at: Option<u64>,
+}
+
+impl Storable for AccountInfo {
+    fn key_prefix() -> &'static str {
+        "account_info"
+    }
+}
+
+impl AccountInfo {
+    pub fn new(account_name: String, password: &str) -> AccountInfo {
+        let mut account = AccountInfo {
+            account_name,
+            password_phc: String::new(),
+            srp6_salt: vec![],
+            srp6_verifier: vec![],
+            created_at: unix_now(),
+            ban: None,
+        };
+        account.set_password(password);
+        account
+    }
+
+    pub fn set_password(&mut self, password: &str) {
+        let salt = SaltString::generate(&mut OsRng);
+        self.password_phc = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("argon2 accepts any password with default params")
+            .to_string();
+        let (salt, verifier) = srp6::make_verifier(&self.account_name, password);
+        self.srp6_salt = salt;
+        self.srp6_verifier = verifier;
+    }
+
+    pub fn check_password(&self, password: &str) -> bool {
+        PasswordHash::new(&self.password_phc).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    /// The ban currently in effect, ignoring expired ones.
+    pub fn active_ban(&self) -> Option<&Ban> {
+        let now = unix_now();
+        self.ban
+            .as_ref()
+            .filter(|ban| ban.expires_at.is_none_or(|expires_at| expires_at > now))
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_and_ban() {
+        let mut account = AccountInfo::new("player@example.org".to_owned(), "secret");
+        assert!(account.check_password("secret"));
+        assert!(!account.check_password("Secret"));
+        let previous = account.password_phc.clone();
+        account.set_password("other");
+        assert!(account.check_password("other"));
+        assert!(!account.check_password("secret"));
+        assert_ne!(account.password_phc, previous);
+        assert!(account.password_phc.starts_with("$argon2id$"));
+        assert_eq!(
+            account.srp6_verifier,
+            srp6::calculate_verifier("PLAYER@EXAMPLE.ORG", "OTHER", &account.srp6_salt)
+        );
+
+        account.ban = Some(Ban {
+            reason: "test".to_owned(),
+            banned_at: 0,
+            expires_at: Some(1),
+        });
+        assert!(account.active_ban().is_none());
+        account.ban.as_mut().unwrap().expires_at = None;
+        assert!(account.active_ban().is_some());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::accounts::unix_now;
+use crate::config;
+use rustycraft_database::redis::{RedisClient, Storable};
+use rustycraft_protocol::rpc_responses::WowRpcResponse;
+use std::net::SocketAddr;
+
+/// Only the most recent entries are kept.
+const MAX_RECORDS: isize = 10_000;
+const RECORDS_KEY: &str = "handshakes";
+
+/// A client turned away during the handshake, along with what it was told.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditRecord {
+    pub recorded_at: u64,
+    pub peer: SocketAddr,
+    /// Unknown until the client presented a valid ticket.
+    pub account_name: Option<String>,
+    pub status: WowRpcResponse,
+    pub reason: String,
+}
+
+impl Storable for AuditRecord {
+    fn key_prefix() -> &'static str {
+        "audit"
+    }
+}
+
+impl AuditRecord {
+    pub fn new(
+        peer: SocketAddr,
+        account_name: Option<String>,
+        status: WowRpcResponse,
+        reason: String,
+    ) -> AuditRecord {
+        AuditRecord {
+            recorded_at: unix_now(),
+            peer,
+            account_name,
+            status,
+            reason,
+        }
+    }
+
+    fn is_of(&self, account_name: Option<&str>) -> bool {
+        account_name.is_none_or(|name| self.account_name.as_deref() == Some(name))
+    }
+}
+
+/// Capped Redis list of rejected handshakes, read back newest first.
+pub struct AuditLog {
+    redis: RedisClient,
+}
+
+impl AuditLog {
+    pub fn new() -> anyhow::Result<AuditLog> {
+        Ok(AuditLog::with_client(RedisClient::new(
+            &config::get().redis.url,
+        )?))
+    }
+
+    fn with_client(redis: RedisClient) -> AuditLog {
+        AuditLog { redis }
+    }
+
+    pub async fn record(&self, record: &AuditRecord) -> anyhow::Result<()> {
+        warn!(target: "Audit", "{}", serde_json::to_string(record)?);
+        self.redis.push(RECORDS_KEY, record, MAX_RECORDS).await
+    }
+
+    /// Up to `limit` of the newest records, optionally of a single account.
+    pub async fn query(
+        &self,
+        account_name: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<AuditRecord>> {
+        let records = self
+            .redis
+            .range::<AuditRecord>(RECORDS_KEY, MAX_RECORDS)
+            .await?;
+        Ok(records
+            .into_iter()
+            .filter(|record| record.is_of(account_name))
+            .take(limit)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(account_name: Option<&str>) -> AuditRecord {
+        AuditRecord::new(
+            ([127, 0, 0, 1], 1119).into(),
+            account_name.map(str::to_owned),
+            WowRpcResponse::Denied,
+            "test".to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_is_of() {
+        assert!(record(Some("a")).is_of(Some("a")));
+        assert!(record(Some("a")).is_of(None));
+        assert!(record(None).is_of(None));
+        assert!(!record(Some("a")).is_of(Some("b")));
+        assert!(!record(None).is_of(Some("a")));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server, set REDIS_URL"]
+    async fn test_record_and_query() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_owned());
+        let audit = AuditLog::with_client(RedisClient::new(&url).unwrap());
+        let account = format!("audit_test_{}", std::process::id());
+        audit.record(&record(None)).await.unwrap();
+        audit.record(&record(Some(&account))).await.unwrap();
+        audit.record(&record(Some(&account))).await.unwrap();
+
+        let records = audit.query(Some(&account), 10).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|r| r.account_name.as_deref() == Some(&*account)));
+        assert_eq!(audit.query(None, 1).await.unwrap().len(), 1);
+    }
+}
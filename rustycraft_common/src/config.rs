@@ -0,0 +1,360 @@
+use anyhow::{anyhow, bail, Context};
+use once_cell::sync::OnceCell;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// File read when `RUSTYCRAFT_CONFIG` is not set. A missing default file is not an error.
+const DEFAULT_CONFIG_PATH: &str = "./rustycraft.toml";
+const CONFIG_PATH_ENV: &str = "RUSTYCRAFT_CONFIG";
+/// Prefix of environment overrides, e.g. `RUSTYCRAFT_BNET__BIND_ADDRESS=0.0.0.0:1119`.
+const ENV_PREFIX: &str = "RUSTYCRAFT_";
+
+/// Top-level tables of [Config], the only ones environment overrides may target.
+const SECTIONS: &[&str] = &["redis", "bnet", "world", "admin", "auth"];
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub redis: RedisConfig,
+    pub bnet: BnetConfig,
+    pub world: WorldConfig,
+    pub admin: AdminConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BnetConfig {
+    /// Battle.net RPC socket.
+    pub bind_address: SocketAddr,
+    /// HTTPS login form served to the client.
+    pub web_bind_address: SocketAddr,
+    /// PEM certificate chain. When neither this nor `key_path` is set, a self-signed
+    /// certificate is generated at startup, which is only good for development.
+    pub cert_path: Option<PathBuf>,
+    /// PEM private key: PKCS#1 RSA, PKCS#8 or SEC1 EC.
+    pub key_path: Option<PathBuf>,
+    /// How often the certificate files are checked for changes. 0 only reloads on SIGHUP.
+    pub tls_reload_interval_secs: u64,
+    /// Sent to the client in `logon` as the `web_auth_url` external challenge.
+    pub login_url: String,
+    /// How long connections get to close after SIGINT/SIGTERM.
+    pub shutdown_timeout_secs: u64,
+    /// Prometheus scrape endpoint. Disabled when unset.
+    pub metrics_bind_address: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldConfig {
+    pub bind_address: SocketAddr,
+    /// Realm this world server hosts, within its region and site. At most 0xFFFF.
+    pub realm_id: u32,
+    /// Region and site of the realm, see [WorldConfig::virtual_realm_address].
+    pub region_id: u8,
+    pub site_id: u8,
+    /// Sent in SMSG_SET_TIME_ZONE_INFORMATION.
+    pub timezone: String,
+    /// Countdown announced to players before the world server stops.
+    pub shutdown_countdown_secs: u64,
+    /// How long sessions get to close once the countdown is over.
+    pub shutdown_timeout_secs: u64,
+    /// Prometheus scrape endpoint. Disabled when unset.
+    pub metrics_bind_address: Option<SocketAddr>,
+    /// `DBFilesClient` directory extracted from the client. No game data is loaded when unset.
+    pub data_dir: Option<PathBuf>,
+    /// PEM RSA key, PKCS#1 or PKCS#8, signing `EncryptedMode`. Clients must be patched with its
+    /// modulus, see the `world_rsa_key` tool.
+    pub rsa_key_path: Option<PathBuf>,
+    /// Signs with TrinityCore's key, which every patched client already trusts. Its private half
+    /// is public, so anyone can impersonate the server: only for development.
+    pub use_bundled_rsa_key: bool,
+    /// Sent in `ConnectTo` for the instance connection. Defaults to the address the client
+    /// reached the realm connection on, set it when that is not reachable from outside.
+    pub instance_address: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// HTTPS admin API, served by the bnet server. Keep it off public interfaces.
+    pub bind_address: SocketAddr,
+    /// Bearer token required on every admin request. The admin API is disabled when unset.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Legacy grunt login socket for 3.3.5a clients.
+    pub bind_address: SocketAddr,
+    /// How long connections get to close after SIGINT/SIGTERM.
+    pub shutdown_timeout_secs: u64,
+    /// Prometheus scrape endpoint. Disabled when unset.
+    pub metrics_bind_address: Option<SocketAddr>,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: "redis://127.0.0.1".to_owned(),
+        }
+    }
+}
+
+impl Default for BnetConfig {
+    fn default() -> Self {
+        BnetConfig {
+            bind_address: ([0, 0, 0, 0], 1119).into(),
+            web_bind_address: ([0, 0, 0, 0], 9990).into(),
+            cert_path: None,
+            key_path: None,
+            tls_reload_interval_secs: 60,
+            login_url: "https://127.0.0.1:9990/bnetserver/login/".to_owned(),
+            shutdown_timeout_secs: 10,
+            metrics_bind_address: None,
+        }
+    }
+}
+
+impl WorldConfig {
+    /// `wow_realm_address` of the realm, `(region << 24) | (site << 16) | realm`.
+    pub fn virtual_realm_address(&self) -> u32 {
+        u32::from(self.region_id) << 24 | u32::from(self.site_id) << 16 | (self.realm_id & 0xFFFF)
+    }
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig {
+            bind_address: ([0, 0, 0, 0], 9900).into(),
+            realm_id: 1024,
+            region_id: 1,
+            site_id: 1,
+            timezone: "Europe/Paris".to_owned(),
+            shutdown_countdown_secs: 30,
+            shutdown_timeout_secs: 10,
+            metrics_bind_address: None,
+            data_dir: None,
+            rsa_key_path: None,
+            use_bundled_rsa_key: false,
+            instance_address: None,
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            bind_address: ([127, 0, 0, 1], 9991).into(),
+            token: None,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            bind_address: ([0, 0, 0, 0], 3724).into(),
+            shutdown_timeout_secs: 10,
+            metrics_bind_address: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, applies `RUSTYCRAFT_*` environment overrides and validates the result.
+    pub fn load() -> anyhow::Result<Config> {
+        let (path, required) = match std::env::var(CONFIG_PATH_ENV) {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+        let mut tree = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .parse::<toml::Value>()
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to read {}", path.display()))?
+            }
+            Err(_) => toml::Value::Table(Default::default()),
+        };
+        apply_overrides(&mut tree, std::env::vars())?;
+        let config: Config = tree.try_into().context("Invalid configuration")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !["redis://", "rediss://", "redis+unix://", "unix://"]
+            .iter()
+            .any(|scheme| self.redis.url.starts_with(scheme))
+        {
+            bail!("redis.url must be a redis:// URL, got {:?}", self.redis.url);
+        }
+        if !self.bnet.login_url.starts_with("https://")
+            && !self.bnet.login_url.starts_with("http://")
+        {
+            bail!(
+                "bnet.login_url must be an http(s) URL, got {:?}",
+                self.bnet.login_url
+            );
+        }
+        if self.bnet.cert_path.is_some() != self.bnet.key_path.is_some() {
+            bail!("bnet.cert_path and bnet.key_path must be set together");
+        }
+        if matches!(&self.admin.token, Some(token) if token.len() < 16) {
+            bail!("admin.token must be at least 16 characters long");
+        }
+        if self.world.realm_id > 0xFFFF {
+            bail!("world.realm_id must be at most 65535");
+        }
+        if self.world.rsa_key_path.is_some() && self.world.use_bundled_rsa_key {
+            bail!("world.rsa_key_path and world.use_bundled_rsa_key are exclusive");
+        }
+        // The client reads time zone names with a 7 bit length prefix.
+        if self.world.timezone.is_empty() || self.world.timezone.len() > 0x7F {
+            bail!("world.timezone must be 1 to 127 bytes long");
+        }
+        Ok(())
+    }
+}
+
+/// Loads the configuration and makes it available through [get]. Call once at startup.
+pub fn init() -> anyhow::Result<&'static Config> {
+    set(Config::load()?)
+}
+
+/// Makes `config` the configuration without reading any file, for tests and tools.
+pub fn set(config: Config) -> anyhow::Result<&'static Config> {
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("Configuration is already initialized"))?;
+    Ok(get())
+}
+
+/// Returns the configuration loaded by [init].
+///
+/// # Panics
+///
+/// If neither [init] nor [set] was called, running on defaults by accident would hide a
+/// missing or unread config file.
+pub fn get() -> &'static Config {
+    CONFIG
+        .get()
+        .expect("config::init must be called before the configuration is read")
+}
+
+fn apply_overrides(
+    tree: &mut toml::Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> anyhow::Result<()> {
+    for (key, raw) in vars {
+        let path = match key.strip_prefix(ENV_PREFIX) {
+            Some(path) if key != CONFIG_PATH_ENV => path.to_lowercase(),
+            _ => continue,
+        };
+        // Other tools share the prefix, only `<SECTION>__<KEY>` of a known section is ours.
+        match path.split_once("__") {
+            Some((section, _)) if SECTIONS.contains(&section) => {}
+            _ => continue,
+        }
+        let mut node = &mut *tree;
+        let mut sections = path.split("__").peekable();
+        while let Some(section) = sections.next() {
+            let table = node
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("{} does not point to a config section", key))?;
+            if sections.peek().is_none() {
+                table.insert(section.to_owned(), parse_override(&raw));
+                break;
+            }
+            node = table
+                .entry(section.to_owned())
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+        }
+    }
+    Ok(())
+}
+
+/// Environment values are TOML literals when they parse as one, plain strings otherwise.
+fn parse_override(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Value>()
+        .ok()
+        .and_then(|mut v| v.as_table_mut()?.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut tree: toml::Value = "[bnet]\nlogin_url = \"https://example.org/login/\"\n"
+            .parse()
+            .unwrap();
+        apply_overrides(
+            &mut tree,
+            vars(&[
+                ("RUSTYCRAFT_BNET__BIND_ADDRESS", "127.0.0.1:1119"),
+                ("RUSTYCRAFT_WORLD__TIMEZONE", "Etc/UTC"),
+                ("RUSTYCRAFT_CONFIG", "ignored.toml"),
+                ("RUSTYCRAFT_LOG", "debug"),
+                ("RUSTYCRAFT_TOOLS__DATA_DIR", "/tmp"),
+                ("PATH", "/usr/bin"),
+            ]),
+        )
+        .unwrap();
+        let config: Config = tree.try_into().unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.bnet.bind_address, ([127, 0, 0, 1], 1119).into());
+        assert_eq!(config.bnet.login_url, "https://example.org/login/");
+        assert_eq!(config.world.timezone, "Etc/UTC");
+        assert_eq!(config.redis.url, RedisConfig::default().url);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.validate().unwrap();
+        config.world.timezone = String::new();
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.bnet.cert_path = Some("./authserver.cert.pem".into());
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.world.rsa_key_path = Some("./world.key.pem".into());
+        config.world.use_bundled_rsa_key = true;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.world.realm_id = 0x1_0000;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_virtual_realm_address() {
+        let mut world = WorldConfig::default();
+        assert_eq!(world.virtual_realm_address(), 0x0101_0400);
+        world.region_id = 3;
+        world.site_id = 0x25;
+        world.realm_id = 0xBEEF;
+        assert_eq!(world.virtual_realm_address(), 0x0325_BEEF);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config;
+use rustycraft_database::redis::{RedisClient, Storable};
+use sha2::{Digest, Sha256};
+
+/// A client table record replaced or removed by the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hotfix {
+    /// Bumped on every change, clients ask for the pushes they have not seen yet.
+    pub push_id: i32,
+    pub table_hash: u32,
+    pub record_id: i32,
+    /// Record in the layout of the client build, `None` removes the record.
+    pub payload: Option<Vec<u8>>,
+}
+
+impl Storable for Hotfix {
+    fn key_prefix() -> &'static str {
+        "hotfix"
+    }
+}
+
+impl Hotfix {
+    fn key(table_hash: u32, record_id: i32) -> String {
+        format!("{:08X}_{}", table_hash, record_id)
+    }
+
+    /// Identifies the record within its push. Every push carries a single record, so its push
+    /// id, never handed out twice, is unique too.
+    pub fn unique_id(&self) -> u32 {
+        self.push_id as u32
+    }
+}
+
+/// Last push id handed out.
+struct PushCounter;
+
+impl Storable for PushCounter {
+    fn key_prefix() -> &'static str {
+        "hotfix_push_counter"
+    }
+}
+
+/// Record a push id was handed out for, so requested pushes are found without a scan.
+#[derive(Serialize, Deserialize, Debug)]
+struct PushRecord {
+    table_hash: u32,
+    record_id: i32,
+}
+
+impl Storable for PushRecord {
+    fn key_prefix() -> &'static str {
+        "hotfix_push"
+    }
+}
+
+/// Version clients key their local cache with, changes whenever a hotfix does.
+pub fn cache_version(hotfixes: &[Hotfix]) -> u32 {
+    if hotfixes.is_empty() {
+        return 0;
+    }
+    let mut hasher = Sha256::new();
+    for hotfix in hotfixes {
+        hasher.update(hotfix.push_id.to_le_bytes());
+        hasher.update(hotfix.table_hash.to_le_bytes());
+        hasher.update(hotfix.record_id.to_le_bytes());
+        match &hotfix.payload {
+            Some(payload) => {
+                hasher.update([1]);
+                hasher.update((payload.len() as u32).to_le_bytes());
+                hasher.update(payload);
+            }
+            None => hasher.update([0]),
+        }
+    }
+    let hash = hasher.finalize();
+    u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// Hotfixes served to every world session, one per table record.
+pub struct HotfixStore {
+    redis: RedisClient,
+}
+
+impl HotfixStore {
+    pub fn new() -> anyhow::Result<HotfixStore> {
+        Ok(HotfixStore {
+            redis: RedisClient::new(&config::get().redis.url)?,
+        })
+    }
+
+    /// Every hotfix, oldest push first.
+    pub async fn list(&self) -> anyhow::Result<Vec<Hotfix>> {
+        let mut hotfixes = Vec::new();
+        for key in self.redis.keys::<Hotfix>("*").await? {
+            if let Some(hotfix) = self.redis.peek::<Hotfix>(&key).await? {
+                hotfixes.push(hotfix);
+            }
+        }
+        hotfixes.sort_by_key(|hotfix| (hotfix.push_id, hotfix.table_hash, hotfix.record_id));
+        Ok(hotfixes)
+    }
+
+    /// The hotfix of one record, if any.
+    pub async fn get(&self, table_hash: u32, record_id: i32) -> anyhow::Result<Option<Hotfix>> {
+        self.redis
+            .peek::<Hotfix>(&Hotfix::key(table_hash, record_id))
+            .await
+    }
+
+    /// Hotfixes still carrying one of `push_ids`. Pushes replaced or deleted since are skipped.
+    pub async fn pushes(&self, push_ids: &[i32]) -> anyhow::Result<Vec<Hotfix>> {
+        let mut hotfixes = Vec::new();
+        for push_id in push_ids {
+            let record = match self.redis.peek::<PushRecord>(&push_id.to_string()).await? {
+                Some(record) => record,
+                None => continue,
+            };
+            match self.get(record.table_hash, record.record_id).await? {
+                Some(hotfix) if hotfix.push_id == *push_id => hotfixes.push(hotfix),
+                _ => (),
+            }
+        }
+        Ok(hotfixes)
+    }
+
+    /// Stores the record under a new push id.
+    pub async fn put(
+        &self,
+        table_hash: u32,
+        record_id: i32,
+        payload: Option<Vec<u8>>,
+    ) -> anyhow::Result<Hotfix> {
+        let push_id = self.redis.incr_by::<PushCounter>("push_id", 1).await?;
+        let hotfix = Hotfix {
+            push_id: i32::try_from(push_id)?,
+            table_hash,
+            record_id,
+            payload,
+        };
+        let record = PushRecord {
+            table_hash,
+            record_id,
+        };
+        self.redis.set(&hotfix.push_id.to_string(), &record).await?;
+        let replaced = self
+            .redis
+            .swap(&Hotfix::key(table_hash, record_id), &hotfix)
+            .await?;
+        if let Some(replaced) = replaced {
+            self.redis
+                .delete::<PushRecord>(&replaced.push_id.to_string())
+                .await?;
+        }
+        Ok(hotfix)
+    }
+
+    /// Forgets the hotfix, clients keep the record from their own files again.
+    pub async fn delete(&self, table_hash: u32, record_id: i32) -> anyhow::Result<bool> {
+        let key = Hotfix::key(table_hash, record_id);
+        let hotfix = match self.redis.take::<Hotfix>(&key).await? {
+            Some(hotfix) => hotfix,
+            None => return Ok(false),
+        };
+        self.redis
+            .delete::<PushRecord>(&hotfix.push_id.to_string())
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_version() {
+        let mut hotfixes = vec![Hotfix {
+            push_id: 1,
+            table_hash: 0x0C77_B3C2,
+            record_id: 17,
+            payload: Some(vec![1, 2, 3]),
+        }];
+        assert_eq!(cache_version(&[]), 0);
+        let version = cache_version(&hotfixes);
+        assert_ne!(version, 0);
+        assert_eq!(cache_version(&hotfixes), version);
+        hotfixes[0].payload = None;
+        assert_ne!(cache_version(&hotfixes), version);
+    }
+}
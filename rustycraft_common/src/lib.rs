@@ -1,16 +1,68 @@
+pub mod accounts;
+pub mod audit;
+pub mod config;
+pub mod hotfixes;
+pub mod sessions;
+pub mod shutdown;
+pub mod srp6;
+pub mod telemetry;
+
 use std::collections::HashMap;
-use rustycraft_database::redis::Storable;
+use rustycraft_database::redis::{RedisClient, Storable};
 
 #[macro_use]
 extern crate serde;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate anyhow;
 
+/// Realm list entry, keyed by its `wow_realm_address`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Realm {
+    pub id: u32,
     pub name: String,
     pub address: String,
+    pub port: u16,
     pub flags: u32,
     pub locale: u32,
 }
 
+impl Storable for Realm {
+    fn key_prefix() -> &'static str {
+        "realm"
+    }
+}
+
+impl Realm {
+    /// Announced when no realm has been configured, pointing at the local world server.
+    pub fn fallback() -> Realm {
+        Realm {
+            id: config::get().world.virtual_realm_address(),
+            name: "RustyCraft".to_owned(),
+            address: "127.0.0.1".to_owned(),
+            port: config::get().world.bind_address.port(),
+            flags: 0,
+            locale: 1,
+        }
+    }
+
+    /// Realms configured through the admin API, or the fallback realm when there are none.
+    pub async fn list(redis: &RedisClient) -> anyhow::Result<Vec<Realm>> {
+        let mut realms = Vec::new();
+        for id in redis.keys::<Realm>("*").await? {
+            if let Some(realm) = redis.peek::<Realm>(&id).await? {
+                realms.push(realm);
+            }
+        }
+        if realms.is_empty() {
+            realms.push(Realm::fallback());
+        }
+        realms.sort_by_key(|realm| realm.id);
+        Ok(realms)
+    }
+}
+
 pub struct Character {
     pub nickname: String,
     pub realm: Realm,
@@ -25,8 +77,11 @@ pub struct BattleNetAccount {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Account {
+    pub account_name: String,
     pub server_secret: Vec<u8>,
     pub client_secret: Vec<u8>,
+    /// Build the client reported to the bnet server, selects the world protocol.
+    pub build: u32,
 }
 
 impl Storable for Account {
@@ -34,3 +89,28 @@ impl Storable for Account {
         "account"
     }
 }
+
+/// Session key of a legacy grunt logon, read by reconnects and by the world server.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LegacySession {
+    pub account_name: String,
+    pub session_key: Vec<u8>,
+    pub build: u16,
+}
+
+impl Storable for LegacySession {
+    fn key_prefix() -> &'static str {
+        "legacy_session"
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoginTicket {
+    pub account_name: String,
+}
+
+impl Storable for LoginTicket {
+    fn key_prefix() -> &'static str {
+        "login_ticket"
+    }
+}
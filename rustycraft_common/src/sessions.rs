@@ -0,0 +1,185 @@
+use crate::config;
+use rustycraft_database::redis::{RedisClient, Storable};
+use rustycraft_protocol::rpc_responses::WowRpcResponse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+const KICK_CHANNEL: &str = "session_kick";
+
+/// Which server owns a session. Each kind allows one active session per account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    Bnet,
+    World,
+}
+
+impl SessionKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionKind::Bnet => "bnet",
+            SessionKind::World => "world",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ActiveSession {
+    pub session_id: String,
+}
+
+impl Storable for ActiveSession {
+    fn key_prefix() -> &'static str {
+        "active_session"
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionKick {
+    session_id: String,
+    reason: WowRpcResponse,
+}
+
+/// Tracks the active session of every account in Redis and delivers kicks
+/// to sessions living in this process, whichever process requested them.
+pub struct SessionRegistry {
+    kind: SessionKind,
+    redis: RedisClient,
+    local: Mutex<HashMap<String, mpsc::Sender<WowRpcResponse>>>,
+}
+
+impl SessionRegistry {
+    pub async fn new(kind: SessionKind) -> anyhow::Result<Arc<SessionRegistry>> {
+        let registry = Arc::new(SessionRegistry {
+            kind,
+            redis: RedisClient::new(&config::get().redis.url)?,
+            local: Mutex::new(HashMap::new()),
+        });
+        let mut kicks = registry
+            .redis
+            .subscribe::<SessionKick>(KICK_CHANNEL)
+            .await?;
+        let listener = Arc::downgrade(&registry);
+        tokio::spawn(async move {
+            while let Some(kick) = kicks.recv().await {
+                match listener.upgrade() {
+                    Some(registry) => registry.deliver(kick),
+                    None => break,
+                }
+            }
+        });
+        Ok(registry)
+    }
+
+    fn key(&self, account_name: &str) -> String {
+        format!("{}__{}", self.kind.as_str(), account_name)
+    }
+
+    /// Makes a new session the active one for `account_name` and kicks the one it replaces.
+    /// Kick reasons are delivered through `kicks`. Returns the new session id.
+    pub async fn register(
+        &self,
+        account_name: &str,
+        kicks: mpsc::Sender<WowRpcResponse>,
+    ) -> anyhow::Result<String> {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        self.local.lock().unwrap().insert(session_id.clone(), kicks);
+        let active = ActiveSession {
+            session_id: session_id.clone(),
+        };
+        if let Some(previous) = self.redis.swap(&self.key(account_name), &active).await? {
+            info!(target: "SessionRegistry",
+                "Account {} logged in again, kicking {:?} session {}",
+                account_name, self.kind, previous.session_id
+            );
+            self.kick(&previous.session_id, WowRpcResponse::SessionDuplicate)
+                .await?;
+        }
+        Ok(session_id)
+    }
+
+    /// Forgets a session. The account entry is only cleared if no newer session took it over.
+    pub async fn unregister(&self, account_name: &str, session_id: &str) -> anyhow::Result<()> {
+        self.local.lock().unwrap().remove(session_id);
+        let active = ActiveSession {
+            session_id: session_id.to_owned(),
+        };
+        self.redis
+            .remove_if_eq(&self.key(account_name), &active)
+            .await?;
+        Ok(())
+    }
+
+    /// Active sessions of this kind across all processes, as `(account name, session id)`.
+    pub async fn active_sessions(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let prefix = self.key("");
+        let mut sessions = Vec::new();
+        for key in self
+            .redis
+            .keys::<ActiveSession>(&format!("{}*", prefix))
+            .await?
+        {
+            if let Some(active) = self.redis.peek::<ActiveSession>(&key).await? {
+                sessions.push((key[prefix.len()..].to_owned(), active.session_id));
+            }
+        }
+        Ok(sessions)
+    }
+
+    pub async fn active_session(&self, account_name: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .redis
+            .peek::<ActiveSession>(&self.key(account_name))
+            .await?
+            .map(|active| active.session_id))
+    }
+
+    pub async fn kick(&self, session_id: &str, reason: WowRpcResponse) -> anyhow::Result<()> {
+        let kick = SessionKick {
+            session_id: session_id.to_owned(),
+            reason,
+        };
+        self.redis.publish(KICK_CHANNEL, &kick).await
+    }
+
+    /// Never waits on a session, a full channel already holds a kick for it.
+    fn deliver(&self, kick: SessionKick) {
+        let local = self.local.lock().unwrap();
+        if let Some(session) = local.get(&kick.session_id) {
+            debug!(target: "SessionRegistry", "Kicking session {}: {:?}", kick.session_id, kick.reason);
+            let _ = session.try_send(kick.reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deliver_to_busy_session() {
+        let registry = SessionRegistry {
+            kind: SessionKind::World,
+            redis: RedisClient::new("redis://127.0.0.1").unwrap(),
+            local: Mutex::new(HashMap::new()),
+        };
+        let (kicks_tx, mut kicks_rx) = mpsc::channel(1);
+        registry
+            .local
+            .lock()
+            .unwrap()
+            .insert("session".to_owned(), kicks_tx);
+        let kick = |reason| SessionKick {
+            session_id: "session".to_owned(),
+            reason,
+        };
+        // The session never reads its kicks, delivering must not wait for it.
+        registry.deliver(kick(WowRpcResponse::SessionDuplicate));
+        registry.deliver(kick(WowRpcResponse::Denied));
+        assert_eq!(
+            kicks_rx.try_recv().unwrap(),
+            WowRpcResponse::SessionDuplicate
+        );
+        assert!(kicks_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+
+/// Owned by `main`. Hands out [Shutdown] handles to every task and, on shutdown,
+/// waits until all of them are dropped.
+pub struct ShutdownController {
+    notify: watch::Sender<bool>,
+    drain_tx: mpsc::Sender<()>,
+    drain_rx: mpsc::Receiver<()>,
+}
+
+/// Held by a task for as long as it runs. The controller treats the task
+/// as finished once its handle is dropped.
+#[derive(Clone)]
+pub struct Shutdown {
+    notify: watch::Receiver<bool>,
+    _drain: mpsc::Sender<()>,
+}
+
+impl Default for ShutdownController {
+    fn default() -> Self {
+        ShutdownController::new()
+    }
+}
+
+impl ShutdownController {
+    pub fn new() -> ShutdownController {
+        let (notify, _) = watch::channel(false);
+        let (drain_tx, drain_rx) = mpsc::channel(1);
+        ShutdownController {
+            notify,
+            drain_tx,
+            drain_rx,
+        }
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        Shutdown {
+            notify: self.notify.subscribe(),
+            _drain: self.drain_tx.clone(),
+        }
+    }
+
+    /// Notifies every [Shutdown] handle and waits for all of them to be dropped.
+    /// Returns `false` if some tasks were still running when `timeout` elapsed.
+    pub async fn shutdown(self, timeout: Duration) -> bool {
+        let ShutdownController {
+            notify,
+            drain_tx,
+            mut drain_rx,
+        } = self;
+        let _ = notify.send(true);
+        drop(drain_tx);
+        tokio::time::timeout(timeout, drain_rx.recv()).await.is_ok()
+    }
+}
+
+impl Shutdown {
+    pub fn is_shutdown(&self) -> bool {
+        *self.notify.borrow()
+    }
+
+    /// Completes once shutdown has been requested. Safe to use in `tokio::select!`.
+    pub async fn recv(&mut self) {
+        while !*self.notify.borrow() {
+            if self.notify.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Completes on the first SIGINT or SIGTERM.
+pub async fn wait_for_signal() -> std::io::Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => info!(target: "Shutdown", "Received SIGINT"),
+        _ = terminate.recv() => info!(target: "Shutdown", "Received SIGTERM"),
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_tasks() {
+        let controller = ShutdownController::new();
+        let mut shutdown = controller.subscribe();
+        let task = tokio::spawn(async move {
+            shutdown.recv().await;
+            assert!(shutdown.is_shutdown());
+        });
+        assert!(controller.shutdown(Duration::from_secs(5)).await);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() {
+        let controller = ShutdownController::default();
+        let shutdown = controller.subscribe();
+        assert!(!shutdown.is_shutdown());
+        assert!(!controller.shutdown(Duration::from_millis(10)).await);
+        assert!(shutdown.is_shutdown());
+    }
+}
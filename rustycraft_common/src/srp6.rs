@@ -0,0 +1,206 @@
+//! SRP6 as used by the legacy grunt login: SHA-1 and 32 byte little-endian numbers.
+
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+pub const SALT_LENGTH: usize = 32;
+pub const KEY_LENGTH: usize = 32;
+pub const SESSION_KEY_LENGTH: usize = 40;
+pub const G: u8 = 7;
+/// Safe prime, little-endian.
+pub const N: [u8; KEY_LENGTH] = [
+    0xB7, 0x9B, 0x3E, 0x2A, 0x87, 0x82, 0x3C, 0xAB, 0x8F, 0x5E, 0xBF, 0xBF, 0x8E, 0xB1, 0x01, 0x08,
+    0x53, 0x50, 0x06, 0x29, 0x8B, 0x5B, 0xAD, 0xBD, 0x5B, 0x53, 0xE1, 0x89, 0x5E, 0x64, 0x4B, 0x89,
+];
+const K: u8 = 3;
+
+fn n() -> BigUint {
+    BigUint::from_bytes_le(&N)
+}
+
+fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn to_bytes(value: &BigUint) -> [u8; KEY_LENGTH] {
+    let mut out = [0; KEY_LENGTH];
+    let bytes = value.to_bytes_le();
+    out[..bytes.len()].copy_from_slice(&bytes);
+    out
+}
+
+/// `v = g ^ H(s | H(USERNAME ":" PASSWORD))`. The client uppercases both before hashing.
+pub fn calculate_verifier(username: &str, password: &str, salt: &[u8]) -> Vec<u8> {
+    let credentials = format!("{}:{}", username.to_uppercase(), password.to_uppercase());
+    let x = BigUint::from_bytes_le(&sha1(&[salt, &sha1(&[credentials.as_bytes()])]));
+    to_bytes(&BigUint::from(G).modpow(&x, &n())).to_vec()
+}
+
+/// Random salt and the verifier derived from it.
+pub fn make_verifier(username: &str, password: &str) -> (Vec<u8>, Vec<u8>) {
+    let salt = rand::random::<[u8; SALT_LENGTH]>().to_vec();
+    let verifier = calculate_verifier(username, password, &salt);
+    (salt, verifier)
+}
+
+/// `K`, hashing the even and odd bytes of `S` separately.
+fn interleave(s: &[u8; KEY_LENGTH]) -> [u8; SESSION_KEY_LENGTH] {
+    let mut start = s.iter().position(|b| *b != 0).unwrap_or(KEY_LENGTH);
+    if start % 2 == 1 {
+        start += 1;
+    }
+    let s = &s[start.min(KEY_LENGTH)..];
+    let even: Vec<u8> = s.iter().step_by(2).copied().collect();
+    let odd: Vec<u8> = s.iter().skip(1).step_by(2).copied().collect();
+    let (even, odd) = (sha1(&[&even]), sha1(&[&odd]));
+    let mut key = [0; SESSION_KEY_LENGTH];
+    for i in 0..20 {
+        key[i * 2] = even[i];
+        key[i * 2 + 1] = odd[i];
+    }
+    key
+}
+
+pub struct SessionProof {
+    pub session_key: [u8; SESSION_KEY_LENGTH],
+    /// `M2`, proves to the client that the server knows the verifier.
+    pub server_proof: [u8; 20],
+}
+
+/// Server side of one logon.
+pub struct Srp6Server {
+    username: String,
+    salt: Vec<u8>,
+    verifier: BigUint,
+    b: BigUint,
+    public_b: [u8; KEY_LENGTH],
+}
+
+impl Srp6Server {
+    pub fn new(username: &str, salt: &[u8], verifier: &[u8]) -> Srp6Server {
+        let verifier = BigUint::from_bytes_le(verifier);
+        let b = BigUint::from_bytes_le(&rand::random::<[u8; 19]>());
+        let public_b = (BigUint::from(K) * &verifier + BigUint::from(G).modpow(&b, &n())) % n();
+        Srp6Server {
+            username: username.to_uppercase(),
+            salt: salt.to_vec(),
+            verifier,
+            b,
+            public_b: to_bytes(&public_b),
+        }
+    }
+
+    pub fn public_b(&self) -> &[u8; KEY_LENGTH] {
+        &self.public_b
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Checks the client proof `M1` against its public key `A`.
+    pub fn verify(
+        &self,
+        public_a: &[u8; KEY_LENGTH],
+        client_proof: &[u8; 20],
+    ) -> Option<SessionProof> {
+        let a = BigUint::from_bytes_le(public_a);
+        if (&a % n()) == BigUint::from(0u8) {
+            return None;
+        }
+        let u = BigUint::from_bytes_le(&sha1(&[public_a, &self.public_b]));
+        let s = (a * self.verifier.modpow(&u, &n())).modpow(&self.b, &n());
+        let session_key = interleave(&to_bytes(&s));
+
+        let n_hash = sha1(&[&N]);
+        let g_hash = sha1(&[&[G]]);
+        let mut ng_hash = [0; 20];
+        for i in 0..20 {
+            ng_hash[i] = n_hash[i] ^ g_hash[i];
+        }
+        let expected = sha1(&[
+            &ng_hash,
+            &sha1(&[self.username.as_bytes()]),
+            &self.salt,
+            public_a,
+            &self.public_b,
+            &session_key,
+        ]);
+        if &expected != client_proof {
+            return None;
+        }
+        Some(SessionProof {
+            session_key,
+            server_proof: sha1(&[public_a, client_proof, &session_key]),
+        })
+    }
+}
+
+/// Proof a reconnecting client computes from the session key of its previous logon.
+pub fn reconnect_proof(
+    username: &str,
+    client_data: &[u8],
+    server_challenge: &[u8],
+    session_key: &[u8],
+) -> [u8; 20] {
+    sha1(&[
+        username.to_uppercase().as_bytes(),
+        client_data,
+        server_challenge,
+        session_key,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the client computes from the challenge.
+    fn client_proof(
+        username: &str,
+        password: &str,
+        salt: &[u8],
+        public_b: &[u8; KEY_LENGTH],
+    ) -> ([u8; KEY_LENGTH], [u8; 20], [u8; SESSION_KEY_LENGTH]) {
+        let a = BigUint::from_bytes_le(&rand::random::<[u8; 19]>());
+        let public_a = to_bytes(&BigUint::from(G).modpow(&a, &n()));
+        let credentials = format!("{}:{}", username.to_uppercase(), password.to_uppercase());
+        let x = BigUint::from_bytes_le(&sha1(&[salt, &sha1(&[credentials.as_bytes()])]));
+        let u = BigUint::from_bytes_le(&sha1(&[&public_a, public_b]));
+        let kv = (BigUint::from(K) * BigUint::from(G).modpow(&x, &n())) % n();
+        let base = (BigUint::from_bytes_le(public_b) + n() - kv) % n();
+        let s = base.modpow(&(a + u * x), &n());
+        let session_key = interleave(&to_bytes(&s));
+        let n_hash = sha1(&[&N]);
+        let g_hash = sha1(&[&[G]]);
+        let ng_hash: Vec<u8> = n_hash.iter().zip(g_hash).map(|(n, g)| n ^ g).collect();
+        let m1 = sha1(&[
+            &ng_hash,
+            &sha1(&[username.to_uppercase().as_bytes()]),
+            salt,
+            &public_a,
+            public_b,
+            &session_key,
+        ]);
+        (public_a, m1, session_key)
+    }
+
+    #[test]
+    fn test_logon() {
+        let (salt, verifier) = make_verifier("player", "Secret");
+        let server = Srp6Server::new("PLAYER", &salt, &verifier);
+        let (public_a, m1, session_key) =
+            client_proof("player", "SECRET", &salt, server.public_b());
+        let proof = server.verify(&public_a, &m1).unwrap();
+        assert_eq!(proof.session_key, session_key);
+        assert_eq!(proof.server_proof, sha1(&[&public_a, &m1, &session_key]));
+
+        let (public_a, m1, _) = client_proof("player", "wrong", &salt, server.public_b());
+        assert!(server.verify(&public_a, &m1).is_none());
+        assert!(server.verify(&N, &m1).is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::accounts::unix_now;
+use crate::config;
+use prost::Message;
+use rustycraft_database::redis::{RedisClient, Storable};
+use rustycraft_protocol::blizzard::telemetry::wow::client::{
+    AuroraError, ClientCancelConnect, ClientDisconnect, ClientInfo, CriticalStreamingError,
+    WorldLoadFailed, WorldSceneLoadFailed,
+};
+use std::net::SocketAddr;
+
+/// Only the most recent reports are kept.
+const MAX_RECORDS: isize = 10_000;
+const RECORDS_KEY: &str = "reports";
+
+/// A client report, decoded from one of the bundled telemetry protos or a world packet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum TelemetryEvent {
+    AuroraError(AuroraError),
+    WorldLoadFailed(WorldLoadFailed),
+    WorldSceneLoadFailed(WorldSceneLoadFailed),
+    CriticalStreamingError(CriticalStreamingError),
+    ClientDisconnect(ClientDisconnect),
+    ClientCancelConnect(ClientCancelConnect),
+    /// `CMSG_LOG_STREAMING_ERROR`.
+    LogStreamingError {
+        error: String,
+    },
+}
+
+impl TelemetryEvent {
+    /// Decodes a protobuf report by its message name, e.g. `WorldLoadFailed`.
+    pub fn decode(message_name: &str, payload: &[u8]) -> anyhow::Result<TelemetryEvent> {
+        Ok(match message_name {
+            "AuroraError" => TelemetryEvent::AuroraError(Message::decode(payload)?),
+            "WorldLoadFailed" => TelemetryEvent::WorldLoadFailed(Message::decode(payload)?),
+            "WorldSceneLoadFailed" => {
+                TelemetryEvent::WorldSceneLoadFailed(Message::decode(payload)?)
+            }
+            "CriticalStreamingError" => {
+                TelemetryEvent::CriticalStreamingError(Message::decode(payload)?)
+            }
+            "ClientDisconnect" => TelemetryEvent::ClientDisconnect(Message::decode(payload)?),
+            "ClientCancelConnect" => TelemetryEvent::ClientCancelConnect(Message::decode(payload)?),
+            _ => bail!("Unknown telemetry message {:?}", message_name),
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TelemetryEvent::AuroraError(_) => "AuroraError",
+            TelemetryEvent::WorldLoadFailed(_) => "WorldLoadFailed",
+            TelemetryEvent::WorldSceneLoadFailed(_) => "WorldSceneLoadFailed",
+            TelemetryEvent::CriticalStreamingError(_) => "CriticalStreamingError",
+            TelemetryEvent::ClientDisconnect(_) => "ClientDisconnect",
+            TelemetryEvent::ClientCancelConnect(_) => "ClientCancelConnect",
+            TelemetryEvent::LogStreamingError { .. } => "LogStreamingError",
+        }
+    }
+
+    fn client_info(&self) -> Option<&ClientInfo> {
+        match self {
+            TelemetryEvent::AuroraError(e) => e.client.as_ref(),
+            TelemetryEvent::WorldLoadFailed(e) => e.client.as_ref(),
+            TelemetryEvent::WorldSceneLoadFailed(e) => e.client.as_ref(),
+            TelemetryEvent::CriticalStreamingError(e) => e.client.as_ref(),
+            TelemetryEvent::ClientDisconnect(e) => e.client.as_ref(),
+            TelemetryEvent::ClientCancelConnect(e) => e.client.as_ref(),
+            TelemetryEvent::LogStreamingError { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TelemetryRecord {
+    pub received_at: u64,
+    pub peer: SocketAddr,
+    /// Unknown for reports sent outside an authenticated session.
+    pub account_name: Option<String>,
+    pub realm_id: Option<u32>,
+    pub event: TelemetryEvent,
+}
+
+impl Storable for TelemetryRecord {
+    fn key_prefix() -> &'static str {
+        "telemetry"
+    }
+}
+
+impl TelemetryRecord {
+    /// Builds a record, taking the realm from the report itself when `realm_id` is unknown.
+    pub fn new(
+        peer: SocketAddr,
+        account_name: Option<String>,
+        realm_id: Option<u32>,
+        event: TelemetryEvent,
+    ) -> TelemetryRecord {
+        let realm_id = realm_id.or_else(|| event.client_info()?.realm_address);
+        TelemetryRecord {
+            received_at: unix_now(),
+            peer,
+            account_name,
+            realm_id,
+            event,
+        }
+    }
+}
+
+/// Appends reports to a capped Redis list and reads them back, newest first.
+pub struct TelemetryStore {
+    redis: RedisClient,
+}
+
+impl TelemetryStore {
+    pub fn new() -> anyhow::Result<TelemetryStore> {
+        Ok(TelemetryStore {
+            redis: RedisClient::new(&config::get().redis.url)?,
+        })
+    }
+
+    pub async fn record(&self, record: &TelemetryRecord) -> anyhow::Result<()> {
+        info!(target: "Telemetry", "{}", serde_json::to_string(record)?);
+        self.redis.push(RECORDS_KEY, record, MAX_RECORDS).await
+    }
+
+    /// Up to `limit` of the newest records matching the optional account and event type.
+    pub async fn query(
+        &self,
+        account_name: Option<&str>,
+        kind: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<TelemetryRecord>> {
+        let records = self
+            .redis
+            .range::<TelemetryRecord>(RECORDS_KEY, MAX_RECORDS)
+            .await?;
+        Ok(records
+            .into_iter()
+            .filter(|r| account_name.is_none_or(|name| r.account_name.as_deref() == Some(name)))
+            .filter(|r| kind.is_none_or(|kind| r.event.kind() == kind))
+            .take(limit)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustycraft_protocol::blizzard::telemetry::wow::client::WorldInfo;
+
+    #[test]
+    fn test_decode() {
+        let report = WorldLoadFailed {
+            file_data_id: Some(1_234),
+            client: Some(ClientInfo {
+                realm_address: Some(1024),
+                ..Default::default()
+            }),
+            world: Some(WorldInfo {
+                map_id: Some(1),
+                ..Default::default()
+            }),
+        };
+        let event = TelemetryEvent::decode("WorldLoadFailed", &report.encode_to_vec()).unwrap();
+        assert_eq!(event, TelemetryEvent::WorldLoadFailed(report));
+        let record = TelemetryRecord::new(([127, 0, 0, 1], 1).into(), None, None, event);
+        assert_eq!(record.realm_id, Some(1024));
+        assert!(serde_json::to_string(&record)
+            .unwrap()
+            .contains("\"type\":\"WorldLoadFailed\""));
+        assert!(TelemetryEvent::decode("Unknown", &[]).is_err());
+    }
+}
@@ -1,15 +1,18 @@
-use redis::{AsyncCommands, RedisResult};
+use futures_util::StreamExt;
+use redis::{AsyncCommands, RedisResult, Script};
+use rustycraft_metrics::redis_timer;
 use serde::de::DeserializeOwned;
-use serde::{Serialize};
+use serde::Serialize;
+use tokio::sync::mpsc;
 
 pub struct RedisClient {
     client: redis::Client,
 }
 
 impl RedisClient {
-    pub fn new() -> RedisResult<RedisClient> {
+    pub fn new(url: &str) -> RedisResult<RedisClient> {
         Ok(RedisClient {
-            client: redis::Client::open("redis://127.0.0.1")?,
+            client: redis::Client::open(url)?,
         })
     }
 
@@ -17,6 +20,7 @@ impl RedisClient {
     where
         T: Serialize + Storable,
     {
+        let _timer = redis_timer("set");
         let mut conn = self.client.get_async_connection().await?;
         Ok(conn
             .set(
@@ -26,18 +30,327 @@ impl RedisClient {
             .await?)
     }
 
+    /// Stores `data` for `ttl_secs` seconds, Redis deletes it afterwards.
+    pub async fn set_expiring<T>(&self, key: &str, data: &T, ttl_secs: usize) -> anyhow::Result<()>
+    where
+        T: Serialize + Storable,
+    {
+        let _timer = redis_timer("setex");
+        let mut conn = self.client.get_async_connection().await?;
+        Ok(conn
+            .set_ex(
+                format!("{}__{}", T::key_prefix(), key),
+                serde_json::to_string(data)?,
+                ttl_secs,
+            )
+            .await?)
+    }
+
+    /// Stores `data` only if the key doesn't exist yet. Returns whether it was stored.
+    pub async fn set_if_absent<T>(&self, key: &str, data: &T) -> anyhow::Result<bool>
+    where
+        T: Serialize + Storable,
+    {
+        let _timer = redis_timer("setnx");
+        let mut conn = self.client.get_async_connection().await?;
+        Ok(conn
+            .set_nx(
+                format!("{}__{}", T::key_prefix(), key),
+                serde_json::to_string(data)?,
+            )
+            .await?)
+    }
+
+    /// Atomically reads and deletes the key, so a value is only ever taken once. Fails if the
+    /// key is missing.
     pub async fn get<T>(&self, key: &str) -> anyhow::Result<T>
     where
         T: DeserializeOwned + Storable,
     {
+        self.take(key)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Key {}__{} does not exist", T::key_prefix(), key))
+    }
+
+    /// Same as [RedisClient::get], but returns `None` for a missing key.
+    pub async fn take<T>(&self, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T: DeserializeOwned + Storable,
+    {
+        let _timer = redis_timer("getdel");
+        let mut conn = self.client.get_async_connection().await?;
+        let key = format!("{}__{}", T::key_prefix(), key);
+        let (data, _deleted): (Option<Vec<u8>>, u32) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .query_async(&mut conn)
+            .await?;
+        Ok(data.map(|d| serde_json::from_slice(&d)).transpose()?)
+    }
+
+    /// Same as [RedisClient::get], but leaves the value in place and returns `None` for a missing key.
+    pub async fn peek<T>(&self, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T: DeserializeOwned + Storable,
+    {
+        let _timer = redis_timer("get");
+        let mut conn = self.client.get_async_connection().await?;
+        let data: Option<Vec<u8>> = conn.get(format!("{}__{}", T::key_prefix(), key)).await?;
+        Ok(data.map(|d| serde_json::from_slice(&d)).transpose()?)
+    }
+
+    /// Atomically stores `data` and returns the value it replaced.
+    pub async fn swap<T>(&self, key: &str, data: &T) -> anyhow::Result<Option<T>>
+    where
+        T: Serialize + DeserializeOwned + Storable,
+    {
+        let _timer = redis_timer("getset");
+        let mut conn = self.client.get_async_connection().await?;
+        let previous: Option<Vec<u8>> = conn
+            .getset(
+                format!("{}__{}", T::key_prefix(), key),
+                serde_json::to_string(data)?,
+            )
+            .await?;
+        Ok(previous.map(|d| serde_json::from_slice(&d)).transpose()?)
+    }
+
+    /// Deletes the key only while it still holds `expected`, so a newer writer is never clobbered.
+    pub async fn remove_if_eq<T>(&self, key: &str, expected: &T) -> anyhow::Result<bool>
+    where
+        T: Serialize + Storable,
+    {
+        let _timer = redis_timer("eval");
+        let mut conn = self.client.get_async_connection().await?;
+        let removed: u32 = Script::new(
+            r"if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) else return 0 end",
+        )
+        .key(format!("{}__{}", T::key_prefix(), key))
+        .arg(serde_json::to_string(expected)?)
+        .invoke_async(&mut conn)
+        .await?;
+        Ok(removed > 0)
+    }
+
+    /// Atomically adds `amount` to the counter `key` and returns the new value.
+    pub async fn incr_by<T>(&self, key: &str, amount: u64) -> anyhow::Result<u64>
+    where
+        T: Storable,
+    {
+        let _timer = redis_timer("incrby");
+        let mut conn = self.client.get_async_connection().await?;
+        Ok(conn
+            .incr(format!("{}__{}", T::key_prefix(), key), amount)
+            .await?)
+    }
+
+    /// Sets the counter `key` to `value` if it still holds `expected`, like a compare and swap.
+    pub async fn set_counter_if_eq<T>(
+        &self,
+        key: &str,
+        expected: u64,
+        value: u64,
+    ) -> anyhow::Result<bool>
+    where
+        T: Storable,
+    {
+        let _timer = redis_timer("eval");
+        let mut conn = self.client.get_async_connection().await?;
+        let set: u32 = Script::new(
+            r"if redis.call('get', KEYS[1]) == ARGV[1] then redis.call('set', KEYS[1], ARGV[2]) return 1 else return 0 end",
+        )
+        .key(format!("{}__{}", T::key_prefix(), key))
+        .arg(expected.to_string())
+        .arg(value.to_string())
+        .invoke_async(&mut conn)
+        .await?;
+        Ok(set > 0)
+    }
+
+    pub async fn delete<T>(&self, key: &str) -> anyhow::Result<()>
+    where
+        T: Storable,
+    {
+        let _timer = redis_timer("del");
+        let mut conn = self.client.get_async_connection().await?;
+        conn.del::<_, ()>(format!("{}__{}", T::key_prefix(), key))
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the keys of every `T` matching the glob `pattern`, without the type prefix.
+    pub async fn keys<T>(&self, pattern: &str) -> anyhow::Result<Vec<String>>
+    where
+        T: Storable,
+    {
+        let _timer = redis_timer("scan");
+        let mut conn = self.client.get_async_connection().await?;
+        let prefix = format!("{}__", T::key_prefix());
+        let mut iter = conn
+            .scan_match::<_, String>(format!("{}{}", prefix, pattern))
+            .await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key[prefix.len()..].to_owned());
+        }
+        Ok(keys)
+    }
+
+    /// Prepends `data` to the list `key`, keeping at most `max_len` entries.
+    pub async fn push<T>(&self, key: &str, data: &T, max_len: isize) -> anyhow::Result<()>
+    where
+        T: Serialize + Storable,
+    {
+        let _timer = redis_timer("lpush");
         let mut conn = self.client.get_async_connection().await?;
         let key = format!("{}__{}", T::key_prefix(), key);
-        let data: Vec<u8> = conn.get(key.clone()).await?;
-        conn.del(key).await?;
-        Ok(serde_json::from_slice(&data)?)
+        redis::pipe()
+            .lpush(&key, serde_json::to_string(data)?)
+            .ignore()
+            .ltrim(&key, 0, max_len - 1)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
     }
+
+    /// Reads the first `count` entries of the list `key`, skipping any that fail to deserialize.
+    pub async fn range<T>(&self, key: &str, count: isize) -> anyhow::Result<Vec<T>>
+    where
+        T: DeserializeOwned + Storable,
+    {
+        let _timer = redis_timer("lrange");
+        let mut conn = self.client.get_async_connection().await?;
+        let data: Vec<Vec<u8>> = conn
+            .lrange(format!("{}__{}", T::key_prefix(), key), 0, count - 1)
+            .await?;
+        Ok(data
+            .iter()
+            .filter_map(|d| serde_json::from_slice(d).ok())
+            .collect())
+    }
+
+    pub async fn publish<T>(&self, channel: &str, data: &T) -> anyhow::Result<()>
+    where
+        T: Serialize,
+    {
+        let _timer = redis_timer("publish");
+        let mut conn = self.client.get_async_connection().await?;
+        conn.publish::<_, _, ()>(channel, serde_json::to_string(data)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribes to `channel` and forwards every message that deserializes as `T`.
+    pub async fn subscribe<T>(&self, channel: &str) -> anyhow::Result<mpsc::Receiver<T>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let _timer = redis_timer("subscribe");
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(channel).await?;
+        let (tx, rx) = mpsc::channel(1024);
+        tokio::spawn(async move {
+            let mut messages = pubsub.into_on_message();
+            while let Some(msg) = messages.next().await {
+                if let Ok(data) = serde_json::from_slice(msg.get_payload_bytes()) {
+                    if tx.send(data).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+/// Escapes glob characters so `value` only matches itself in [RedisClient::keys].
+pub fn escape_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 pub trait Storable {
     fn key_prefix() -> &'static str;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Ticket {
+        id: u32,
+    }
+
+    impl Storable for Ticket {
+        fn key_prefix() -> &'static str {
+            "test_ticket"
+        }
+    }
+
+    fn client() -> RedisClient {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_owned());
+        RedisClient::new(&url).unwrap()
+    }
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+        assert_eq!(escape_pattern("plain"), "plain");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server, set REDIS_URL"]
+    async fn test_take() {
+        let redis = client();
+        let key = uuid_like();
+        redis.set(&key, &Ticket { id: 7 }).await.unwrap();
+        assert_eq!(
+            redis.peek::<Ticket>(&key).await.unwrap(),
+            Some(Ticket { id: 7 })
+        );
+        assert_eq!(
+            redis.take::<Ticket>(&key).await.unwrap(),
+            Some(Ticket { id: 7 })
+        );
+        assert_eq!(redis.take::<Ticket>(&key).await.unwrap(), None);
+        assert!(redis.get::<Ticket>(&key).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server, set REDIS_URL"]
+    async fn test_take_once() {
+        let redis = std::sync::Arc::new(client());
+        let key = uuid_like();
+        redis.set(&key, &Ticket { id: 1 }).await.unwrap();
+        let takers: Vec<_> = (0..8)
+            .map(|_| {
+                let redis = redis.clone();
+                let key = key.clone();
+                tokio::spawn(async move { redis.take::<Ticket>(&key).await.unwrap() })
+            })
+            .collect();
+        let mut taken = 0;
+        for taker in takers {
+            taken += taker.await.unwrap().is_some() as u32;
+        }
+        assert_eq!(taken, 1);
+    }
+
+    fn uuid_like() -> String {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("{}_{}", std::process::id(), nanos)
+    }
+}
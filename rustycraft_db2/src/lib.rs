@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate log;
+
+mod record;
+pub mod tables;
+pub mod wdc3;
+
+pub use record::Record;
+pub use wdc3::Db2File;
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A row type of one client table.
+pub trait Db2Record: Sized {
+    /// File name inside `DBFilesClient`.
+    const FILE_NAME: &'static str;
+
+    fn read(record: &mut Record) -> anyhow::Result<Self>;
+}
+
+/// Every record of one table, keyed by id.
+pub struct Db2Store<T> {
+    records: BTreeMap<u32, T>,
+}
+
+impl<T: Db2Record> Db2Store<T> {
+    /// Loads `T::FILE_NAME` from a `DBFilesClient` directory extracted from the client.
+    pub fn load(dir: &Path) -> anyhow::Result<Db2Store<T>> {
+        let path = dir.join(T::FILE_NAME);
+        let data = std::fs::read(&path).with_context(|| format!("Reading {:?}", path))?;
+        let file = Db2File::parse(data).with_context(|| format!("Parsing {:?}", path))?;
+        let store = Db2Store::from_file(&file).with_context(|| format!("Reading {:?}", path))?;
+        info!(target: "Db2", "Loaded {} records from {}", store.len(), T::FILE_NAME);
+        Ok(store)
+    }
+
+    pub fn from_file(file: &Db2File) -> anyhow::Result<Db2Store<T>> {
+        let mut records = BTreeMap::new();
+        for mut record in file.records() {
+            let id = record.id();
+            records.insert(
+                id,
+                T::read(&mut record).with_context(|| format!("Record {}", id))?,
+            );
+        }
+        Ok(Db2Store { records })
+    }
+
+    pub fn get(&self, id: u32) -> Option<&T> {
+        self.records.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &T)> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
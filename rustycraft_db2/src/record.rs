@@ -0,0 +1,273 @@
+use crate::wdc3::{
+    Db2File, FieldStorageInfo, Location, RecordEntry, StorageType, FLAG_HAS_NON_INLINE_IDS,
+};
+use anyhow::{anyhow, bail};
+
+fn read_bits(data: &[u8], offset: usize, bits: usize) -> anyhow::Result<u64> {
+    let first = offset / 8;
+    let last = (offset + bits).div_ceil(8);
+    let bytes = data
+        .get(first..last)
+        .ok_or_else(|| anyhow!("Field at bit {} is out of the record", offset))?;
+    let value = bytes
+        .iter()
+        .rev()
+        .fold(0u128, |value, byte| (value << 8) | *byte as u128);
+    let mask = if bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << bits) - 1
+    };
+    Ok((value >> (offset % 8)) as u64 & mask)
+}
+
+/// One record of a [`Db2File`], read field after field.
+///
+/// Fixed size records may skip any field. Sparse records store strings inline, so string fields
+/// have to be skipped with [`Record::skip_string`].
+pub struct Record<'a> {
+    file: &'a Db2File,
+    entry: RecordEntry,
+    field: usize,
+    /// Byte cursor of sparse records.
+    position: usize,
+}
+
+impl Db2File {
+    pub(crate) fn record_at(&self, index: usize) -> Record<'_> {
+        let entry = self.records[index];
+        Record {
+            file: self,
+            entry,
+            field: 0,
+            position: match entry.location {
+                Location::Fixed { offset, .. } => offset,
+                Location::Sparse { offset, .. } => offset,
+            },
+        }
+    }
+
+    pub fn record(&self, id: u32) -> Option<Record<'_>> {
+        self.index.get(&id).map(|index| self.record_at(*index))
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = Record<'_>> {
+        (0..self.records.len()).map(|index| self.record_at(index))
+    }
+}
+
+impl<'a> Record<'a> {
+    pub fn id(&self) -> u32 {
+        self.entry.id
+    }
+
+    pub fn parent_id(&self) -> Option<u32> {
+        self.entry.parent_id
+    }
+
+    /// The id field of files without an id list.
+    pub(crate) fn inline_id(&self) -> anyhow::Result<u32> {
+        let mut record = Record {
+            field: self.file.header.id_index as usize,
+            ..*self
+        };
+        if let Location::Sparse { .. } = self.entry.location {
+            bail!("Sparse records need an id list");
+        }
+        Ok(record.value(1, 0)? as u32)
+    }
+
+    fn next_field(&mut self) -> anyhow::Result<usize> {
+        let field = self.field;
+        if field >= self.file.fields.len() {
+            bail!("Record {} has only {} fields", self.entry.id, field);
+        }
+        self.field += 1;
+        Ok(field)
+    }
+
+    /// Bits of one element of `field`, as sparse records store it.
+    fn field_bits(&self, field: usize) -> anyhow::Result<usize> {
+        let size = self
+            .file
+            .fields
+            .get(field)
+            .ok_or_else(|| anyhow!("Record {} has only {} fields", self.entry.id, field))?
+            .size;
+        match 32 - i32::from(size) {
+            bits @ 0..=64 => Ok(bits as usize),
+            bits => bail!("Field {} has a size of {} bits", field, bits),
+        }
+    }
+
+    fn storage_info(&self, field: usize) -> anyhow::Result<&'a FieldStorageInfo> {
+        self.file
+            .storage
+            .get(field)
+            .ok_or_else(|| anyhow!("Field {} has no storage info", field))
+    }
+
+    /// Element `index` of the current field, an array of `count` elements.
+    fn value(&mut self, count: usize, index: usize) -> anyhow::Result<u64> {
+        let field = self.field;
+        let offset = match self.entry.location {
+            Location::Fixed { offset, .. } => offset,
+            Location::Sparse { offset, size } => {
+                let bits = self.field_bits(field)?;
+                let bytes = bits / 8;
+                let start = self.position + index * bytes;
+                let data = self
+                    .file
+                    .data
+                    .get(start..start + bytes)
+                    .filter(|_| start + bytes <= offset + size)
+                    .ok_or_else(|| anyhow!("Field {} is out of record {}", field, self.entry.id))?;
+                return read_bits(data, 0, bits);
+            }
+        };
+        let info = self.storage_info(field)?;
+        let record = self
+            .file
+            .data
+            .get(offset..offset + self.file.header.record_size as usize)
+            .ok_or_else(|| anyhow!("Record {} is out of the file", self.entry.id))?;
+        let start = info.offset_bits as usize;
+        Ok(match info.storage_type {
+            StorageType::None => {
+                let bits = info.size_bits as usize / count;
+                read_bits(record, start + index * bits, bits)?
+            }
+            StorageType::Bitpacked => read_bits(record, start, info.size_bits as usize)?,
+            StorageType::BitpackedSigned => {
+                let bits = info.size_bits as usize;
+                let value = read_bits(record, start, bits)?;
+                let shift = 64 - bits;
+                ((value << shift) as i64 >> shift) as u64
+            }
+            StorageType::CommonData => self
+                .file
+                .common
+                .get(field)
+                .and_then(|common| common.get(&self.entry.id))
+                .copied()
+                .unwrap_or(info.value1) as u64,
+            StorageType::BitpackedIndexed => {
+                let pallet_index = read_bits(record, start, info.size_bits as usize)? as usize;
+                self.pallet(field, pallet_index)? as u64
+            }
+            StorageType::BitpackedIndexedArray => {
+                let pallet_index = read_bits(record, start, info.size_bits as usize)? as usize;
+                self.pallet(field, pallet_index * info.value3 as usize + index)? as u64
+            }
+        })
+    }
+
+    fn pallet(&self, field: usize, index: usize) -> anyhow::Result<u32> {
+        self.file
+            .pallets
+            .get(field)
+            .and_then(|pallet| pallet.get(index))
+            .copied()
+            .ok_or_else(|| anyhow!("Pallet index {} of field {} is out of range", index, field))
+    }
+
+    /// Steps over the id when the file keeps it in the record, typed records never list it.
+    fn skip_inline_id(&mut self) -> anyhow::Result<()> {
+        let header = &self.file.header;
+        if header.flags & FLAG_HAS_NON_INLINE_IDS == 0 && self.field == header.id_index as usize {
+            if let Location::Sparse { .. } = self.entry.location {
+                self.position += self.field_bits(self.field)? / 8;
+            }
+            self.field += 1;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, count: usize, index: usize) -> anyhow::Result<u64> {
+        if index == 0 {
+            self.skip_inline_id()?;
+        }
+        let value = self.value(count, index)?;
+        if index + 1 == count {
+            if let Location::Sparse { .. } = self.entry.location {
+                self.position += count * self.field_bits(self.field)? / 8;
+            }
+            self.next_field()?;
+        }
+        Ok(value)
+    }
+
+    pub fn read_u64(&mut self) -> anyhow::Result<u64> {
+        self.read(1, 0)
+    }
+
+    /// Narrower integer fields are read the same way, e.g. a `u8` map type.
+    pub fn read_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(self.read(1, 0)? as u32)
+    }
+
+    pub fn read_i32(&mut self) -> anyhow::Result<i32> {
+        Ok(self.read(1, 0)? as u32 as i32)
+    }
+
+    pub fn read_f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_bits(self.read(1, 0)? as u32))
+    }
+
+    /// An array field of `N` elements.
+    pub fn read_u32_array<const N: usize>(&mut self) -> anyhow::Result<[u32; N]> {
+        let mut values = [0; N];
+        for (index, value) in values.iter_mut().enumerate() {
+            *value = self.read(N, index)? as u32;
+        }
+        Ok(values)
+    }
+
+    pub fn read_f32_array<const N: usize>(&mut self) -> anyhow::Result<[f32; N]> {
+        Ok(self.read_u32_array::<N>()?.map(f32::from_bits))
+    }
+
+    pub fn read_string(&mut self) -> anyhow::Result<String> {
+        self.skip_inline_id()?;
+        let (bytes, start) = match self.entry.location {
+            Location::Sparse { .. } => (&self.file.data[..], self.position),
+            Location::Fixed { index, .. } => {
+                let field_offset = self.storage_info(self.field)?.offset_bits as usize / 8;
+                let value = self.value(1, 0)? as usize;
+                let record_size = self.file.header.record_size as usize;
+                // String offsets are relative to the field, as if every section's records
+                // preceded the string tables.
+                let start = (index * record_size + field_offset + value)
+                    .checked_sub(self.file.header.record_count as usize * record_size)
+                    .ok_or_else(|| anyhow!("Bad string offset in record {}", self.entry.id))?;
+                (&self.file.strings[..], start)
+            }
+        };
+        let string = bytes
+            .get(start..)
+            .and_then(|rest| rest.split(|byte| *byte == 0).next())
+            .ok_or_else(|| anyhow!("Bad string offset in record {}", self.entry.id))?;
+        if let Location::Sparse { .. } = self.entry.location {
+            self.position += string.len() + 1;
+        }
+        self.next_field()?;
+        Ok(String::from_utf8_lossy(string).into_owned())
+    }
+
+    /// Skips `count` numeric fields.
+    pub fn skip(&mut self, count: usize) -> anyhow::Result<&mut Self> {
+        for _ in 0..count {
+            self.skip_inline_id()?;
+            if let Location::Sparse { .. } = self.entry.location {
+                self.position += self.field_bits(self.field)? / 8;
+            }
+            self.next_field()?;
+        }
+        Ok(self)
+    }
+
+    pub fn skip_string(&mut self) -> anyhow::Result<&mut Self> {
+        self.read_string()?;
+        Ok(self)
+    }
+}
@@ -0,0 +1,150 @@
+//! Typed rows of the core tables, laid out as in 9.2.0.43206.
+//!
+//! Only the leading columns are read, the remaining ones are left for when they are needed.
+
+use crate::{Db2Record, Record};
+use rustycraft_protocol::classes::Classes;
+use rustycraft_protocol::races::Races;
+
+#[derive(Debug, Clone)]
+pub struct Map {
+    pub id: u32,
+    pub directory: String,
+    pub name: String,
+    pub corpse: [f32; 2],
+    pub map_type: u8,
+    pub instance_type: i8,
+    pub expansion_id: u8,
+    pub area_table_id: u16,
+    pub loading_screen_id: i16,
+    pub time_of_day_override: i16,
+    pub parent_map_id: i16,
+    pub cosmetic_parent_map_id: i16,
+}
+
+impl Db2Record for Map {
+    const FILE_NAME: &'static str = "Map.db2";
+
+    fn read(record: &mut Record) -> anyhow::Result<Self> {
+        Ok(Map {
+            id: record.id(),
+            directory: record.read_string()?,
+            name: record.read_string()?,
+            corpse: record
+                .skip_string()?
+                .skip_string()?
+                .skip_string()?
+                .skip_string()?
+                .read_f32_array()?,
+            map_type: record.read_u32()? as u8,
+            instance_type: record.read_u32()? as i8,
+            expansion_id: record.read_u32()? as u8,
+            area_table_id: record.read_u32()? as u16,
+            loading_screen_id: record.read_u32()? as i16,
+            time_of_day_override: record.read_u32()? as i16,
+            parent_map_id: record.read_u32()? as i16,
+            cosmetic_parent_map_id: record.read_u32()? as i16,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChrRaces {
+    pub id: u32,
+    pub client_prefix: String,
+    pub client_file_string: String,
+    pub name: String,
+    pub name_female: String,
+}
+
+impl ChrRaces {
+    /// `None` for NPC races.
+    pub fn race(&self) -> Option<Races> {
+        Races::try_from(self.id as u8).ok()
+    }
+}
+
+impl Db2Record for ChrRaces {
+    const FILE_NAME: &'static str = "ChrRaces.db2";
+
+    fn read(record: &mut Record) -> anyhow::Result<Self> {
+        Ok(ChrRaces {
+            id: record.id(),
+            client_prefix: record.read_string()?,
+            client_file_string: record.read_string()?,
+            name: record.read_string()?,
+            name_female: record.read_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChrClasses {
+    pub id: u32,
+    pub name: String,
+    pub filename: String,
+    pub name_male: String,
+    pub name_female: String,
+}
+
+impl ChrClasses {
+    pub fn class(&self) -> Option<Classes> {
+        Classes::try_from(self.id as u8).ok()
+    }
+}
+
+impl Db2Record for ChrClasses {
+    const FILE_NAME: &'static str = "ChrClasses.db2";
+
+    fn read(record: &mut Record) -> anyhow::Result<Self> {
+        Ok(ChrClasses {
+            id: record.id(),
+            name: record.read_string()?,
+            filename: record.read_string()?,
+            name_male: record.read_string()?,
+            name_female: record.read_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub id: u32,
+    pub class_id: u8,
+    pub subclass_id: u8,
+    pub material: u8,
+    pub inventory_type: i8,
+    pub sheathe_type: u8,
+}
+
+impl Db2Record for Item {
+    const FILE_NAME: &'static str = "Item.db2";
+
+    fn read(record: &mut Record) -> anyhow::Result<Self> {
+        Ok(Item {
+            id: record.id(),
+            class_id: record.read_u32()? as u8,
+            subclass_id: record.read_u32()? as u8,
+            material: record.read_u32()? as u8,
+            inventory_type: record.read_u32()? as i8,
+            sheathe_type: record.read_u32()? as u8,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpellName {
+    pub id: u32,
+    pub name: String,
+}
+
+impl Db2Record for SpellName {
+    const FILE_NAME: &'static str = "SpellName.db2";
+
+    fn read(record: &mut Record) -> anyhow::Result<Self> {
+        Ok(SpellName {
+            id: record.id(),
+            name: record.read_string()?,
+        })
+    }
+}
@@ -0,0 +1,465 @@
+//! Layout of `WDC3` files, the format of client `.db2` files since 8.1.
+
+use anyhow::{anyhow, bail};
+use deku::prelude::*;
+use std::collections::HashMap;
+
+pub const MAGIC: [u8; 4] = *b"WDC3";
+/// Records are variable sized with inline strings, located through the offset map.
+const FLAG_HAS_OFFSET_MAP: u16 = 0x01;
+/// Ids come from the id lists instead of the `id_index` field of the record.
+pub(crate) const FLAG_HAS_NON_INLINE_IDS: u16 = 0x04;
+
+#[derive(Debug, DekuRead)]
+#[deku(endian = "little")]
+pub struct Header {
+    #[deku(assert_eq = "MAGIC")]
+    pub magic: [u8; 4],
+    pub record_count: u32,
+    pub field_count: u32,
+    pub record_size: u32,
+    pub string_table_size: u32,
+    pub table_hash: u32,
+    pub layout_hash: u32,
+    pub min_id: u32,
+    pub max_id: u32,
+    pub locale: u32,
+    pub flags: u16,
+    pub id_index: u16,
+    pub total_field_count: u32,
+    pub bitpacked_data_offset: u32,
+    pub lookup_column_count: u32,
+    pub field_storage_info_size: u32,
+    pub common_data_size: u32,
+    pub pallet_data_size: u32,
+    pub section_count: u32,
+}
+
+#[derive(Debug, DekuRead)]
+#[deku(endian = "little")]
+pub struct SectionHeader {
+    /// Non zero when the section is encrypted with this TACT key.
+    pub tact_key_hash: u64,
+    pub file_offset: u32,
+    pub record_count: u32,
+    pub string_table_size: u32,
+    /// End of the variable sized records, only set with an offset map.
+    pub offset_records_end: u32,
+    pub id_list_size: u32,
+    pub relationship_data_size: u32,
+    pub offset_map_id_count: u32,
+    pub copy_table_count: u32,
+}
+
+#[derive(Debug, DekuRead)]
+#[deku(endian = "little")]
+pub struct FieldStructure {
+    /// `32 - size in bits`.
+    pub size: i16,
+    pub offset: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DekuRead)]
+#[deku(type = "u32", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum StorageType {
+    None = 0,
+    Bitpacked = 1,
+    CommonData = 2,
+    BitpackedIndexed = 3,
+    BitpackedIndexedArray = 4,
+    BitpackedSigned = 5,
+}
+
+#[derive(Debug, DekuRead)]
+#[deku(endian = "little")]
+pub struct FieldStorageInfo {
+    pub offset_bits: u16,
+    pub size_bits: u16,
+    /// Bytes of pallet or common data owned by the field.
+    pub additional_data_size: u32,
+    pub storage_type: StorageType,
+    /// Default value of common data fields, bit offset of bitpacked ones.
+    pub value1: u32,
+    pub value2: u32,
+    /// Element count of `BitpackedIndexedArray` fields.
+    pub value3: u32,
+}
+
+/// Where the bytes of one record are.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Location {
+    /// Fixed size record, `index` counts the records of every previous section.
+    Fixed { offset: usize, index: usize },
+    /// Variable sized record with inline strings.
+    Sparse { offset: usize, size: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RecordEntry {
+    pub id: u32,
+    pub location: Location,
+    /// Foreign key stored in the relationship data, e.g. the map of a map difficulty.
+    pub parent_id: Option<u32>,
+}
+
+/// A parsed `.db2` file. Records are decoded lazily, see [`crate::Record`].
+pub struct Db2File {
+    pub(crate) header: Header,
+    pub(crate) data: Vec<u8>,
+    pub(crate) fields: Vec<FieldStructure>,
+    pub(crate) storage: Vec<FieldStorageInfo>,
+    /// Pallet values of every indexed field.
+    pub(crate) pallets: Vec<Vec<u32>>,
+    /// Common data of every common field, keyed by record id.
+    pub(crate) common: Vec<HashMap<u32, u32>>,
+    /// String tables of every section, one after the other.
+    pub(crate) strings: Vec<u8>,
+    pub(crate) records: Vec<RecordEntry>,
+    pub(crate) index: HashMap<u32, usize>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, size: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.position + size;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or_else(|| anyhow!("Unexpected end of file at {}", self.position))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_list(&mut self, count: usize) -> anyhow::Result<Vec<u32>> {
+        (0..count).map(|_| self.u32()).collect()
+    }
+
+    fn structs<T>(&mut self, count: usize, size: usize) -> anyhow::Result<Vec<T>>
+    where
+        T: for<'b> DekuContainerRead<'b>,
+    {
+        (0..count)
+            .map(|_| Ok(T::from_bytes((self.bytes(size)?, 0))?.1))
+            .collect()
+    }
+}
+
+/// Counts come from the file, a corrupt one must not reserve more than its own size.
+fn capacity(count: u32, data: &[u8]) -> usize {
+    (count as usize).min(data.len())
+}
+
+impl Db2File {
+    pub fn parse(data: Vec<u8>) -> anyhow::Result<Db2File> {
+        const HEADER_SIZE: usize = 72;
+        const SECTION_HEADER_SIZE: usize = 40;
+        const FIELD_STORAGE_INFO_SIZE: usize = 24;
+
+        let mut reader = Reader {
+            data: &data,
+            position: 0,
+        };
+        let header = Header::from_bytes((reader.bytes(HEADER_SIZE)?, 0))
+            .map_err(|e| anyhow!("Not a WDC3 file: {}", e))?
+            .1;
+        let sections: Vec<SectionHeader> =
+            reader.structs(header.section_count as usize, SECTION_HEADER_SIZE)?;
+        let fields: Vec<FieldStructure> = reader.structs(header.total_field_count as usize, 4)?;
+        let storage: Vec<FieldStorageInfo> = reader.structs(
+            header.field_storage_info_size as usize / FIELD_STORAGE_INFO_SIZE,
+            FIELD_STORAGE_INFO_SIZE,
+        )?;
+
+        let mut pallets = Vec::with_capacity(storage.len());
+        for info in &storage {
+            pallets.push(match info.storage_type {
+                StorageType::BitpackedIndexed | StorageType::BitpackedIndexedArray => {
+                    reader.u32_list(info.additional_data_size as usize / 4)?
+                }
+                _ => Vec::new(),
+            });
+        }
+        let mut common = Vec::with_capacity(storage.len());
+        for info in &storage {
+            let mut values = HashMap::new();
+            if info.storage_type == StorageType::CommonData {
+                for _ in 0..info.additional_data_size / 8 {
+                    values.insert(reader.u32()?, reader.u32()?);
+                }
+            }
+            common.push(values);
+        }
+
+        let sparse = header.flags & FLAG_HAS_OFFSET_MAP != 0;
+        let mut strings = Vec::new();
+        let mut records = Vec::with_capacity(capacity(header.record_count, &data));
+        let mut copies = Vec::new();
+        let mut section_index = 0;
+        for section in &sections {
+            let mut reader = Reader {
+                data: &data,
+                position: section.file_offset as usize,
+            };
+            let mut locations = Vec::with_capacity(capacity(section.record_count, &data));
+            if sparse {
+                reader.position = section.offset_records_end as usize;
+            } else {
+                for i in 0..section.record_count as usize {
+                    locations.push(Location::Fixed {
+                        offset: reader.position + i * header.record_size as usize,
+                        index: section_index + i,
+                    });
+                }
+                reader.bytes(section.record_count as usize * header.record_size as usize)?;
+                strings.extend_from_slice(reader.bytes(section.string_table_size as usize)?);
+            }
+            section_index += section.record_count as usize;
+
+            let ids = reader.u32_list(section.id_list_size as usize / 4)?;
+            for _ in 0..section.copy_table_count {
+                copies.push((reader.u32()?, reader.u32()?));
+            }
+            let mut offsets = Vec::with_capacity(capacity(section.offset_map_id_count, &data));
+            for _ in 0..section.offset_map_id_count {
+                let offset = reader.u32()? as usize;
+                let size = reader.u16()? as usize;
+                offsets.push(Location::Sparse { offset, size });
+            }
+            let mut parents = HashMap::new();
+            if section.relationship_data_size > 0 {
+                let count = reader.u32()?;
+                // min and max id
+                reader.bytes(8)?;
+                for _ in 0..count {
+                    let parent_id = reader.u32()?;
+                    parents.insert(reader.u32()? as usize, parent_id);
+                }
+            }
+            let sparse_ids = reader.u32_list(offsets.len())?;
+
+            if section.tact_key_hash != 0 && ids.iter().all(|id| *id == 0) {
+                debug!(target: "Db2", "Skipping section encrypted with key {:016X}", section.tact_key_hash);
+                continue;
+            }
+            if sparse {
+                locations = offsets;
+            }
+            for (i, location) in locations.into_iter().enumerate() {
+                let id = match (ids.get(i), sparse_ids.get(i)) {
+                    (Some(id), _) | (None, Some(id)) => *id,
+                    (None, None) if header.flags & FLAG_HAS_NON_INLINE_IDS == 0 => 0,
+                    (None, None) => bail!("Record {} has no id", i),
+                };
+                records.push(RecordEntry {
+                    id,
+                    location,
+                    parent_id: parents.get(&i).copied(),
+                });
+            }
+        }
+
+        let mut file = Db2File {
+            header,
+            data,
+            fields,
+            storage,
+            pallets,
+            common,
+            strings,
+            records,
+            index: HashMap::new(),
+        };
+        for i in 0..file.records.len() {
+            if file.records[i].id == 0 {
+                let id = file.record_at(i).inline_id()?;
+                file.records[i].id = id;
+            }
+            file.index.insert(file.records[i].id, i);
+        }
+        for (id, source) in copies {
+            let source = *file
+                .index
+                .get(&source)
+                .ok_or_else(|| anyhow!("Copy of unknown record {}", source))?;
+            let entry = RecordEntry {
+                id,
+                ..file.records[source]
+            };
+            file.index.insert(id, file.records.len());
+            file.records.push(entry);
+        }
+        Ok(file)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Db2Record, Db2Store, Record};
+
+    #[derive(Debug, PartialEq)]
+    struct Row {
+        id: u32,
+        name: String,
+        small: u16,
+        packed: u32,
+        common: u32,
+        pallet: u32,
+    }
+
+    impl Db2Record for Row {
+        const FILE_NAME: &'static str = "Test.db2";
+
+        fn read(record: &mut Record) -> anyhow::Result<Self> {
+            Ok(Row {
+                id: record.id(),
+                name: record.read_string()?,
+                small: record.read_u32()? as u16,
+                packed: record.read_u32()?,
+                common: record.read_u32()?,
+                pallet: record.read_u32()?,
+            })
+        }
+    }
+
+    fn put(out: &mut Vec<u8>, values: &[u32]) {
+        for value in values {
+            out.extend(value.to_le_bytes());
+        }
+    }
+
+    /// Two records with ids 10 and 20, plus 30 copied from 10.
+    fn build_file() -> Vec<u8> {
+        const RECORD_SIZE: u32 = 8;
+        let strings = b"Alpha\0Beta\0";
+        let pallet = [100, 200, 300];
+        let field_storage_info_size = 5 * 24;
+        let file_offset = 72 + 40 + 5 * 4 + field_storage_info_size + 12 + 8;
+
+        let mut out = MAGIC.to_vec();
+        put(
+            &mut out,
+            &[2, 5, RECORD_SIZE, strings.len() as u32, 0, 0, 10, 30, 0],
+        );
+        out.extend(FLAG_HAS_NON_INLINE_IDS.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        put(&mut out, &[5, 0, 0, field_storage_info_size, 8, 12, 1]);
+
+        out.extend(0u64.to_le_bytes());
+        put(
+            &mut out,
+            &[file_offset, 2, strings.len() as u32, 0, 8, 0, 0, 1],
+        );
+        for (size, offset) in [(0, 0), (16, 4), (27, 6), (32, 7), (30, 7)] {
+            out.extend((size as i16).to_le_bytes());
+            out.extend((offset as u16).to_le_bytes());
+        }
+        out.extend(0u16.to_le_bytes());
+        out.extend(32u16.to_le_bytes());
+        put(&mut out, &[0, 0, 0, 0, 0]);
+        out.extend(32u16.to_le_bytes());
+        out.extend(16u16.to_le_bytes());
+        put(&mut out, &[0, 0, 0, 0, 0]);
+        out.extend(48u16.to_le_bytes());
+        out.extend(5u16.to_le_bytes());
+        put(&mut out, &[0, 1, 48, 5, 0]);
+        out.extend(0u16.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        put(&mut out, &[8, 2, 7, 0, 0]);
+        out.extend(53u16.to_le_bytes());
+        out.extend(2u16.to_le_bytes());
+        put(&mut out, &[12, 3, 53, 2, 0]);
+        put(&mut out, &pallet);
+        put(&mut out, &[20, 99]);
+        assert_eq!(out.len(), file_offset as usize);
+
+        // String offsets are relative to the field, as if the strings followed every record.
+        for (index, (string_offset, small, packed, pallet_index)) in
+            [(0u64, 1000u64, 17u64, 2u64), (6, 2000, 3, 0)]
+                .into_iter()
+                .enumerate()
+        {
+            let name = string_offset + 2 * RECORD_SIZE as u64 - index as u64 * RECORD_SIZE as u64;
+            let record = name | small << 32 | packed << 48 | pallet_index << 53;
+            out.extend(record.to_le_bytes());
+        }
+        out.extend(strings);
+        put(&mut out, &[10, 20, 30, 10]);
+        out
+    }
+
+    #[test]
+    fn test_read_records() {
+        let file = Db2File::parse(build_file()).unwrap();
+        assert_eq!(file.len(), 3);
+        let store = Db2Store::<Row>::from_file(&file).unwrap();
+        assert_eq!(
+            store.get(10),
+            Some(&Row {
+                id: 10,
+                name: "Alpha".to_string(),
+                small: 1000,
+                packed: 17,
+                common: 7,
+                pallet: 300,
+            })
+        );
+        assert_eq!(
+            store.get(20),
+            Some(&Row {
+                id: 20,
+                name: "Beta".to_string(),
+                small: 2000,
+                packed: 3,
+                common: 99,
+                pallet: 100,
+            })
+        );
+        assert_eq!(store.get(30).unwrap().name, "Alpha");
+        assert!(Db2File::parse(b"WDC2".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_malformed_file() {
+        let data = build_file();
+        for len in [data.len() - 20, data.len() / 2, 80] {
+            assert!(Db2File::parse(data[..len].to_vec()).is_err());
+        }
+
+        // A record count far larger than the file must not be reserved up front.
+        let mut data = build_file();
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Db2File::parse(data).is_ok());
+
+        let file = Db2File::parse(build_file()).unwrap();
+        let mut record = file.record(10).unwrap();
+        record.skip(5).unwrap();
+        assert!(record.read_u32().is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{Headers, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Router, Server};
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::fmt::Debug;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+
+lazy_static! {
+    pub static ref SESSIONS: IntGaugeVec = register_int_gauge_vec!(
+        "rustycraft_sessions",
+        "Connected client sessions.",
+        &["server"]
+    )
+    .unwrap();
+    pub static ref RPC_CALLS: IntCounterVec = register_int_counter_vec!(
+        "rustycraft_rpc_calls_total",
+        "Battle.net RPC calls handled.",
+        &["service", "method"]
+    )
+    .unwrap();
+    pub static ref RPC_DURATION: HistogramVec = register_histogram_vec!(
+        "rustycraft_rpc_duration_seconds",
+        "Battle.net RPC handling time.",
+        &["service", "method"]
+    )
+    .unwrap();
+    pub static ref RPC_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "rustycraft_rpc_errors_total",
+        "Battle.net RPC calls answered with a WowRpcResponse error.",
+        &["service", "method", "status"]
+    )
+    .unwrap();
+    pub static ref WORLD_PACKETS: IntCounterVec = register_int_counter_vec!(
+        "rustycraft_world_packets_total",
+        "World packets by opcode. `direction` is `client` or `server`.",
+        &["direction", "opcode"]
+    )
+    .unwrap();
+    pub static ref HANDSHAKE_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "rustycraft_handshake_failures_total",
+        "Connections dropped before the session was established.",
+        &["server"]
+    )
+    .unwrap();
+    pub static ref REDIS_DURATION: HistogramVec = register_histogram_vec!(
+        "rustycraft_redis_duration_seconds",
+        "Redis command latency, including connection setup.",
+        &["command"],
+        vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap();
+}
+
+/// Records one generated `dispatch` call.
+pub fn observe_rpc<T, E: Debug>(
+    service: &str,
+    method: &str,
+    started: Instant,
+    result: &Result<T, E>,
+) {
+    RPC_CALLS.with_label_values(&[service, method]).inc();
+    RPC_DURATION
+        .with_label_values(&[service, method])
+        .observe(started.elapsed().as_secs_f64());
+    if let Err(e) = result {
+        RPC_ERRORS
+            .with_label_values(&[service, method, &format!("{:?}", e)])
+            .inc();
+    }
+}
+
+pub fn redis_timer(command: &str) -> HistogramTimer {
+    REDIS_DURATION.with_label_values(&[command]).start_timer()
+}
+
+/// Binds `bind_address` and returns the server of `GET /metrics`, the registry in the Prometheus
+/// text format, which runs until `shutdown` completes.
+pub fn serve(
+    bind_address: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<impl Future<Output = ()>> {
+    let server = Server::try_bind(&bind_address)?
+        .serve(router().into_make_service())
+        .with_graceful_shutdown(shutdown);
+    info!(target: "Metrics", "Serving metrics on: {}", bind_address);
+    Ok(async move {
+        if let Err(e) = server.await {
+            error!(target: "Metrics", "Metrics server failed: {}", e);
+        }
+    })
+}
+
+fn router() -> Router {
+    Router::new().route("/metrics", get(metrics))
+}
+
+async fn metrics() -> Response {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    match encoder.encode(&prometheus::gather(), &mut body) {
+        Ok(()) => {
+            let headers = Headers([(CONTENT_TYPE, encoder.format_type().to_owned())]);
+            (headers, body).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_observe_rpc() {
+        observe_rpc::<(), &str>("TestService", "ok", Instant::now(), &Ok(()));
+        observe_rpc::<(), &str>("TestService", "fail", Instant::now(), &Err("Denied"));
+        assert_eq!(RPC_CALLS.with_label_values(&["TestService", "ok"]).get(), 1);
+        assert_eq!(
+            RPC_ERRORS
+                .with_label_values(&["TestService", "fail", "\"Denied\""])
+                .get(),
+            1
+        );
+        let mut body = Vec::new();
+        TextEncoder::new()
+            .encode(&prometheus::gather(), &mut body)
+            .unwrap();
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains("rustycraft_rpc_calls_total{method=\"ok\",service=\"TestService\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router().oneshot(request("GET", "/metrics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            TextEncoder::new().format_type()
+        );
+        let response = router().oneshot(request("GET", "/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = router().oneshot(request("POST", "/metrics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_serve_bind_failure() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(serve(taken.local_addr().unwrap(), async {}).is_err());
+    }
+}
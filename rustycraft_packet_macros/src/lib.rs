@@ -0,0 +1,231 @@
+//! Binds world packet structs to their opcodes.
+//!
+//! Both attributes expand to items of `rustycraft_world_server`, so they are only meant to be
+//! used inside that crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, ItemStruct, Token};
+
+/// `Opcode` or `Opcode, connection = ConnectionTypeX`.
+struct ServerPacketArgs {
+    opcode: Ident,
+    connection: Option<Ident>,
+}
+
+impl Parse for ServerPacketArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let opcode = input.parse()?;
+        let mut connection = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() {
+            let key: Ident = input.parse()?;
+            if key != "connection" {
+                return Err(syn::Error::new_spanned(key, "expected `connection = ...`"));
+            }
+            input.parse::<Token![=]>()?;
+            connection = Some(input.parse()?);
+        }
+        Ok(ServerPacketArgs { opcode, connection })
+    }
+}
+
+/// `#[server_packet(AuthResponse)]` sends the struct as `OpcodeServer::AuthResponse`.
+///
+/// Length fields with a `#[deku(update = "...")]` attribute are filled by `serialize` right before
+/// the packet is written, so constructors leave them at zero. Call `update()` before a bare
+/// `to_bytes()`.
+///
+/// `#[server_packet(ConnectTo, connection = ConnectionTypeRealm)]` pins the packet to one of
+/// the session connections, see `IntoServerPacket::connection`.
+#[proc_macro_attribute]
+pub fn server_packet(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand_server_packet(attr.into(), item.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// `#[client_packet(Ping)]` registers the struct as the `ClientPacketParser` of
+/// `OpcodeClient::Ping` packets, so `ClientPacket::parse` reads them.
+///
+/// The packet is handled once it has an entry in the `HANDLERS` table of `crate::handlers`,
+/// `OpcodeHandler::packet::<Ping>(state, handler)`, where the session state and handler of
+/// each opcode are picked.
+///
+/// Handlers take the struct back out of the `ClientPacket` with `TryFrom`.
+#[proc_macro_attribute]
+pub fn client_packet(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand_client_packet(attr.into(), item.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_server_packet(attr: TokenStream2, item: TokenStream2) -> syn::Result<TokenStream2> {
+    let ServerPacketArgs { opcode, connection } = syn::parse2(attr)?;
+    let packet: ItemStruct = syn::parse2(item)?;
+    let name = &packet.ident;
+    let (impl_generics, ty_generics, where_clause) = packet.generics.split_for_impl();
+    let connection = connection.map(|connection| {
+        quote! {
+            fn connection(&self) -> crate::opcodes::ConnectionType {
+                crate::opcodes::ConnectionType::#connection
+            }
+        }
+    });
+    Ok(quote! {
+        #packet
+
+        impl #impl_generics crate::packets::IntoServerPacket for #name #ty_generics #where_clause {
+            fn get_opcode(&self) -> crate::opcodes::OpcodeServer {
+                crate::opcodes::OpcodeServer::#opcode
+            }
+
+            #connection
+        }
+    })
+}
+
+fn expand_client_packet(attr: TokenStream2, item: TokenStream2) -> syn::Result<TokenStream2> {
+    let opcode: Ident = syn::parse2(attr)?;
+    let packet: ItemStruct = syn::parse2(item)?;
+    let name = &packet.ident;
+    if !packet.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &packet.generics,
+            "client packets cannot be generic",
+        ));
+    }
+    Ok(quote! {
+        #packet
+
+        impl crate::packets::IntoClientPacket for #name {
+            const OPCODE: crate::opcodes::OpcodeClient = crate::opcodes::OpcodeClient::#opcode;
+        }
+
+        impl std::convert::TryFrom<crate::packets::ClientPacket> for #name {
+            type Error = anyhow::Error;
+
+            fn try_from(packet: crate::packets::ClientPacket) -> anyhow::Result<Self> {
+                packet.take()
+            }
+        }
+
+        inventory::submit! {
+            crate::packets::ClientPacketParser {
+                opcode: crate::opcodes::OpcodeClient::#opcode,
+                parse: |body| {
+                    let (_, packet) = <#name as deku::DekuContainerRead>::from_bytes((body, 0))?;
+                    Ok(crate::packets::ClientPacket::new(packet))
+                },
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::{File, ImplItem, Item};
+
+    fn trait_impls(expanded: TokenStream2) -> Vec<syn::ItemImpl> {
+        let file: File = syn::parse2(expanded).unwrap();
+        assert!(matches!(file.items[0], Item::Struct(_)));
+        file.items
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Impl(item) => Some(item),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn trait_name(item: &syn::ItemImpl) -> String {
+        let (_, path, _) = item.trait_.as_ref().unwrap();
+        path.segments.last().unwrap().ident.to_string()
+    }
+
+    fn method_names(item: &syn::ItemImpl) -> Vec<String> {
+        item.items
+            .iter()
+            .filter_map(|item| match item {
+                ImplItem::Method(method) => Some(method.sig.ident.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_server_packet() {
+        let expanded = expand_server_packet(
+            quote!(Pong),
+            quote!(
+                struct Pong {
+                    serial: u32,
+                }
+            ),
+        )
+        .unwrap();
+        let impls = trait_impls(expanded.clone());
+        assert_eq!(impls.len(), 1);
+        assert_eq!(trait_name(&impls[0]), "IntoServerPacket");
+        assert_eq!(method_names(&impls[0]), ["get_opcode"]);
+        assert!(expanded
+            .to_string()
+            .contains("crate :: opcodes :: OpcodeServer :: Pong"));
+
+        let expanded = expand_server_packet(
+            quote!(ConnectTo, connection = ConnectionTypeRealm),
+            quote!(
+                struct ConnectTo<T> {
+                    key: T,
+                }
+            ),
+        )
+        .unwrap();
+        let impls = trait_impls(expanded.clone());
+        assert_eq!(method_names(&impls[0]), ["get_opcode", "connection"]);
+        assert!(expanded
+            .to_string()
+            .contains("ConnectionType :: ConnectionTypeRealm"));
+
+        assert!(expand_server_packet(
+            quote!(Pong, conection = X),
+            quote!(
+                struct Pong;
+            )
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_client_packet() {
+        let expanded = expand_client_packet(
+            quote!(Ping),
+            quote!(
+                struct Ping {
+                    serial: u32,
+                }
+            ),
+        )
+        .unwrap();
+        let impls = trait_impls(expanded.clone());
+        assert_eq!(impls.len(), 2);
+        assert_eq!(trait_name(&impls[0]), "IntoClientPacket");
+        assert_eq!(trait_name(&impls[1]), "TryFrom");
+        let expanded = expanded.to_string();
+        assert!(expanded.contains("inventory :: submit !"));
+        assert!(expanded.contains("opcode : crate :: opcodes :: OpcodeClient :: Ping"));
+
+        let error = expand_client_packet(
+            quote!(Ping),
+            quote!(
+                struct Ping<T> {
+                    serial: T,
+                }
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "client packets cannot be generic");
+    }
+}
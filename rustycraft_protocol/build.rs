@@ -310,6 +310,7 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
         let mut inputs = Vec::new();
         let mut outputs = Vec::new();
         let mut ids = Vec::new();
+        let mut expects_responses = Vec::new();
 
         let mut ret_rpc_types = Vec::new();
         let mut ret_rpc_names = Vec::new();
@@ -329,6 +330,7 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
             let output: TokenStream = method.output_type.parse().unwrap();
             inputs.push(input.clone());
             outputs.push(output.clone());
+            expects_responses.push(!method.output_proto_type.ends_with(".NoResponse"));
             ret_rpc_rets.push(quote::format_ident!(
                 "{}",
                 method
@@ -352,6 +354,7 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
             };
         }
         let service_name = quote::format_ident!("{}", service.name);
+        let client_name = quote::format_ident!("{}Client", service.name);
 
         let quoted = quote::quote! {
             #[async_trait::async_trait]
@@ -360,31 +363,79 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 const ORIGINAL_HASH: u32 = #original_hash;
                 # ( const #fields: #types = #values; ) *
                 # ( const #methods_consts: u8 = #ids; ) *
-                # ( async fn #methods(&mut self, _: #inputs) -> Result<#outputs, crate::rpc_responses::WowRpcResponse> {Err(crate::rpc_responses::WowRpcResponse::NotImplemented)} ) *
+                # ( async fn #methods(&mut self, _: #inputs) -> Result<#outputs, crate::rpc_responses::WowRpcResponse> {Err(crate::rpc_responses::WowRpcResponse::RpcNotImplemented)} ) *
+
+                fn route() -> crate::router::Route<Self> where Self: Sized + Send {
+                    crate::router::Route {
+                        name: stringify!(#service_name),
+                        original_hash: #original_hash,
+                        name_hash: #name_hash,
+                        dispatch: |target, msg| <Self as #service_name>::dispatch(target, msg),
+                    }
+                }
 
                 async fn dispatch(&mut self, msg: crate::messages::RawMessage) -> Result<bytes::Bytes, crate::rpc_responses::WowRpcResponse> {
                     use prost::Message;
                     let method_id = msg.headers.method_id.ok_or_else(|| crate::rpc_responses::WowRpcResponse::RpcMalformedRequest )? as u8;
                     match method_id {
                       # ( #ids => {
-                            let parsed = if let Some(0) = msg.headers.size
-                                {<#inputs>::default()}
-                                else
-                                {<#inputs>::decode(&mut msg.data.clone())?};
-                            log::debug!(target: stringify!(#service_name), "[{:?}] Method `{}` called with data: {:?}", self.get_client_addr(), stringify!(#methods), &parsed);
-                            let response = self.#methods(parsed).await;
-                            let mut headers = crate::bgs::protocol::Header::default();
-                            headers.method_id = Some(#ids as u32);
-                            headers.service_hash = Some(Self::ORIGINAL_HASH);
-                            headers.token = msg.headers.token;
-                            log::debug!(target: stringify!(#service_name), "[{:?}] Method `{}` response: {:?}", self.get_client_addr(), stringify!(#methods), &response);
-                            let mut outoing_message = crate::messages::OutgoingMessage{ headers, message: Some(response?) };
-                            Ok(outoing_message.encode(true))
+                            let started = std::time::Instant::now();
+                            let result: Result<bytes::Bytes, crate::rpc_responses::WowRpcResponse> = async {
+                                let parsed = if let Some(0) = msg.headers.size
+                                    {<#inputs>::default()}
+                                    else
+                                    {<#inputs>::decode(&mut msg.data.clone()).map_err(|e| {
+                                        let e = crate::rpc_responses::RpcError::from(e);
+                                        log::warn!(target: stringify!(#service_name), "[{:?}] Method `{}` got a malformed request: {}", self.get_client_addr(), stringify!(#methods), e);
+                                        e.status()
+                                    })?};
+                                log::debug!(target: stringify!(#service_name), "[{:?}] Method `{}` called with data: {:?}", self.get_client_addr(), stringify!(#methods), &parsed);
+                                let response = self.#methods(parsed).await;
+                                let mut headers = crate::bgs::protocol::Header::default();
+                                headers.method_id = Some(#ids as u32);
+                                headers.service_hash = Some(Self::ORIGINAL_HASH);
+                                headers.token = msg.headers.token;
+                                log::debug!(target: stringify!(#service_name), "[{:?}] Method `{}` response: {:?}", self.get_client_addr(), stringify!(#methods), &response);
+                                let mut outoing_message = crate::messages::OutgoingMessage{ headers, message: Some(response?) };
+                                Ok(outoing_message.encode(true))
+                            }.await;
+                            rustycraft_metrics::observe_rpc(stringify!(#service_name), stringify!(#methods), started, &result);
+                            result
                         } ) *
                         _ => Err( crate::rpc_responses::WowRpcResponse::RpcNotImplemented ),
                     }
                 }
             }
+
+            /// Typed client stub, sending requests through `T`.
+            pub struct #client_name<T: crate::client::RpcTransport> {
+                transport: T,
+            }
+
+            impl<T: crate::client::RpcTransport> #client_name<T> {
+                pub fn new(transport: T) -> Self {
+                    #client_name { transport }
+                }
+
+                pub fn transport(&mut self) -> &mut T {
+                    &mut self.transport
+                }
+
+                pub fn into_inner(self) -> T {
+                    self.transport
+                }
+
+                # ( pub async fn #methods(&mut self, request: #inputs) -> Result<#outputs, crate::rpc_responses::RpcError> {
+                    let token = self.transport.next_token();
+                    let mut headers = crate::bgs::protocol::Header::default();
+                    headers.method_id = Some(#ids as u32);
+                    headers.service_hash = Some(#original_hash);
+                    headers.token = token;
+                    let mut outgoing_message = crate::messages::OutgoingMessage{ headers, message: Some(request) };
+                    let response = self.transport.call(token, outgoing_message.encode(false), #expects_responses).await?;
+                    crate::client::decode_response(response)
+                } ) *
+            }
         };
         buf.push_str(&quoted.to_string())
     }
@@ -0,0 +1,80 @@
+/// A client build both servers can talk to.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ClientBuild {
+    pub major: u32,
+    pub minor: u32,
+    pub revision: u32,
+    pub build: u32,
+    /// Suffix of the `Command_*` attributes of `GameUtilitiesService` requests.
+    pub command_suffix: &'static str,
+}
+
+pub const V9_2_0_43206: ClientBuild = ClientBuild {
+    major: 9,
+    minor: 2,
+    revision: 0,
+    build: 43206,
+    command_suffix: "v1_b9",
+};
+
+/// Newest first. The first entry is assumed until the client reports its build.
+pub const SUPPORTED_BUILDS: &[ClientBuild] = &[V9_2_0_43206];
+
+impl ClientBuild {
+    pub fn latest() -> &'static ClientBuild {
+        &SUPPORTED_BUILDS[0]
+    }
+
+    pub fn find(build: u32) -> Option<&'static ClientBuild> {
+        SUPPORTED_BUILDS.iter().find(|b| b.build == build)
+    }
+
+    /// `Command_{name}_{suffix}` attribute of this build.
+    pub fn command(&self, name: &str) -> String {
+        format!("Command_{}_{}", name, self.command_suffix)
+    }
+
+    /// Splits a `Command_*` attribute name of any supported build into the command name and build.
+    pub fn parse_command(attribute: &str) -> Option<(&str, &'static ClientBuild)> {
+        let command = attribute.strip_prefix("Command_")?;
+        SUPPORTED_BUILDS.iter().find_map(|build| {
+            let name = command
+                .strip_suffix(build.command_suffix)?
+                .strip_suffix('_')?;
+            Some((name, build))
+        })
+    }
+}
+
+impl std::fmt::Display for ClientBuild {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.revision, self.build
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let build = ClientBuild::find(43206).unwrap();
+        assert_eq!(build.to_string(), "9.2.0.43206");
+        let attribute = build.command("RealmListRequest");
+        assert_eq!(attribute, "Command_RealmListRequest_v1_b9");
+        assert_eq!(
+            ClientBuild::parse_command(&attribute),
+            Some(("RealmListRequest", build))
+        );
+        assert_eq!(
+            ClientBuild::parse_command("Command_RealmListRequest_v1_b1"),
+            None
+        );
+        assert_eq!(ClientBuild::parse_command("Param_RealmAddress"), None);
+        assert!(ClientBuild::find(1).is_none());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::expansions::Expansions;
+use deku::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DekuWrite)]
+#[deku(type = "u8")]
+#[repr(u8)]
+pub enum Classes {
+    ClassWarrior = 1,
+    ClassPaladin = 2,
+    ClassHunter = 3,
+    ClassRogue = 4,
+    ClassPriest = 5,
+    ClassDeathKnight = 6,
+    ClassShaman = 7,
+    ClassMage = 8,
+    ClassWarlock = 9,
+    ClassMonk = 10,
+    ClassDruid = 11,
+    ClassDemonHunter = 12,
+}
+
+impl Classes {
+    pub const ALL: [Classes; 12] = [
+        Classes::ClassWarrior,
+        Classes::ClassPaladin,
+        Classes::ClassHunter,
+        Classes::ClassRogue,
+        Classes::ClassPriest,
+        Classes::ClassDeathKnight,
+        Classes::ClassShaman,
+        Classes::ClassMage,
+        Classes::ClassWarlock,
+        Classes::ClassMonk,
+        Classes::ClassDruid,
+        Classes::ClassDemonHunter,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Classes::ClassWarrior => "Warrior",
+            Classes::ClassPaladin => "Paladin",
+            Classes::ClassHunter => "Hunter",
+            Classes::ClassRogue => "Rogue",
+            Classes::ClassPriest => "Priest",
+            Classes::ClassDeathKnight => "Death Knight",
+            Classes::ClassShaman => "Shaman",
+            Classes::ClassMage => "Mage",
+            Classes::ClassWarlock => "Warlock",
+            Classes::ClassMonk => "Monk",
+            Classes::ClassDruid => "Druid",
+            Classes::ClassDemonHunter => "Demon Hunter",
+        }
+    }
+
+    /// Expansion the account needs to create a character of this class.
+    pub fn min_expansion(&self) -> Expansions {
+        match self {
+            Classes::ClassDeathKnight => Expansions::ExpansionWrathOfTheLichKing,
+            Classes::ClassMonk => Expansions::ExpansionMistsOfPandaria,
+            Classes::ClassDemonHunter => Expansions::ExpansionLegion,
+            _ => Expansions::ExpansionClassic,
+        }
+    }
+}
+
+impl TryFrom<u8> for Classes {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Classes::ALL
+            .into_iter()
+            .find(|class| *class as u8 == value)
+            .ok_or(value)
+    }
+}
@@ -0,0 +1,207 @@
+use crate::bgs::protocol::Header;
+use crate::messages::RawMessage;
+use crate::rpc_responses::{RpcError, WowRpcResponse};
+use bytes::Bytes;
+use prost::Message as _;
+use std::collections::VecDeque;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// `service_id` of every response frame.
+pub const RESPONSE_SERVICE_ID: u32 = 0xFE;
+
+/// Carries requests encoded by the generated `*Client` stubs.
+#[async_trait::async_trait]
+pub trait RpcTransport: Send {
+    /// Token of the next outgoing request.
+    fn next_token(&mut self) -> u32;
+
+    /// Sends an encoded request frame and, if `expects_response` is set, waits for the response
+    /// carrying the same token.
+    async fn call(
+        &mut self,
+        token: u32,
+        frame: Bytes,
+        expects_response: bool,
+    ) -> Result<Option<RawMessage>, RpcError>;
+}
+
+/// Turns a response frame into the typed response, or the error status set by the peer.
+pub fn decode_response<O>(response: Option<RawMessage>) -> Result<O, RpcError>
+where
+    O: prost::Message + Default,
+{
+    let response = match response {
+        Some(response) => response,
+        None => return Ok(O::default()),
+    };
+    match response.headers.status {
+        Some(0) | None => {}
+        Some(status) => {
+            return Err(match WowRpcResponse::try_from(status) {
+                Ok(status) => status.into(),
+                Err(status) => RpcError::new(
+                    WowRpcResponse::Internal,
+                    format!("Unknown status {:#010X}", status),
+                ),
+            })
+        }
+    }
+    if let Some(0) = response.headers.size {
+        return Ok(O::default());
+    }
+    Ok(O::decode(response.data)?)
+}
+
+/// Reads one `u16` length prefixed `Header` followed by its payload.
+pub async fn read_frame<S>(stream: &mut S) -> Result<RawMessage, RpcError>
+where
+    S: AsyncRead + Unpin,
+{
+    let headers_len = stream.read_u16().await?;
+    let mut headers_buf = vec![0; headers_len as usize];
+    stream.read_exact(&mut headers_buf).await?;
+    let headers = Header::decode(headers_buf.as_slice())?;
+    let mut data = vec![0; headers.size.unwrap_or(0) as usize];
+    stream.read_exact(&mut data).await?;
+    Ok(RawMessage {
+        headers,
+        data: data.into(),
+    })
+}
+
+/// Client side of a bnet RPC connection over any byte stream, e.g. a TLS connection.
+///
+/// Requests sent by the peer while a response is awaited are queued, see
+/// [`StreamTransport::next_request`].
+pub struct StreamTransport<S> {
+    stream: S,
+    token: u32,
+    requests: VecDeque<RawMessage>,
+}
+
+impl<S> StreamTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        StreamTransport {
+            stream,
+            token: 0,
+            requests: VecDeque::new(),
+        }
+    }
+
+    /// Next request the peer sent to one of our listeners.
+    pub async fn next_request(&mut self) -> Result<RawMessage, RpcError> {
+        match self.requests.pop_front() {
+            Some(request) => Ok(request),
+            None => read_frame(&mut self.stream).await,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> RpcTransport for StreamTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn next_token(&mut self) -> u32 {
+        let token = self.token;
+        self.token = self.token.wrapping_add(1);
+        token
+    }
+
+    async fn call(
+        &mut self,
+        token: u32,
+        frame: Bytes,
+        expects_response: bool,
+    ) -> Result<Option<RawMessage>, RpcError> {
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        if !expects_response {
+            return Ok(None);
+        }
+        loop {
+            let message = read_frame(&mut self.stream).await?;
+            if message.headers.service_id == RESPONSE_SERVICE_ID && message.headers.token == token {
+                return Ok(Some(message));
+            }
+            self.requests.push_back(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgs::protocol::connection::v1::{
+        ConnectRequest, ConnectResponse, ConnectionService, ConnectionServiceClient, EchoRequest,
+    };
+    use crate::bgs::protocol::NoData;
+    use crate::messages::LoggingAttributes;
+    use std::net::SocketAddr;
+
+    struct Peer;
+
+    impl LoggingAttributes for Peer {
+        fn get_client_addr(&self) -> SocketAddr {
+            ([127, 0, 0, 1], 1119).into()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ConnectionService for Peer {
+        async fn connect(
+            &mut self,
+            request: ConnectRequest,
+        ) -> Result<ConnectResponse, WowRpcResponse> {
+            Ok(ConnectResponse {
+                use_bindless_rpc: request.use_bindless_rpc,
+                ..ConnectResponse::get_default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_roundtrip() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let mut peer = Peer;
+            while let Ok(request) = read_frame(&mut server).await {
+                let token = request.headers.token;
+                let frame = match ConnectionService::dispatch(&mut peer, request).await {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        let mut msg = crate::messages::OutgoingMessage::<NoData> {
+                            headers: Header {
+                                status: Some(e as u32),
+                                token,
+                                ..Default::default()
+                            },
+                            message: None,
+                        };
+                        msg.encode(true)
+                    }
+                };
+                server.write_all(&frame).await.unwrap();
+            }
+        });
+
+        let mut client = ConnectionServiceClient::new(StreamTransport::new(client));
+        let response = client
+            .connect(ConnectRequest {
+                use_bindless_rpc: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.use_bindless_rpc, Some(true));
+        let error = client.echo(EchoRequest::default()).await.unwrap_err();
+        assert_eq!(error.status(), WowRpcResponse::RpcNotImplemented);
+    }
+}
@@ -0,0 +1,43 @@
+use deku::prelude::*;
+
+/// Side a playable race belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DekuWrite)]
+#[deku(type = "u8")]
+#[repr(u8)]
+pub enum Factions {
+    Alliance = 0,
+    Horde = 1,
+    /// Pandaren before they pick a side.
+    Neutral = 2,
+}
+
+impl Factions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Factions::Alliance => "Alliance",
+            Factions::Horde => "Horde",
+            Factions::Neutral => "Neutral",
+        }
+    }
+
+    /// `FactionGroup` mask used by character templates.
+    pub fn group(&self) -> FactionGroup {
+        match self {
+            Factions::Alliance => FactionGroup::Alliance,
+            Factions::Horde => FactionGroup::Horde,
+            Factions::Neutral => FactionGroup::Player,
+        }
+    }
+}
+
+/// `FactionMasks` combinations the client expects in faction group fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DekuWrite)]
+#[deku(type = "u8")]
+#[repr(u8)]
+pub enum FactionGroup {
+    Player = 0x01,
+    /// `Player | Alliance`.
+    Alliance = 0x03,
+    /// `Player | Horde`.
+    Horde = 0x05,
+}
@@ -1,8 +1,13 @@
 mod autogen;
+pub mod builds;
+pub mod classes;
+pub mod client;
 pub mod expansions;
+pub mod factions;
 pub mod messages;
 pub mod rpc_responses;
 pub mod races;
+pub mod router;
 pub mod autogen_impls;
 
 pub use autogen::bgs;
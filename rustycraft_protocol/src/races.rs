@@ -1,21 +1,24 @@
+use crate::classes::Classes::{self, *};
+use crate::expansions::Expansions;
+use crate::factions::Factions;
 use deku::prelude::*;
 
-#[derive(Debug, Clone, DekuWrite)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DekuWrite)]
 #[deku(type = "u8")]
 #[repr(u8)]
 pub enum Races {
-    RaceNone = 0,          // SKIP
-    RaceHuman = 1,         // TITLE Human
-    RaceOrc = 2,           // TITLE Orc
-    RaceDwarf = 3,         // TITLE Dwarf
-    RaceNightelf = 4,      // TITLE Night Elf
-    RaceUndeadPlayer = 5, // TITLE Undead
-    RaceTauren = 6,        // TITLE Tauren
-    RaceGnome = 7,         // TITLE Gnome
-    RaceTroll = 8,         // TITLE Troll
-    RaceGoblin = 9,        // TITLE Goblin
-    RaceBloodelf = 10,     // TITLE Blood Elf
-    RaceDraenei = 11,      // TITLE Draenei
+    RaceNone = 0, // SKIP
+    RaceHuman = 1,
+    RaceOrc = 2,
+    RaceDwarf = 3,
+    RaceNightelf = 4,
+    RaceUndeadPlayer = 5,
+    RaceTauren = 6,
+    RaceGnome = 7,
+    RaceTroll = 8,
+    RaceGoblin = 9,
+    RaceBloodelf = 10,
+    RaceDraenei = 11,
 
     // RaceFelOrc = 12,
     // RaceNaga = 13,
@@ -27,25 +30,378 @@ pub enum Races {
     // RaceTaunka = 19,
     // RaceNorthrendSkeleton = 20,
     // RaceIceTroll = 21,
-
-    RaceWorgen = 22, // TITLE Worgen
+    RaceWorgen = 22,
 
     // RaceGilnean = 23,
-
-    RacePandarenNeutral = 24, // TITLE Pandaren DESCRIPTION Pandaren (Neutral)
-    RacePandarenAlliance = 25, // TITLE Pandaren DESCRIPTION Pandaren (Alliance)
-    RacePandarenHorde = 26,   // TITLE Pandaren DESCRIPTION Pandaren (Horde)
-    RaceNightborne = 27,       // TITLE Nightborne
-    RaceHighmountainTauren = 28, // TITLE Highmountain Tauren
-    RaceVoidElf = 29,         // TITLE Void Elf
-    RaceLightforgedDraenei = 30, // TITLE Lightforged Draenei
-    RaceZandalariTroll = 31,  // TITLE Zandalari Troll
-    RaceKulTiran = 32,        // TITLE Kul Tiran
+    RacePandarenNeutral = 24,
+    RacePandarenAlliance = 25,
+    RacePandarenHorde = 26,
+    RaceNightborne = 27,
+    RaceHighmountainTauren = 28,
+    RaceVoidElf = 29,
+    RaceLightforgedDraenei = 30,
+    RaceZandalariTroll = 31,
+    RaceKulTiran = 32,
 
     // RaceThinHuman = 33,
+    RaceDarkIronDwarf = 34,
+    RaceVulpera = 35,
+    RaceMagharOrc = 36,
+    RaceMechagnome = 37,
+}
+
+impl Races {
+    /// Every race a player can create.
+    pub const PLAYABLE: [Races; 25] = [
+        Races::RaceHuman,
+        Races::RaceOrc,
+        Races::RaceDwarf,
+        Races::RaceNightelf,
+        Races::RaceUndeadPlayer,
+        Races::RaceTauren,
+        Races::RaceGnome,
+        Races::RaceTroll,
+        Races::RaceGoblin,
+        Races::RaceBloodelf,
+        Races::RaceDraenei,
+        Races::RaceWorgen,
+        Races::RacePandarenNeutral,
+        Races::RacePandarenAlliance,
+        Races::RacePandarenHorde,
+        Races::RaceNightborne,
+        Races::RaceHighmountainTauren,
+        Races::RaceVoidElf,
+        Races::RaceLightforgedDraenei,
+        Races::RaceZandalariTroll,
+        Races::RaceKulTiran,
+        Races::RaceDarkIronDwarf,
+        Races::RaceVulpera,
+        Races::RaceMagharOrc,
+        Races::RaceMechagnome,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Races::RaceNone => "None",
+            Races::RaceHuman => "Human",
+            Races::RaceOrc => "Orc",
+            Races::RaceDwarf => "Dwarf",
+            Races::RaceNightelf => "Night Elf",
+            Races::RaceUndeadPlayer => "Undead",
+            Races::RaceTauren => "Tauren",
+            Races::RaceGnome => "Gnome",
+            Races::RaceTroll => "Troll",
+            Races::RaceGoblin => "Goblin",
+            Races::RaceBloodelf => "Blood Elf",
+            Races::RaceDraenei => "Draenei",
+            Races::RaceWorgen => "Worgen",
+            Races::RacePandarenNeutral | Races::RacePandarenAlliance | Races::RacePandarenHorde => {
+                "Pandaren"
+            }
+            Races::RaceNightborne => "Nightborne",
+            Races::RaceHighmountainTauren => "Highmountain Tauren",
+            Races::RaceVoidElf => "Void Elf",
+            Races::RaceLightforgedDraenei => "Lightforged Draenei",
+            Races::RaceZandalariTroll => "Zandalari Troll",
+            Races::RaceKulTiran => "Kul Tiran",
+            Races::RaceDarkIronDwarf => "Dark Iron Dwarf",
+            Races::RaceVulpera => "Vulpera",
+            Races::RaceMagharOrc => "Mag'har Orc",
+            Races::RaceMechagnome => "Mechagnome",
+        }
+    }
+
+    pub fn faction(&self) -> Factions {
+        match self {
+            Races::RaceHuman
+            | Races::RaceDwarf
+            | Races::RaceNightelf
+            | Races::RaceGnome
+            | Races::RaceDraenei
+            | Races::RaceWorgen
+            | Races::RacePandarenAlliance
+            | Races::RaceVoidElf
+            | Races::RaceLightforgedDraenei
+            | Races::RaceKulTiran
+            | Races::RaceDarkIronDwarf
+            | Races::RaceMechagnome => Factions::Alliance,
+            Races::RaceOrc
+            | Races::RaceUndeadPlayer
+            | Races::RaceTauren
+            | Races::RaceTroll
+            | Races::RaceGoblin
+            | Races::RaceBloodelf
+            | Races::RacePandarenHorde
+            | Races::RaceNightborne
+            | Races::RaceHighmountainTauren
+            | Races::RaceZandalariTroll
+            | Races::RaceVulpera
+            | Races::RaceMagharOrc => Factions::Horde,
+            Races::RaceNone | Races::RacePandarenNeutral => Factions::Neutral,
+        }
+    }
+
+    /// Expansion the account needs to create a character of this race.
+    pub fn min_expansion(&self) -> Expansions {
+        match self {
+            Races::RaceBloodelf | Races::RaceDraenei => Expansions::ExpansionTheBurningCrusade,
+            Races::RaceGoblin | Races::RaceWorgen => Expansions::ExpansionCataclysm,
+            Races::RacePandarenNeutral | Races::RacePandarenAlliance | Races::RacePandarenHorde => {
+                Expansions::ExpansionMistsOfPandaria
+            }
+            Races::RaceNightborne
+            | Races::RaceHighmountainTauren
+            | Races::RaceVoidElf
+            | Races::RaceLightforgedDraenei
+            | Races::RaceZandalariTroll
+            | Races::RaceKulTiran
+            | Races::RaceDarkIronDwarf
+            | Races::RaceVulpera
+            | Races::RaceMagharOrc
+            | Races::RaceMechagnome => Expansions::ExpansionBattleForAzeroth,
+            _ => Expansions::ExpansionClassic,
+        }
+    }
+
+    /// Classes this race can be created as.
+    pub fn classes(&self) -> &'static [Classes] {
+        match self {
+            Races::RaceNone => &[],
+            Races::RaceHuman => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+            ],
+            Races::RaceOrc => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+            ],
+            Races::RaceDwarf | Races::RaceDarkIronDwarf => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+            ],
+            Races::RaceNightelf => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassMage,
+                ClassMonk,
+                ClassDruid,
+                ClassDemonHunter,
+            ],
+            Races::RaceUndeadPlayer
+            | Races::RaceGnome
+            | Races::RaceNightborne
+            | Races::RaceVoidElf
+            | Races::RaceMechagnome => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+            ],
+            Races::RaceTauren => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMonk,
+                ClassDruid,
+            ],
+            Races::RaceTroll => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+                ClassDruid,
+            ],
+            Races::RaceGoblin => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassWarlock,
+            ],
+            Races::RaceBloodelf => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+                ClassDemonHunter,
+            ],
+            Races::RaceDraenei => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassMonk,
+            ],
+            Races::RaceWorgen => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassMage,
+                ClassWarlock,
+                ClassDruid,
+            ],
+            Races::RacePandarenNeutral
+            | Races::RacePandarenAlliance
+            | Races::RacePandarenHorde
+            | Races::RaceMagharOrc => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassMonk,
+            ],
+            Races::RaceHighmountainTauren => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMonk,
+                ClassDruid,
+            ],
+            Races::RaceLightforgedDraenei => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassMage,
+            ],
+            Races::RaceZandalariTroll => &[
+                ClassWarrior,
+                ClassPaladin,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassMonk,
+                ClassDruid,
+            ],
+            Races::RaceKulTiran => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassMonk,
+                ClassDruid,
+            ],
+            Races::RaceVulpera => &[
+                ClassWarrior,
+                ClassHunter,
+                ClassRogue,
+                ClassPriest,
+                ClassDeathKnight,
+                ClassShaman,
+                ClassMage,
+                ClassWarlock,
+                ClassMonk,
+            ],
+        }
+    }
+
+    pub fn can_be(&self, class: Classes) -> bool {
+        self.classes().contains(&class)
+    }
+
+    /// Expansion needed for this race and class combination.
+    pub fn min_expansion_for(&self, class: Classes) -> Expansions {
+        self.min_expansion().max(class.min_expansion())
+    }
+}
+
+impl TryFrom<u8> for Races {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Races::PLAYABLE
+            .into_iter()
+            .find(|race| *race as u8 == value)
+            .ok_or(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    RaceDarkIronDwarf = 34, // TITLE Dark Iron Dwarf DESCRIPTION Dark Iron Dwarf (RaceMask bit 11)
-    RaceVulpera = 35,         // TITLE Vulpera DESCRIPTION Vulpera (RaceMask bit 12)
-    RaceMagharOrc = 36,      // TITLE Mag'har Orc DESCRIPTION Mag'har Orc (RaceMask bit 13)
-    RaceMechagnome = 37,      // TITLE Mechagnome DESCRIPTION Mechagnome (RaceMask bit 14)
+    #[test]
+    fn test_race_data() {
+        for race in Races::PLAYABLE {
+            assert!(!race.classes().is_empty(), "{}", race.name());
+            assert_eq!(Races::try_from(race as u8), Ok(race));
+        }
+        let demon_hunters: Vec<Races> = Races::PLAYABLE
+            .into_iter()
+            .filter(|race| race.can_be(ClassDemonHunter))
+            .collect();
+        assert_eq!(
+            demon_hunters,
+            vec![Races::RaceNightelf, Races::RaceBloodelf]
+        );
+        assert_eq!(Races::RacePandarenNeutral.faction(), Factions::Neutral);
+        assert_eq!(
+            Races::RaceDraenei.min_expansion_for(ClassDeathKnight),
+            Expansions::ExpansionWrathOfTheLichKing
+        );
+        assert_eq!(
+            Races::RaceVulpera.min_expansion_for(ClassMonk),
+            Expansions::ExpansionBattleForAzeroth
+        );
+    }
 }
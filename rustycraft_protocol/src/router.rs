@@ -0,0 +1,181 @@
+use crate::bgs::protocol::Header;
+use crate::messages::{LoggingAttributes, RawMessage};
+use crate::rpc_responses::WowRpcResponse;
+use bytes::Bytes;
+use log::warn;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+pub type DispatchFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Bytes, WowRpcResponse>> + Send + 'a>>;
+
+/// One service implemented by `T`, built by the generated `route()` of each service trait.
+pub struct Route<T> {
+    pub name: &'static str,
+    pub original_hash: u32,
+    pub name_hash: u32,
+    pub dispatch: for<'a> fn(&'a mut T, RawMessage) -> DispatchFuture<'a>,
+}
+
+/// Dispatches requests to the services of `T` by `service_hash`, or by the id the client bound
+/// the service to when the hash is absent.
+///
+/// Routes are shared between clones, bindings are not: clone once per connection, and share
+/// that clone behind an `Arc` rather than cloning it for every request.
+pub struct Router<T> {
+    routes: Arc<Vec<Route<T>>>,
+    bound: Mutex<HashMap<u32, usize>>,
+}
+
+impl<T> Clone for Router<T> {
+    fn clone(&self) -> Self {
+        Router {
+            routes: self.routes.clone(),
+            bound: Mutex::new(self.bound.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Router {
+            routes: Arc::new(Vec::new()),
+            bound: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> Router<T>
+where
+    T: LoggingAttributes + Send,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, route: Route<T>) -> &mut Self {
+        Arc::get_mut(&mut self.routes)
+            .expect("Routes are added before the router is cloned")
+            .push(route);
+        self
+    }
+
+    /// Binds `service_id` to the service with the given hash. Returns `false` for unknown services.
+    pub fn bind(&self, service_id: u32, hash: u32) -> bool {
+        match self.position(hash) {
+            Some(idx) => {
+                self.bound.lock().unwrap().insert(service_id, idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn position(&self, hash: u32) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|route| route.original_hash == hash || route.name_hash == hash)
+    }
+
+    fn resolve(&self, headers: &Header) -> Option<&Route<T>> {
+        let idx = match headers.service_hash {
+            Some(hash) => self.position(hash)?,
+            None => *self.bound.lock().unwrap().get(&headers.service_id)?,
+        };
+        self.routes.get(idx)
+    }
+
+    /// Unknown services and unimplemented methods both end up as `RpcNotImplemented`.
+    pub async fn dispatch(&self, target: &mut T, msg: RawMessage) -> Result<Bytes, WowRpcResponse> {
+        let route = match self.resolve(&msg.headers) {
+            Some(route) => route,
+            None => {
+                warn!(target: "Router",
+                    "[{:?}] Unknown service: hash {:?}, id {}",
+                    target.get_client_addr(), msg.headers.service_hash, msg.headers.service_id
+                );
+                return Err(WowRpcResponse::RpcNotImplemented);
+            }
+        };
+        let method_id = msg.headers.method_id;
+        match (route.dispatch)(target, msg).await {
+            Err(WowRpcResponse::NotImplemented) | Err(WowRpcResponse::RpcNotImplemented) => {
+                warn!(target: "Router",
+                    "[{:?}] Method {:?} of `{}` is not implemented",
+                    target.get_client_addr(), method_id, route.name
+                );
+                Err(WowRpcResponse::RpcNotImplemented)
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgs::protocol::connection::v1::ConnectionService;
+    use crate::bgs::protocol::{NoData, NoResponse};
+    use std::net::SocketAddr;
+
+    struct Peer;
+
+    impl LoggingAttributes for Peer {
+        fn get_client_addr(&self) -> SocketAddr {
+            ([127, 0, 0, 1], 1119).into()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ConnectionService for Peer {
+        async fn keep_alive(&mut self, _: NoData) -> Result<NoResponse, WowRpcResponse> {
+            Ok(Default::default())
+        }
+    }
+
+    fn request(service_hash: Option<u32>, service_id: u32, method_id: u8) -> RawMessage {
+        RawMessage {
+            headers: Header {
+                service_id,
+                service_hash,
+                method_id: Some(method_id as u32),
+                size: Some(0),
+                ..Default::default()
+            },
+            data: Bytes::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let mut router = Router::new();
+        router.add(<Peer as ConnectionService>::route());
+        let mut peer = Peer;
+        let keep_alive = <Peer as ConnectionService>::KEEP_ALIVE;
+        let original_hash = <Peer as ConnectionService>::ORIGINAL_HASH;
+        let name_hash = <Peer as ConnectionService>::NAME_HASH;
+
+        for hash in [original_hash, name_hash] {
+            let msg = request(Some(hash), 0, keep_alive);
+            assert!(router.dispatch(&mut peer, msg).await.is_ok());
+        }
+        let msg = request(None, 7, keep_alive);
+        assert!(matches!(
+            router.dispatch(&mut peer, msg).await,
+            Err(WowRpcResponse::RpcNotImplemented)
+        ));
+        assert!(router.bind(7, original_hash));
+        assert!(!router.bind(8, 0));
+        let msg = request(None, 7, keep_alive);
+        assert!(router.dispatch(&mut peer, msg).await.is_ok());
+
+        let connect = <Peer as ConnectionService>::CONNECT;
+        let msg = request(Some(original_hash), 0, connect);
+        assert!(matches!(
+            router.dispatch(&mut peer, msg).await,
+            Err(WowRpcResponse::RpcNotImplemented)
+        ));
+    }
+}
@@ -1,13 +1,15 @@
 use deku::prelude::*;
-use log::error;
+use log::log;
 use prost::{DecodeError, EncodeError};
 use std::ffi::NulError;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use tokio::sync::mpsc::error::SendError;
 use tokio::task::JoinError;
 use tokio::time::error::Elapsed;
 
-#[derive(Debug, Clone, DekuWrite)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, DekuRead, DekuWrite, serde::Serialize, serde::Deserialize,
+)]
 #[deku(type = "u32", endian = "little")]
 #[repr(u32)]
 pub enum WowRpcResponse {
@@ -658,51 +660,264 @@ pub enum WowRpcResponse {
     WowServicesCantConnect = 0x80000148,
 }
 
-impl From<prost::EncodeError> for WowRpcResponse {
-    fn from(_: EncodeError) -> Self {
-        Self::RpcMalformedResponse
+/// Subsystem a status code belongs to, following the ranges the codes are allocated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    General,
+    Logon,
+    Challenge,
+    Config,
+    Network,
+    Rpc,
+    Service,
+    Storage,
+    Client,
+}
+
+/// How much attention a status deserves when it is logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Success, or an expected end of the session.
+    Info,
+    /// The request was rejected, the server is fine.
+    Warning,
+    /// Something is broken on our side or between us and the peer.
+    Error,
+}
+
+impl Severity {
+    pub fn level(&self) -> log::Level {
+        match self {
+            Severity::Info => log::Level::Info,
+            Severity::Warning => log::Level::Warn,
+            Severity::Error => log::Level::Error,
+        }
+    }
+}
+
+impl WowRpcResponse {
+    pub fn category(&self) -> ErrorCategory {
+        match *self as u32 {
+            0x0000_0000..=0x0000_01F3 => ErrorCategory::General,
+            0x0000_01F4..=0x0000_0257 => ErrorCategory::Logon,
+            0x0000_0258..=0x0000_02BB => ErrorCategory::Challenge,
+            0x0000_02BC..=0x0000_03E7 => ErrorCategory::Config,
+            0x0000_03E8..=0x0000_0BB7 => ErrorCategory::Network,
+            0x0000_0BB8..=0x0000_0F9F => ErrorCategory::Rpc,
+            0x0000_0FA0..=0x0000_FFFF => ErrorCategory::Service,
+            0x0001_0000..=0x7FFF_FFFF => ErrorCategory::Storage,
+            _ => ErrorCategory::Client,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::Ok
+            | Self::OkDeprecated
+            | Self::ServerShuttingDown
+            | Self::PlannedMaintenance
+            | Self::RpcShutdown
+            | Self::RpcDisconnect
+            | Self::RpcDisconnectIdle => Severity::Info,
+            Self::Internal
+            | Self::TimedOut
+            | Self::UnplannedMaintenance
+            | Self::RpcServerError
+            | Self::RpcMalformedResponse
+            | Self::RpcEncryptionFailed => Severity::Error,
+            _ => match self.category() {
+                ErrorCategory::Network | ErrorCategory::Storage => Severity::Error,
+                _ => Severity::Warning,
+            },
+        }
+    }
+}
+
+impl TryFrom<u32> for WowRpcResponse {
+    /// The unknown status.
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        WowRpcResponse::from_bytes((&value.to_le_bytes(), 0))
+            .map(|(_, status)| status)
+            .map_err(|_| value)
+    }
+}
+
+impl std::fmt::Display for WowRpcResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} ({:#010X})", self, *self as u32)
+    }
+}
+
+impl std::error::Error for WowRpcResponse {}
+
+/// A failed RPC along with what caused it. Only the status is ever sent to the peer.
+#[derive(Debug)]
+pub struct RpcError {
+    status: WowRpcResponse,
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl RpcError {
+    pub fn new<E>(status: WowRpcResponse, source: E) -> RpcError
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        RpcError {
+            status,
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn status(&self) -> WowRpcResponse {
+        self.status
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}: {}", self.status, source),
+            None => write!(f, "{}", self.status),
+        }
     }
 }
 
-impl From<std::io::Error> for WowRpcResponse {
-    fn from(_: Error) -> Self {
-        Self::RpcMalformedRequest
+impl std::error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
     }
 }
 
-impl From<DecodeError> for WowRpcResponse {
+impl From<WowRpcResponse> for RpcError {
+    fn from(status: WowRpcResponse) -> Self {
+        RpcError {
+            status,
+            source: None,
+        }
+    }
+}
+
+impl From<RpcError> for WowRpcResponse {
+    fn from(e: RpcError) -> Self {
+        e.status
+    }
+}
+
+impl From<EncodeError> for RpcError {
+    fn from(e: EncodeError) -> Self {
+        RpcError::new(WowRpcResponse::RpcMalformedResponse, e)
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        let status = match e.kind() {
+            ErrorKind::TimedOut => WowRpcResponse::RpcRequestTimedOut,
+            ErrorKind::UnexpectedEof
+            | ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted => WowRpcResponse::RpcPeerDisconnected,
+            _ => WowRpcResponse::RpcMalformedRequest,
+        };
+        RpcError::new(status, e)
+    }
+}
+
+impl From<DecodeError> for RpcError {
     fn from(e: DecodeError) -> Self {
-        error!("{}", e.to_string());
-        Self::RpcMalformedRequest
+        RpcError::new(WowRpcResponse::RpcMalformedRequest, e)
     }
 }
 
-impl From<Elapsed> for WowRpcResponse {
-    fn from(_: Elapsed) -> Self {
-        Self::TimedOut
+impl From<Elapsed> for RpcError {
+    fn from(e: Elapsed) -> Self {
+        RpcError::new(WowRpcResponse::TimedOut, e)
     }
 }
 
-impl From<NulError> for WowRpcResponse {
-    fn from(_: NulError) -> Self {
-        Self::Internal
+impl From<NulError> for RpcError {
+    fn from(e: NulError) -> Self {
+        RpcError::new(WowRpcResponse::Internal, e)
     }
 }
 
-impl<T> From<SendError<T>> for WowRpcResponse {
-    fn from(_: SendError<T>) -> Self {
-        Self::Internal
+impl<T> From<SendError<T>> for RpcError {
+    fn from(e: SendError<T>) -> Self {
+        RpcError::new(WowRpcResponse::Internal, e.to_string())
+    }
+}
+
+impl From<JoinError> for RpcError {
+    fn from(e: JoinError) -> Self {
+        RpcError::new(WowRpcResponse::Internal, e)
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(e: serde_json::Error) -> Self {
+        RpcError::new(WowRpcResponse::RpcMalformedRequest, e)
     }
 }
 
-impl From<JoinError> for WowRpcResponse {
-    fn from(_: JoinError) -> Self {
-        Self::Internal
+/// Conversions for handlers returning a bare status, the cause is logged before it is dropped.
+macro_rules! status_from {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for WowRpcResponse {
+                fn from(e: $error) -> Self {
+                    let e = RpcError::from(e);
+                    log!(e.status.severity().level(), "{}", e);
+                    e.status
+                }
+            }
+        )*
+    };
+}
+
+status_from!(
+    EncodeError,
+    Error,
+    DecodeError,
+    Elapsed,
+    NulError,
+    JoinError,
+    serde_json::Error,
+);
+
+impl<T> From<SendError<T>> for WowRpcResponse {
+    fn from(e: SendError<T>) -> Self {
+        let e = RpcError::from(e);
+        log!(e.status.severity().level(), "{}", e);
+        e.status
     }
 }
 
-impl From<serde_json::Error> for WowRpcResponse {
-    fn from(_: serde_json::Error) -> Self {
-        Self::RpcMalformedRequest
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_conversions() {
+        let status = WowRpcResponse::try_from(0x0000_0BC7).unwrap();
+        assert_eq!(status, WowRpcResponse::RpcNotImplemented);
+        assert_eq!(status.to_string(), "RpcNotImplemented (0x00000BC7)");
+        assert_eq!(status.category(), ErrorCategory::Rpc);
+        assert_eq!(status.severity(), Severity::Warning);
+        assert_eq!(WowRpcResponse::try_from(0x0000_0BFF), Err(0x0000_0BFF));
+        assert_eq!(
+            WowRpcResponse::WowServicesCantConnect.category(),
+            ErrorCategory::Client
+        );
+
+        let e = RpcError::from(Error::from(ErrorKind::UnexpectedEof));
+        assert_eq!(e.status(), WowRpcResponse::RpcPeerDisconnected);
+        assert!(std::error::Error::source(&e).is_some());
+        assert!(e
+            .to_string()
+            .starts_with("RpcPeerDisconnected (0x00000BBD): "));
     }
 }
@@ -0,0 +1,181 @@
+//! Packet encryption and the `EncryptedMode` signature.
+//!
+//! `cargo bench --features boring-sys` also measures the OpenSSL code these replaced.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustycraft_world_server::crypt::{AES128Companion, RSA};
+
+const PACKET_SIZES: [usize; 3] = [16, 1024, 16 * 1024];
+const KEY: [u8; 16] = [7; 16];
+
+fn aes_encrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aes_128_gcm_encrypt");
+    for size in PACKET_SIZES {
+        let data = vec![0x42; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let mut companion = AES128Companion::new();
+        companion.init(&KEY).unwrap();
+        group.bench_with_input(BenchmarkId::new("rustcrypto", size), &data, |b, data| {
+            b.iter(|| companion.encrypt(black_box(data)).unwrap())
+        });
+
+        #[cfg(feature = "boring-sys")]
+        {
+            let mut cipher = legacy::AES128::new(0x52565253, &KEY);
+            group.bench_with_input(BenchmarkId::new("boring", size), &data, |b, data| {
+                b.iter(|| cipher.encrypt(black_box(data)))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn rsa_sign(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rsa_sign");
+    let digest = [1; 32];
+    let rsa = RSA::bundled().unwrap();
+    group.bench_function("rustcrypto", |b| {
+        b.iter(|| rsa.sign(black_box(&digest)).unwrap())
+    });
+
+    #[cfg(feature = "boring-sys")]
+    {
+        let rsa = legacy::RSA::new();
+        group.bench_function("boring", |b| b.iter(|| rsa.sign(black_box(&digest))));
+    }
+    group.finish();
+}
+
+/// What `crypt.rs` did before, through `boring-sys`.
+#[cfg(feature = "boring-sys")]
+mod legacy {
+    use boring_sys::{
+        EVP_CIPHER_CTX_ctrl, EVP_CIPHER_CTX_free, EVP_CIPHER_CTX_new, EVP_CipherFinal_ex,
+        EVP_CipherInit_ex, EVP_CipherUpdate, EVP_aes_128_gcm, EVP_CTRL_GCM_GET_TAG,
+    };
+    use rustycraft_world_server::constants::SERVER_PRIVATE_KEY;
+    use std::ffi::c_void;
+    use std::os::raw::{c_int, c_uint};
+
+    pub struct RSA {
+        c_rsa: *mut boring_sys::RSA,
+    }
+
+    impl RSA {
+        pub fn new() -> RSA {
+            unsafe {
+                let mut rsa = boring_sys::RSA_new();
+                let pk_bio = boring_sys::BIO_new_mem_buf(
+                    SERVER_PRIVATE_KEY.as_ptr() as *const c_void,
+                    SERVER_PRIVATE_KEY.len() as i32,
+                );
+                boring_sys::PEM_read_bio_RSAPrivateKey(
+                    pk_bio,
+                    &mut rsa as *mut *mut boring_sys::RSA,
+                    None,
+                    std::ptr::null_mut(),
+                );
+                boring_sys::BIO_free(pk_bio);
+                RSA { c_rsa: rsa }
+            }
+        }
+
+        pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+            let mut signature = vec![0; 256];
+            let mut siglen = 0 as c_uint;
+            unsafe {
+                boring_sys::RSA_sign(
+                    boring_sys::NID_sha256,
+                    data.as_ptr(),
+                    data.len() as c_uint,
+                    signature.as_mut_ptr(),
+                    &mut siglen as *mut c_uint,
+                    self.c_rsa,
+                );
+            };
+            signature.reverse();
+            signature
+        }
+    }
+
+    impl Drop for RSA {
+        fn drop(&mut self) {
+            unsafe { boring_sys::RSA_free(self.c_rsa) }
+        }
+    }
+
+    pub struct AES128 {
+        ctx: *mut boring_sys::EVP_CIPHER_CTX,
+        magic: u32,
+        counter: u64,
+    }
+
+    impl AES128 {
+        pub fn new(magic: u32, key: &[u8]) -> AES128 {
+            unsafe {
+                let ctx = EVP_CIPHER_CTX_new();
+                EVP_CipherInit_ex(
+                    ctx,
+                    EVP_aes_128_gcm(),
+                    std::ptr::null_mut(),
+                    key.as_ptr(),
+                    std::ptr::null(),
+                    1,
+                );
+                AES128 {
+                    ctx,
+                    magic,
+                    counter: 0,
+                }
+            }
+        }
+
+        pub fn encrypt(&mut self, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            let mut iv = self.counter.to_le_bytes().to_vec();
+            iv.extend(self.magic.to_le_bytes());
+            let mut out = vec![0; data.len()];
+            let mut tag = vec![0; 12];
+            let mut out_len = 0;
+            unsafe {
+                EVP_CipherInit_ex(
+                    self.ctx,
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    iv.as_ptr(),
+                    -1,
+                );
+                EVP_CipherUpdate(
+                    self.ctx,
+                    out.as_mut_ptr(),
+                    &mut out_len as *mut c_int,
+                    data.as_ptr(),
+                    data.len() as c_int,
+                );
+                EVP_CipherFinal_ex(
+                    self.ctx,
+                    out.as_mut_ptr().offset(out_len as isize),
+                    &mut out_len as *mut c_int,
+                );
+                EVP_CIPHER_CTX_ctrl(
+                    self.ctx,
+                    EVP_CTRL_GCM_GET_TAG,
+                    tag.len() as c_int,
+                    tag.as_mut_ptr() as *mut c_void,
+                );
+            }
+            self.counter += 1;
+            (out, tag)
+        }
+    }
+
+    impl Drop for AES128 {
+        fn drop(&mut self) {
+            unsafe { EVP_CIPHER_CTX_free(self.ctx) }
+        }
+    }
+}
+
+criterion_group!(benches, aes_encrypt, rsa_sign);
+criterion_main!(benches);
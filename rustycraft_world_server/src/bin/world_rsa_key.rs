@@ -0,0 +1,53 @@
+//! Manages the key the world server signs `EncryptedMode` with.
+//!
+//! `generate <key.pem>` writes a new key and prints its modulus, `modulus <key.pem>` prints the
+//! modulus of an existing one. Clients must be patched with that modulus to accept the server.
+
+use anyhow::{anyhow, bail};
+use rustycraft_world_server::crypt::RSA;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+const USAGE: &str = "Usage: world_rsa_key generate <key.pem> | modulus <key.pem>";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [command, path] if command == "generate" => {
+            let rsa = RSA::generate()?;
+            write_key(Path::new(path), &rsa)?;
+            eprintln!("Wrote {}, set world.rsa_key_path to it", path);
+            print_modulus(&rsa);
+        }
+        [command, path] if command == "modulus" => print_modulus(&RSA::load(path)?),
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
+
+/// Never overwrites a key, clients patched for it would stop connecting.
+fn write_key(path: &Path, rsa: &RSA) -> anyhow::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow!("Could not create {}: {}", path.display(), e))?;
+    file.write_all(rsa.to_pkcs1_pem()?.as_bytes())?;
+    Ok(())
+}
+
+/// Hex of the little endian modulus, the bytes to patch into the client.
+fn print_modulus(rsa: &RSA) {
+    let hex: String = rsa
+        .modulus_le()
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    println!("{}", hex);
+}
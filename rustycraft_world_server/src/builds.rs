@@ -0,0 +1,159 @@
+use crate::opcodes::OpcodeClient;
+use crate::OpcodeServer;
+use rustycraft_protocol::builds::{ClientBuild, V9_2_0_43206};
+
+/// World protocol of one client build.
+///
+/// Opcode values move between builds, so each build maps the opcodes this server reads and
+/// writes to its own values. Packets whose layout changes between builds match on `build` when
+/// reading or writing themselves.
+pub struct WorldProtocol {
+    pub build: &'static ClientBuild,
+    pub client_opcodes: &'static [(OpcodeClient, u16)],
+    pub server_opcodes: &'static [(OpcodeServer, u16)],
+}
+
+/// Same order as `rustycraft_protocol::builds::SUPPORTED_BUILDS`.
+pub static PROTOCOLS: &[WorldProtocol] = &[WorldProtocol {
+    build: &V9_2_0_43206,
+    client_opcodes: &[
+        (OpcodeClient::AuthSession, 0x3765),
+        (OpcodeClient::AuthContinuedSession, 0x3766),
+        (OpcodeClient::EnterEncryptedModeAck, 0x3767),
+        (OpcodeClient::Ping, 0x3768),
+        (OpcodeClient::LogDisconnect, 0x3769),
+        (OpcodeClient::LogStreamingError, 0x376D),
+        (OpcodeClient::ConnectToFailed, 0x35D4),
+        (OpcodeClient::DbQueryBulk, 0x35E4),
+        (OpcodeClient::HotfixRequest, 0x35E5),
+        (OpcodeClient::PlayerLogin, 0x35EA),
+    ],
+    server_opcodes: &[
+        (OpcodeServer::AuthChallenge, 0x3048),
+        (OpcodeServer::EnterEncryptedMode, 0x3049),
+        (OpcodeServer::ResumeComms, 0x304B),
+        (OpcodeServer::ConnectTo, 0x304D),
+        (OpcodeServer::Pong, 0x304E),
+        (OpcodeServer::AuthResponse, 0x256D),
+        (OpcodeServer::FeatureSystemStatusGlueScreen, 0x25BC),
+        (OpcodeServer::SetTimeZoneInformation, 0x266E),
+        (OpcodeServer::DbReply, 0x290E),
+        (OpcodeServer::AvailableHotfixes, 0x290F),
+        (OpcodeServer::HotfixConnect, 0x2911),
+        (OpcodeServer::CacheVersion, 0x291C),
+        (OpcodeServer::ChatServerMessage, 0x2BC4),
+    ],
+}];
+
+impl WorldProtocol {
+    pub fn latest() -> &'static WorldProtocol {
+        &PROTOCOLS[0]
+    }
+
+    pub fn find(build: u32) -> Option<&'static WorldProtocol> {
+        PROTOCOLS.iter().find(|p| p.build.build == build)
+    }
+
+    /// The first build that reads `opcode` from `value`, used before the client build is known.
+    pub fn detect(value: u16, opcode: OpcodeClient) -> Option<&'static WorldProtocol> {
+        PROTOCOLS
+            .iter()
+            .find(|p| p.client_opcode(value).as_ref() == Some(&opcode))
+    }
+
+    /// `None` for the opcodes this build doesn't send, or this server doesn't read.
+    pub fn client_opcode(&self, value: u16) -> Option<OpcodeClient> {
+        self.client_opcodes
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(opcode, _)| *opcode)
+    }
+
+    /// `None` for the opcodes this build doesn't read.
+    pub fn server_opcode(&self, opcode: OpcodeServer) -> Option<u16> {
+        self.server_opcodes
+            .iter()
+            .find(|(o, _)| *o == opcode)
+            .map(|(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::auth::{Ping, Pong};
+    use crate::packets::{ClientPacket, IntoServerPacket};
+
+    const V9_2_5_TEST: ClientBuild = ClientBuild {
+        build: 99999,
+        ..V9_2_0_43206
+    };
+
+    /// A later build where `Ping` and `Pong` moved.
+    static REMAPPED: WorldProtocol = WorldProtocol {
+        build: &V9_2_5_TEST,
+        client_opcodes: &[(OpcodeClient::Ping, 0x3770)],
+        server_opcodes: &[(OpcodeServer::Pong, 0x3050)],
+    };
+
+    #[test]
+    fn test_opcode_lookup() {
+        let protocol = WorldProtocol::find(43206).unwrap();
+        assert_eq!(protocol.build.to_string(), "9.2.0.43206");
+        assert!(WorldProtocol::find(1).is_none());
+
+        assert_eq!(
+            protocol.client_opcode(0x3765),
+            Some(OpcodeClient::AuthSession)
+        );
+        assert_eq!(protocol.client_opcode(0), None);
+        assert_eq!(
+            protocol.server_opcode(OpcodeServer::AuthResponse),
+            Some(0x256D)
+        );
+
+        let detected = WorldProtocol::detect(0x3765, OpcodeClient::AuthSession).unwrap();
+        assert_eq!(detected.build, protocol.build);
+        assert!(WorldProtocol::detect(0x3765, OpcodeClient::Ping).is_none());
+    }
+
+    #[test]
+    fn test_opcode_tables() {
+        // The opcode enums carry the 9.2.0.43206 values.
+        let protocol = WorldProtocol::find(43206).unwrap();
+        for (opcode, value) in protocol.client_opcodes {
+            assert_eq!(*opcode as u16, *value, "{:?}", opcode);
+        }
+        for (opcode, value) in protocol.server_opcodes {
+            assert_eq!(*opcode as u16, *value, "{:?}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_remapped_build() {
+        assert_eq!(REMAPPED.client_opcode(0x3770), Some(OpcodeClient::Ping));
+        assert_eq!(REMAPPED.client_opcode(0x3768), None);
+        assert_eq!(WorldProtocol::latest().client_opcode(0x3770), None);
+
+        let data = [0x70, 0x37, 1, 0, 0, 0, 2, 0, 0, 0];
+        assert!(matches!(
+            ClientPacket::parse(&data, &REMAPPED).unwrap(),
+            ClientPacket {
+                opcode: OpcodeClient::Ping,
+                ..
+            }
+        ));
+        assert!(ClientPacket::parse(&data, WorldProtocol::latest()).is_err());
+
+        let mut pong = Pong::from(Ping {
+            serial: 1,
+            latency: 2,
+        });
+        assert_eq!(pong.serialize(&REMAPPED).unwrap()[..2], [0x50, 0x30]);
+        assert_eq!(
+            pong.serialize(WorldProtocol::latest()).unwrap()[..2],
+            [0x4E, 0x30]
+        );
+        assert!(REMAPPED.server_opcode(OpcodeServer::AuthResponse).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! Instance connections. The realm session sends `ConnectTo` with a fresh key, the client opens
+//! a second socket and names that key in `AuthContinuedSession`, then the new socket is handed
+//! over to the realm session once its own handshake is done.
+
+use crate::builds::WorldProtocol;
+use crate::crypt::AES128Companion;
+use crate::opcodes::ConnectionType;
+use crate::packets::auth::ConnectToKey;
+use crate::packets::RawClientPacket;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::WriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use zeroize::Zeroizing;
+
+/// An encrypted instance socket, owned by the realm session it joined.
+pub struct InstanceConnection {
+    pub(crate) addr: SocketAddr,
+    pub(crate) frames: mpsc::Receiver<anyhow::Result<RawClientPacket>>,
+    pub(crate) writer: WriteHalf<TcpStream>,
+    pub(crate) aes_companion: AES128Companion,
+}
+
+/// What the instance socket needs from the realm session to finish its handshake.
+pub struct PendingConnectTo {
+    pub account_name: Option<String>,
+    pub protocol: &'static WorldProtocol,
+    pub session_key: Zeroizing<[u8; 40]>,
+    pub joined: oneshot::Sender<InstanceConnection>,
+}
+
+/// How long a client has to open the socket of a `ConnectTo`.
+const CONNECT_TO_TTL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    expires_at: Instant,
+    pending: PendingConnectTo,
+}
+
+/// `ConnectTo` keys waiting for their socket, shared by every connection of the listener.
+pub struct ConnectToRegistry {
+    next_id: AtomicU32,
+    ttl: Duration,
+    pending: Mutex<HashMap<ConnectToKey, Entry>>,
+}
+
+impl Default for ConnectToRegistry {
+    fn default() -> Self {
+        ConnectToRegistry::new()
+    }
+}
+
+impl ConnectToRegistry {
+    pub fn new() -> ConnectToRegistry {
+        ConnectToRegistry::with_ttl(CONNECT_TO_TTL)
+    }
+
+    pub fn with_ttl(ttl: Duration) -> ConnectToRegistry {
+        ConnectToRegistry {
+            next_id: AtomicU32::new(0),
+            ttl,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn register(&self, pending: PendingConnectTo) -> ConnectToKey {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let key = ConnectToKey::new(
+            id,
+            ConnectionType::ConnectionTypeInstance,
+            rand::thread_rng().gen(),
+        );
+        let now = Instant::now();
+        let mut entries = self.pending.lock().unwrap();
+        // Clients that never connect would otherwise keep their entry for good.
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(
+            key,
+            Entry {
+                expires_at: now + self.ttl,
+                pending,
+            },
+        );
+        key
+    }
+
+    /// Keys are single use, a second socket naming the same key is refused, and so is one
+    /// naming an expired key.
+    pub fn take(&self, key: ConnectToKey) -> Option<PendingConnectTo> {
+        let now = Instant::now();
+        let mut entries = self.pending.lock().unwrap();
+        let entry = entries.remove(&key);
+        entries.retain(|_, entry| entry.expires_at > now);
+        entry
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.pending)
+    }
+
+    pub fn cancel(&self, key: ConnectToKey) {
+        self.take(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt::RSA;
+    use crate::packets::auth::{ConnectTo, ConnectToSerial};
+    use deku::DekuContainerWrite;
+
+    fn pending() -> (PendingConnectTo, oneshot::Receiver<InstanceConnection>) {
+        let (joined, joined_rx) = oneshot::channel();
+        let pending = PendingConnectTo {
+            account_name: Some("test".to_owned()),
+            protocol: WorldProtocol::latest(),
+            session_key: Zeroizing::new([3; 40]),
+            joined,
+        };
+        (pending, joined_rx)
+    }
+
+    #[test]
+    fn test_key_layout() {
+        let key = ConnectToKey::new(0x1234_5678, ConnectionType::ConnectionTypeInstance, !0);
+        assert_eq!(key.raw, 0xFFFF_FFFF_1234_5678);
+        assert_eq!(key.id(), 0x1234_5678);
+        assert_eq!(
+            key.connection_type(),
+            ConnectionType::ConnectionTypeInstance
+        );
+        let key = ConnectToKey::new(7, ConnectionType::ConnectionTypeRealm, 1);
+        assert_eq!(key.raw, 0x2_0000_0007);
+        assert_eq!(key.connection_type(), ConnectionType::ConnectionTypeRealm);
+    }
+
+    #[test]
+    fn test_single_use() {
+        let registry = ConnectToRegistry::new();
+        let (first, _first_rx) = pending();
+        let (second, _second_rx) = pending();
+        let first = registry.register(first);
+        let second = registry.register(second);
+        assert_ne!(first, second);
+        assert_eq!(
+            first.connection_type(),
+            ConnectionType::ConnectionTypeInstance
+        );
+
+        let taken = registry.take(first).unwrap();
+        assert_eq!(*taken.session_key, [3; 40]);
+        assert!(registry.take(first).is_none());
+        registry.cancel(second);
+        assert!(registry.take(second).is_none());
+    }
+
+    #[test]
+    fn test_expired_keys() {
+        let registry = ConnectToRegistry::with_ttl(Duration::ZERO);
+        let (first, _first_rx) = pending();
+        let first = registry.register(first);
+        assert!(registry.take(first).is_none());
+
+        let (second, _second_rx) = pending();
+        registry.register(second);
+        let (third, _third_rx) = pending();
+        registry.register(third);
+        assert_eq!(registry.pending.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_connect_to_layout() {
+        let key = ConnectToKey::new(1, ConnectionType::ConnectionTypeInstance, 2);
+        let address = ([127, 0, 0, 1], 9900).into();
+        let connect_to = ConnectTo::new(
+            &RSA::bundled().unwrap(),
+            address,
+            ConnectToSerial::WorldAttempt1,
+            key,
+        )
+        .unwrap();
+        let bytes = connect_to.to_bytes().unwrap();
+        assert_eq!(
+            &bytes[256..],
+            &[1, 127, 0, 0, 1, 0xAC, 0x26, 17, 0, 0, 0, 1, 1, 0, 0, 0, 5, 0, 0, 0]
+        );
+    }
+}
@@ -52,3 +52,6 @@ pub const REALM_WIN_AUTH_SEED: [u8; 16] = [
 pub const ENABLE_ENCRYPTION_SEED: [u8; 16] = [
     0x90, 0x9C, 0xD0, 0x50, 0x5A, 0x2C, 0x14, 0xDD, 0x5C, 0x2C, 0xC0, 0x64, 0x14, 0xF3, 0xFE, 0xC9
 ]; // reversed from client
+
+/// Clients never send bigger packets, anything above is a desynced or hostile stream.
+pub const MAX_CLIENT_PACKET_SIZE: u32 = 0x40000;
@@ -1,56 +1,112 @@
 use crate::constants::SERVER_PRIVATE_KEY;
-use boring_sys::{
-    EVP_CIPHER_CTX_ctrl, EVP_CIPHER_CTX_init, EVP_CIPHER_CTX_new, EVP_CipherFinal_ex,
-    EVP_CipherInit_ex, EVP_CipherUpdate, EVP_aes_128_gcm, EVP_CTRL_GCM_GET_TAG,
-    EVP_CTRL_GCM_SET_TAG,
-};
+use aes::Aes128;
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{AesGcm, KeyInit};
 use bytes::Bytes;
-use std::ffi::c_void;
-use std::os::raw::{c_int, c_uint};
+use once_cell::sync::OnceCell;
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey, LineEnding};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{Hash, PaddingScheme, PublicKeyParts, RsaPrivateKey};
+use rustycraft_common::config::WorldConfig;
+use std::path::Path;
+use zeroize::Zeroizing;
 
-pub struct RSA {
-    c_rsa: *mut boring_sys::RSA,
+/// Packets carry 12 byte tags and 12 byte nonces.
+type Aes128Gcm = AesGcm<Aes128, U12, U12>;
+
+pub const AES_TAG_SIZE: usize = 12;
+
+/// The client expects a 2048 bit signature.
+pub const RSA_KEY_BITS: usize = 2048;
+
+static SIGNING_KEY: OnceCell<RSA> = OnceCell::new();
+
+/// Loads the key configured in `world`, call once at startup before accepting sessions.
+pub fn init_signing_key(world: &WorldConfig) -> anyhow::Result<&'static RSA> {
+    SIGNING_KEY.get_or_try_init(|| match &world.rsa_key_path {
+        Some(path) => RSA::load(path),
+        None if world.use_bundled_rsa_key => {
+            log::warn!(target: "WorldServer", "Signing with the bundled TrinityCore RSA key");
+            RSA::bundled()
+        }
+        None => bail!(
+            "No world RSA key configured: set world.rsa_key_path to a key created with \
+             `world_rsa_key generate`, or world.use_bundled_rsa_key for development"
+        ),
+    })
+}
+
+pub fn signing_key() -> anyhow::Result<&'static RSA> {
+    SIGNING_KEY
+        .get()
+        .ok_or_else(|| anyhow!("World RSA key is not initialized"))
 }
 
-lazy_static! {
-    pub static ref INITIALIZED_RSA: RSA = RSA::new();
+pub struct RSA {
+    key: RsaPrivateKey,
 }
 
 impl RSA {
-    fn new() -> RSA {
-        unsafe {
-            let mut rsa = boring_sys::RSA_new();
-            let rsa_pk: *const c_void = std::mem::transmute(SERVER_PRIVATE_KEY);
-            let pk_bio = boring_sys::BIO_new_mem_buf(rsa_pk, SERVER_PRIVATE_KEY.len() as i32);
-            boring_sys::PEM_read_bio_RSAPrivateKey(
-                pk_bio,
-                &mut rsa as *mut *mut boring_sys::RSA,
-                None,
-                std::ptr::null_mut(),
-            );
-            RSA { c_rsa: rsa }
-        }
+    /// TrinityCore's key. Its private half is published, so it only proves anything to clients
+    /// patched by the same people who run the server.
+    pub fn bundled() -> anyhow::Result<RSA> {
+        let pem = std::str::from_utf8(SERVER_PRIVATE_KEY)?.trim_end_matches('\0');
+        RSA::from_pem(pem)
     }
 
-    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
-        let mut signature = vec![0; 256];
-        let mut siglen = 0 as c_uint;
-        unsafe {
-            boring_sys::RSA_sign(
-                boring_sys::NID_sha256,
-                data.as_ptr(),
-                data.len() as c_uint,
-                signature.as_mut_ptr(),
-                &mut siglen as *mut c_uint,
-                self.c_rsa,
-            );
-        };
+    pub fn generate() -> anyhow::Result<RSA> {
+        Ok(RSA {
+            key: RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS)?,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<RSA> {
+        let path = path.as_ref();
+        let pem = Zeroizing::new(
+            std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("Could not read RSA key {}: {}", path.display(), e))?,
+        );
+        RSA::from_pem(&pem).map_err(|e| anyhow!("Invalid RSA key {}: {}", path.display(), e))
+    }
+
+    /// Accepts PKCS#1 (`BEGIN RSA PRIVATE KEY`) and PKCS#8 (`BEGIN PRIVATE KEY`) PEM.
+    pub fn from_pem(pem: &str) -> anyhow::Result<RSA> {
+        let key = RsaPrivateKey::from_pkcs1_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+            .map_err(|e| anyhow!("Expected a PKCS#1 or PKCS#8 PEM key: {}", e))?;
+        ensure!(
+            key.size() * 8 == RSA_KEY_BITS,
+            "Expected a {} bit key, got {} bits",
+            RSA_KEY_BITS,
+            key.size() * 8
+        );
+        Ok(RSA { key })
+    }
+
+    pub fn to_pkcs1_pem(&self) -> anyhow::Result<Zeroizing<String>> {
+        Ok(self.key.to_pkcs1_pem(LineEnding::LF)?)
+    }
+
+    /// The modulus as the client stores it, little endian.
+    pub fn modulus_le(&self) -> Vec<u8> {
+        let mut modulus = self.key.n().to_bytes_le();
+        modulus.resize(self.key.size(), 0);
+        modulus
+    }
+
+    /// PKCS#1 v1.5 signature of the SHA-256 `digest`, little endian like the client reads it.
+    pub fn sign(&self, digest: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut signature = self.key.sign_blinded(
+            &mut rand::thread_rng(),
+            PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+            digest,
+        )?;
         signature.reverse();
-        signature
+        Ok(signature)
     }
 }
-unsafe impl Send for RSA {}
-unsafe impl Sync for RSA {}
 
 #[derive(Debug)]
 pub struct EncryptionResult {
@@ -58,165 +114,65 @@ pub struct EncryptionResult {
     pub aes_tag: Vec<u8>,
 }
 
-#[derive(Debug)]
-pub struct AES128 {
-    ctx: *mut boring_sys::EVP_CIPHER_CTX,
+/// One direction of the connection. Packets are sent in the clear until a key is set, but
+/// still count towards the nonce.
+struct AES128 {
+    cipher: Option<Aes128Gcm>,
     magic: u32,
     counter: u64,
-    initialized: bool,
-    encrypting: bool,
 }
 
 impl AES128 {
-    fn new(encrypting: bool, magic: u32) -> AES128 {
-        unsafe {
-            let ctx = EVP_CIPHER_CTX_new();
-            EVP_CIPHER_CTX_init(ctx);
-            let res = EVP_CipherInit_ex(
-                ctx,
-                EVP_aes_128_gcm(),
-                std::ptr::null_mut(),
-                std::ptr::null(),
-                std::ptr::null(),
-                {
-                    if encrypting {
-                        1
-                    } else {
-                        0
-                    }
-                },
-            );
-            assert_ne!(res, 0 as c_int);
-            AES128 {
-                ctx,
-                magic,
-                counter: 0,
-                initialized: false,
-                encrypting,
-            }
+    fn new(magic: u32) -> AES128 {
+        AES128 {
+            cipher: None,
+            magic,
+            counter: 0,
         }
     }
 
-    fn get_iv(&self) -> Vec<u8> {
-        let mut iv = vec![];
-        iv.extend(self.counter.to_le_bytes());
-        iv.extend(self.magic.to_le_bytes());
-        iv
+    fn nonce(&self) -> GenericArray<u8, U12> {
+        let mut nonce = GenericArray::default();
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        nonce[8..].copy_from_slice(&self.magic.to_le_bytes());
+        nonce
     }
 
-    fn init(&mut self, key: &[u8]) -> anyhow::Result<()> {
-        unsafe {
-            let res = EVP_CipherInit_ex(
-                self.ctx,
-                std::ptr::null(),
-                std::ptr::null_mut(),
-                key.as_ptr(),
-                std::ptr::null(),
-                -1,
-            );
-            assert_ne!(res, 0);
-        };
-        self.initialized = true;
-        Ok(())
+    fn init(&mut self, key: &[u8]) {
+        self.cipher = Some(Aes128Gcm::new(GenericArray::from_slice(key)));
     }
 
     fn decrypt(&mut self, data: &[u8], tag: &[u8]) -> anyhow::Result<Vec<u8>> {
-        assert_eq!(self.encrypting, false);
-        let out = if self.initialized {
-            let mut tag = tag.to_vec();
-            let len = data.len() as i32;
-            let mut out = data.clone().to_vec();
-            let mut _out_len = 0;
-            let iv = self.get_iv();
-            unsafe {
-                let res = EVP_CipherInit_ex(
-                    self.ctx,
-                    std::ptr::null(),
-                    std::ptr::null_mut(),
-                    std::ptr::null(),
-                    iv.as_ptr(),
-                    -1,
-                );
-                assert_ne!(res, 0);
-                let res = EVP_CipherUpdate(
-                    self.ctx,
-                    out.as_mut_ptr(),
-                    &mut _out_len as *mut c_int,
-                    data.as_ptr(),
-                    len as c_int,
-                );
-                assert_ne!(res, 0);
-                let res = EVP_CIPHER_CTX_ctrl(
-                    self.ctx,
-                    EVP_CTRL_GCM_SET_TAG,
-                    tag.len() as c_int,
-                    tag.as_mut_ptr() as *mut c_void,
-                );
-                assert_ne!(res, 0);
-
-                let res = EVP_CipherFinal_ex(
-                    self.ctx,
-                    out.as_mut_ptr().offset(_out_len as isize),
-                    &mut _out_len as *mut c_int,
-                );
-                assert_ne!(res, 0);
-            };
-            out
-        } else {
-            data.to_vec()
-        };
+        let mut out = data.to_vec();
+        if let Some(cipher) = &self.cipher {
+            ensure!(
+                tag.len() == AES_TAG_SIZE,
+                "Expected a {} byte tag, got {}",
+                AES_TAG_SIZE,
+                tag.len()
+            );
+            cipher
+                .decrypt_in_place_detached(
+                    &self.nonce(),
+                    &[],
+                    &mut out,
+                    GenericArray::from_slice(tag),
+                )
+                .map_err(|_| anyhow!("Packet {} failed authentication", self.counter))?;
+        }
         self.counter += 1;
         Ok(out)
     }
 
     fn encrypt(&mut self, data: &[u8]) -> anyhow::Result<EncryptionResult> {
-        assert_eq!(self.encrypting, true);
-        let mut tag = vec![0; 12];
-        let out = if self.initialized {
-            let len = data.len() as i32;
-            let mut out = Vec::with_capacity(data.len());
-            out.extend((0..data.len()).map(|_| 0));
-            let mut _out_len = 0;
-            let iv = self.get_iv();
-            unsafe {
-                let res = EVP_CipherInit_ex(
-                    self.ctx,
-                    std::ptr::null(),
-                    std::ptr::null_mut(),
-                    std::ptr::null(),
-                    iv.as_ptr(),
-                    -1,
-                );
-                assert_ne!(res, 0);
-                let res = EVP_CipherUpdate(
-                    self.ctx,
-                    out.as_mut_ptr(),
-                    &mut _out_len as *mut c_int,
-                    data.as_ptr(),
-                    len as c_int,
-                );
-                assert_ne!(res, 0);
-
-                let res = EVP_CipherFinal_ex(
-                    self.ctx,
-                    out.as_mut_ptr().offset(_out_len as isize),
-                    &mut _out_len as *mut c_int,
-                );
-                assert_ne!(res, 0);
-
-                let res = EVP_CIPHER_CTX_ctrl(
-                    self.ctx,
-                    EVP_CTRL_GCM_GET_TAG,
-                    tag.len() as c_int,
-                    tag.as_mut_ptr() as *mut c_void,
-                );
-                assert_ne!(res, 0);
-                out
-            }
-        } else {
-            data.to_vec()
+        let mut out = data.to_vec();
+        let tag = match &self.cipher {
+            Some(cipher) => cipher
+                .encrypt_in_place_detached(&self.nonce(), &[], &mut out)
+                .map_err(|_| anyhow!("Packet {} could not be encrypted", self.counter))?
+                .to_vec(),
+            None => vec![0; AES_TAG_SIZE],
         };
-
         self.counter += 1;
         Ok(EncryptionResult {
             cipher_text: out.into(),
@@ -225,26 +181,27 @@ impl AES128 {
     }
 }
 
+/// `WorldPacketCrypt`, AES-128-GCM keyed with the session encryption key. The nonce is the
+/// packet counter followed by "SRVR" for server packets or "CLNT" for client packets.
+///
+/// The expanded keys are wiped when the companion is dropped.
 pub struct AES128Companion {
     server_encrypt: AES128,
     client_decrypt: AES128,
-    initialized: bool,
 }
 
 impl AES128Companion {
     pub fn new() -> AES128Companion {
         AES128Companion {
-            server_encrypt: AES128::new(true, 0x52565253),
-            client_decrypt: AES128::new(false, 0x544E4C43),
-            initialized: false,
+            server_encrypt: AES128::new(0x52565253),
+            client_decrypt: AES128::new(0x544E4C43),
         }
     }
 
     pub fn init(&mut self, key: &[u8]) -> anyhow::Result<()> {
-        assert_eq!(key.len(), 16);
-        self.server_encrypt.init(key)?;
-        self.client_decrypt.init(key)?;
-        self.initialized = true;
+        ensure!(key.len() == 16, "Expected a 16 byte key, got {}", key.len());
+        self.server_encrypt.init(key);
+        self.client_decrypt.init(key);
         Ok(())
     }
 
@@ -257,5 +214,100 @@ impl AES128Companion {
     }
 }
 
-unsafe impl Send for AES128 {}
-unsafe impl Sync for AES128 {}
+impl Default for AES128Companion {
+    fn default() -> Self {
+        AES128Companion::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The client side of a connection: encrypts with "CLNT" and decrypts with "SRVR".
+    fn client(key: &[u8]) -> (AES128, AES128) {
+        let mut encrypt = AES128::new(0x544E4C43);
+        let mut decrypt = AES128::new(0x52565253);
+        encrypt.init(key);
+        decrypt.init(key);
+        (encrypt, decrypt)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key = [7; 16];
+        let mut server = AES128Companion::new();
+        server.init(&key).unwrap();
+        let (mut client_encrypt, mut client_decrypt) = client(&key);
+
+        for i in 0..3u8 {
+            let sent = server.encrypt(&[i; 40]).unwrap();
+            assert_ne!(&sent.cipher_text[..], &[i; 40]);
+            let received = client_decrypt
+                .decrypt(&sent.cipher_text, &sent.aes_tag)
+                .unwrap();
+            assert_eq!(received, vec![i; 40]);
+
+            let sent = client_encrypt.encrypt(&[i; 10]).unwrap();
+            let received = server.decrypt(&sent.cipher_text, &sent.aes_tag).unwrap();
+            assert_eq!(received, vec![i; 10]);
+        }
+    }
+
+    #[test]
+    fn test_authentication_failure() {
+        let key = [7; 16];
+        let mut server = AES128Companion::new();
+        server.init(&key).unwrap();
+        let (mut client_encrypt, _) = client(&key);
+
+        let sent = client_encrypt.encrypt(b"hello").unwrap();
+        let mut tampered = sent.cipher_text.to_vec();
+        tampered[0] ^= 1;
+        assert!(server.decrypt(&tampered, &sent.aes_tag).is_err());
+        assert!(server
+            .decrypt(&sent.cipher_text, &sent.aes_tag[..4])
+            .is_err());
+    }
+
+    #[test]
+    fn test_counter_before_init() {
+        let key = [7; 16];
+        let mut server = AES128Companion::new();
+        let plain = server.encrypt(b"hello").unwrap();
+        assert_eq!(&plain.cipher_text[..], b"hello");
+        server.init(&key).unwrap();
+
+        let sent = server.encrypt(b"world").unwrap();
+        // The first encrypted packet already uses counter 1.
+        let (_, mut fresh) = client(&key);
+        assert!(fresh.decrypt(&sent.cipher_text, &sent.aes_tag).is_err());
+
+        let mut client_decrypt = AES128::new(0x52565253);
+        client_decrypt
+            .decrypt(&plain.cipher_text, &plain.aes_tag)
+            .unwrap();
+        client_decrypt.init(&key);
+        let received = client_decrypt
+            .decrypt(&sent.cipher_text, &sent.aes_tag)
+            .unwrap();
+        assert_eq!(received, b"world");
+    }
+
+    #[test]
+    fn test_sign() {
+        let rsa = RSA::bundled().unwrap();
+        let signature = rsa.sign(&[1; 32]).unwrap();
+        assert_eq!(signature.len(), 256);
+        assert_eq!(signature, rsa.sign(&[1; 32]).unwrap());
+    }
+
+    #[test]
+    fn test_pem_round_trip() {
+        let rsa = RSA::bundled().unwrap();
+        let reloaded = RSA::from_pem(&rsa.to_pkcs1_pem().unwrap()).unwrap();
+        assert_eq!(reloaded.modulus_le(), rsa.modulus_le());
+        assert_eq!(rsa.modulus_le().len(), 256);
+        assert!(RSA::from_pem("not a key").is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use rustycraft_db2::tables::{ChrClasses, ChrRaces, Item, Map, SpellName};
+use rustycraft_db2::Db2Store;
+use std::path::Path;
+
+/// Client tables the world server reads its static data from.
+pub struct GameData {
+    pub maps: Db2Store<Map>,
+    pub races: Db2Store<ChrRaces>,
+    pub classes: Db2Store<ChrClasses>,
+    pub items: Db2Store<Item>,
+    pub spell_names: Db2Store<SpellName>,
+}
+
+impl GameData {
+    pub fn load(data_dir: &Path) -> anyhow::Result<GameData> {
+        Ok(GameData {
+            maps: Db2Store::load(data_dir)?,
+            races: Db2Store::load(data_dir)?,
+            classes: Db2Store::load(data_dir)?,
+            items: Db2Store::load(data_dir)?,
+            spell_names: Db2Store::load(data_dir)?,
+        })
+    }
+}
+
+impl std::fmt::Debug for GameData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GameData")
+            .field("maps", &self.maps.len())
+            .field("races", &self.races.len())
+            .field("classes", &self.classes.len())
+            .field("items", &self.items.len())
+            .field("spell_names", &self.spell_names.len())
+            .finish()
+    }
+}
@@ -0,0 +1,549 @@
+//! 128 bit object ids, `ObjectGuid` in TrinityCore.
+
+use crate::packets::wire::PackedGuid;
+use deku::bitvec::{BitSlice, BitVec, Msb0};
+use deku::prelude::*;
+use rustycraft_common::config;
+use rustycraft_database::redis::{RedisClient, Storable};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Counters reserved from Redis at once, at most this many ids are skipped by a restart.
+const GUID_BLOCK_SIZE: u64 = 1000;
+
+/// Counters of world objects only have 40 bits, the other 24 bits of the low half hold the
+/// server id.
+const WORLD_OBJECT_MAX_COUNTER: u64 = 0xFF_FFFF_FFFF;
+
+/// `HighGuid`, the kind of object, stored in the top 6 bits of a guid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum HighGuid {
+    Null = 0,
+    Uniq = 1,
+    Player = 2,
+    Item = 3,
+    WorldTransaction = 4,
+    StaticDoor = 5,
+    Transport = 6,
+    Conversation = 7,
+    Creature = 8,
+    Vehicle = 9,
+    Pet = 10,
+    GameObject = 11,
+    DynamicObject = 12,
+    AreaTrigger = 13,
+    Corpse = 14,
+    LootObject = 15,
+    SceneObject = 16,
+    Scenario = 17,
+    AiGroup = 18,
+    DynamicDoor = 19,
+    ClientActor = 20,
+    Vignette = 21,
+    CallForHelp = 22,
+    AiResource = 23,
+    AiLock = 24,
+    AiLockTicket = 25,
+    ChatChannel = 26,
+    Party = 27,
+    Guild = 28,
+    WowAccount = 29,
+    BNetAccount = 30,
+    GmTask = 31,
+    MobileSession = 32,
+    RaidGroup = 33,
+    Spell = 34,
+    Mail = 35,
+    WebObj = 36,
+    LfgObject = 37,
+    LfgList = 38,
+    UserRouter = 39,
+    PvpQueueGroup = 40,
+    UserClient = 41,
+    PetBattle = 42,
+    UniqUserClient = 43,
+    BattlePet = 44,
+    CommerceObj = 45,
+    ClientSession = 46,
+    Cast = 47,
+    ClientConnection = 48,
+    ClubFinder = 49,
+}
+
+impl HighGuid {
+    const ALL: [HighGuid; 50] = [
+        HighGuid::Null,
+        HighGuid::Uniq,
+        HighGuid::Player,
+        HighGuid::Item,
+        HighGuid::WorldTransaction,
+        HighGuid::StaticDoor,
+        HighGuid::Transport,
+        HighGuid::Conversation,
+        HighGuid::Creature,
+        HighGuid::Vehicle,
+        HighGuid::Pet,
+        HighGuid::GameObject,
+        HighGuid::DynamicObject,
+        HighGuid::AreaTrigger,
+        HighGuid::Corpse,
+        HighGuid::LootObject,
+        HighGuid::SceneObject,
+        HighGuid::Scenario,
+        HighGuid::AiGroup,
+        HighGuid::DynamicDoor,
+        HighGuid::ClientActor,
+        HighGuid::Vignette,
+        HighGuid::CallForHelp,
+        HighGuid::AiResource,
+        HighGuid::AiLock,
+        HighGuid::AiLockTicket,
+        HighGuid::ChatChannel,
+        HighGuid::Party,
+        HighGuid::Guild,
+        HighGuid::WowAccount,
+        HighGuid::BNetAccount,
+        HighGuid::GmTask,
+        HighGuid::MobileSession,
+        HighGuid::RaidGroup,
+        HighGuid::Spell,
+        HighGuid::Mail,
+        HighGuid::WebObj,
+        HighGuid::LfgObject,
+        HighGuid::LfgList,
+        HighGuid::UserRouter,
+        HighGuid::PvpQueueGroup,
+        HighGuid::UserClient,
+        HighGuid::PetBattle,
+        HighGuid::UniqUserClient,
+        HighGuid::BattlePet,
+        HighGuid::CommerceObj,
+        HighGuid::ClientSession,
+        HighGuid::Cast,
+        HighGuid::ClientConnection,
+        HighGuid::ClubFinder,
+    ];
+
+    /// Whether guids of this kind carry a map, an entry and a 40 bit counter.
+    pub fn is_world_object(self) -> bool {
+        matches!(
+            self,
+            HighGuid::WorldTransaction
+                | HighGuid::Conversation
+                | HighGuid::Creature
+                | HighGuid::Vehicle
+                | HighGuid::Pet
+                | HighGuid::GameObject
+                | HighGuid::DynamicObject
+                | HighGuid::AreaTrigger
+                | HighGuid::Corpse
+                | HighGuid::LootObject
+                | HighGuid::SceneObject
+                | HighGuid::Scenario
+                | HighGuid::AiGroup
+                | HighGuid::DynamicDoor
+                | HighGuid::Vignette
+                | HighGuid::CallForHelp
+                | HighGuid::AiResource
+                | HighGuid::AiLock
+                | HighGuid::AiLockTicket
+        )
+    }
+
+    fn max_counter(self) -> u64 {
+        if self.is_world_object() {
+            WORLD_OBJECT_MAX_COUNTER
+        } else {
+            // Redis counters are signed.
+            i64::MAX as u64
+        }
+    }
+}
+
+impl TryFrom<u8> for HighGuid {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        HighGuid::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown high guid {}", value))
+    }
+}
+
+/// Layout of the high half, from the top: 6 bits of type, 13 of realm, 13 of map, 23 of entry
+/// and 6 of sub type. The low half is the counter, world objects keep their server id in its
+/// top 24 bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectGuid {
+    high: u64,
+    low: u64,
+}
+
+impl ObjectGuid {
+    pub const EMPTY: ObjectGuid = ObjectGuid { high: 0, low: 0 };
+
+    pub fn new(high: u64, low: u64) -> ObjectGuid {
+        ObjectGuid { high, low }
+    }
+
+    pub fn create_player(realm_id: u32, counter: u64) -> ObjectGuid {
+        ObjectGuid::create_global(HighGuid::Player, realm_id, counter)
+    }
+
+    pub fn create_item(realm_id: u32, counter: u64) -> ObjectGuid {
+        ObjectGuid::create_global(HighGuid::Item, realm_id, counter)
+    }
+
+    pub fn create_uniq(id: u64) -> ObjectGuid {
+        ObjectGuid::new((HighGuid::Uniq as u64) << 58, id)
+    }
+
+    fn create_global(high_type: HighGuid, realm_id: u32, counter: u64) -> ObjectGuid {
+        ObjectGuid::new(
+            (high_type as u64) << 58 | (realm_id as u64 & 0x1FFF) << 42,
+            counter,
+        )
+    }
+
+    pub fn create_world_object(
+        high_type: HighGuid,
+        sub_type: u8,
+        realm_id: u32,
+        map_id: u32,
+        server_id: u32,
+        entry: u32,
+        counter: u64,
+    ) -> ObjectGuid {
+        ObjectGuid::new(
+            (high_type as u64) << 58
+                | (realm_id as u64 & 0x1FFF) << 42
+                | (map_id as u64 & 0x1FFF) << 29
+                | (entry as u64 & 0x7F_FFFF) << 6
+                | (sub_type as u64 & 0x3F),
+            (server_id as u64 & 0xFF_FFFF) << 40 | (counter & WORLD_OBJECT_MAX_COUNTER),
+        )
+    }
+
+    pub fn high(&self) -> u64 {
+        self.high
+    }
+
+    pub fn low(&self) -> u64 {
+        self.low
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == ObjectGuid::EMPTY
+    }
+
+    /// `None` for a type this build does not know.
+    pub fn high_type(&self) -> Option<HighGuid> {
+        HighGuid::try_from((self.high >> 58) as u8).ok()
+    }
+
+    pub fn realm_id(&self) -> u32 {
+        ((self.high >> 42) & 0x1FFF) as u32
+    }
+
+    pub fn map_id(&self) -> u32 {
+        ((self.high >> 29) & 0x1FFF) as u32
+    }
+
+    pub fn entry(&self) -> u32 {
+        ((self.high >> 6) & 0x7F_FFFF) as u32
+    }
+
+    pub fn sub_type(&self) -> u8 {
+        (self.high & 0x3F) as u8
+    }
+
+    pub fn server_id(&self) -> u32 {
+        match self.high_type() {
+            Some(high_type) if high_type.is_world_object() => (self.low >> 40) as u32,
+            _ => 0,
+        }
+    }
+
+    pub fn counter(&self) -> u64 {
+        match self.high_type() {
+            Some(high_type) if high_type.is_world_object() => self.low & WORLD_OBJECT_MAX_COUNTER,
+            _ => self.low,
+        }
+    }
+}
+
+impl fmt::Display for ObjectGuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.high_type() {
+            Some(high_type) => write!(f, "{:?}", high_type)?,
+            None => write!(f, "Unknown")?,
+        }
+        write!(
+            f,
+            "-{}-{}-{}-{:X}",
+            self.realm_id(),
+            self.map_id(),
+            self.entry(),
+            self.counter()
+        )
+    }
+}
+
+impl From<ObjectGuid> for PackedGuid {
+    fn from(guid: ObjectGuid) -> Self {
+        PackedGuid::new(guid.low, guid.high)
+    }
+}
+
+impl From<PackedGuid> for ObjectGuid {
+    fn from(guid: PackedGuid) -> Self {
+        ObjectGuid::new(guid.high, guid.low)
+    }
+}
+
+/// Always packed, the way packets carry guids.
+impl DekuRead<'_> for ObjectGuid {
+    fn read(input: &BitSlice<Msb0, u8>, _: ()) -> Result<(&BitSlice<Msb0, u8>, Self), DekuError> {
+        let (rest, guid) = PackedGuid::read(input, ())?;
+        Ok((rest, guid.into()))
+    }
+}
+
+impl DekuWrite for ObjectGuid {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, _: ()) -> Result<(), DekuError> {
+        PackedGuid::from(*self).write(output, ())
+    }
+}
+
+/// Last counter reserved for a guid type, keyed by realm and type.
+struct GuidCounter;
+
+impl Storable for GuidCounter {
+    fn key_prefix() -> &'static str {
+        "guid_counter"
+    }
+}
+
+/// Hands out the counters of one guid type from blocks reserved in Redis, so ids stay unique
+/// across restarts and between world servers of the same realm.
+#[derive(Debug)]
+pub struct ObjectGuidGenerator {
+    high_type: HighGuid,
+    next: u64,
+    end: u64,
+}
+
+impl ObjectGuidGenerator {
+    pub fn new(high_type: HighGuid) -> ObjectGuidGenerator {
+        ObjectGuidGenerator {
+            high_type,
+            next: 0,
+            end: 0,
+        }
+    }
+
+    pub async fn generate(&mut self, redis: &RedisClient, realm_id: u32) -> anyhow::Result<u64> {
+        if self.next == self.end {
+            let key = format!("{}_{:?}", realm_id, self.high_type);
+            let end = redis.incr_by::<GuidCounter>(&key, GUID_BLOCK_SIZE).await?;
+            self.next = end - GUID_BLOCK_SIZE + 1;
+            self.end = end + 1;
+        }
+        ensure!(
+            self.next <= self.high_type.max_counter(),
+            "{:?} guids are exhausted",
+            self.high_type
+        );
+        let counter = self.next;
+        self.next += 1;
+        Ok(counter)
+    }
+
+    /// Gives the unused end of the current block back, unless another world server reserved
+    /// a block since. Returns whether it did.
+    pub async fn release(&mut self, redis: &RedisClient, realm_id: u32) -> anyhow::Result<bool> {
+        if self.next == self.end {
+            return Ok(false);
+        }
+        let key = format!("{}_{:?}", realm_id, self.high_type);
+        let released = redis
+            .set_counter_if_eq::<GuidCounter>(&key, self.end - 1, self.next - 1)
+            .await?;
+        self.next = self.end;
+        Ok(released)
+    }
+}
+
+/// A generator per guid type for the realm this world server hosts.
+pub struct GuidGenerators {
+    redis: RedisClient,
+    realm_id: u32,
+    generators: HashMap<HighGuid, ObjectGuidGenerator>,
+}
+
+impl fmt::Debug for GuidGenerators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuidGenerators")
+            .field("realm_id", &self.realm_id)
+            .field("generators", &self.generators)
+            .finish()
+    }
+}
+
+impl GuidGenerators {
+    pub fn new() -> anyhow::Result<GuidGenerators> {
+        Ok(GuidGenerators::for_realm(
+            RedisClient::new(&config::get().redis.url)?,
+            config::get().world.realm_id,
+        ))
+    }
+
+    pub fn for_realm(redis: RedisClient, realm_id: u32) -> GuidGenerators {
+        GuidGenerators {
+            redis,
+            // Guids only have 13 bits for the realm, counters are kept under the same id so
+            // realms that share it never hand out the same guid.
+            realm_id: realm_id & 0x1FFF,
+            generators: HashMap::new(),
+        }
+    }
+
+    pub async fn generate(&mut self, high_type: HighGuid) -> anyhow::Result<u64> {
+        self.generators
+            .entry(high_type)
+            .or_insert_with(|| ObjectGuidGenerator::new(high_type))
+            .generate(&self.redis, self.realm_id)
+            .await
+    }
+
+    /// Persists the counters of every type, called once nothing generates guids anymore.
+    pub async fn flush(&mut self) -> anyhow::Result<()> {
+        for generator in self.generators.values_mut() {
+            if generator.release(&self.redis, self.realm_id).await? {
+                debug!(target: "WorldServer", "Released unused {:?} guids", generator.high_type);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn player(&mut self) -> anyhow::Result<ObjectGuid> {
+        let counter = self.generate(HighGuid::Player).await?;
+        Ok(ObjectGuid::create_player(self.realm_id, counter))
+    }
+
+    pub async fn item(&mut self) -> anyhow::Result<ObjectGuid> {
+        let counter = self.generate(HighGuid::Item).await?;
+        Ok(ObjectGuid::create_item(self.realm_id, counter))
+    }
+
+    pub async fn creature(&mut self, map_id: u32, entry: u32) -> anyhow::Result<ObjectGuid> {
+        self.world_object(HighGuid::Creature, map_id, entry).await
+    }
+
+    pub async fn game_object(&mut self, map_id: u32, entry: u32) -> anyhow::Result<ObjectGuid> {
+        self.world_object(HighGuid::GameObject, map_id, entry).await
+    }
+
+    async fn world_object(
+        &mut self,
+        high_type: HighGuid,
+        map_id: u32,
+        entry: u32,
+    ) -> anyhow::Result<ObjectGuid> {
+        let counter = self.generate(high_type).await?;
+        Ok(ObjectGuid::create_world_object(
+            high_type,
+            0,
+            self.realm_id,
+            map_id,
+            0,
+            entry,
+            counter,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_world_object_fields() {
+        let guid = ObjectGuid::create_world_object(HighGuid::Creature, 5, 1, 571, 3, 12345, 0x42);
+        assert_eq!(guid.high_type(), Some(HighGuid::Creature));
+        assert_eq!(guid.sub_type(), 5);
+        assert_eq!(guid.realm_id(), 1);
+        assert_eq!(guid.map_id(), 571);
+        assert_eq!(guid.server_id(), 3);
+        assert_eq!(guid.entry(), 12345);
+        assert_eq!(guid.counter(), 0x42);
+        assert_eq!(guid.to_string(), "Creature-1-571-12345-42");
+    }
+
+    #[test]
+    fn test_player_fields() {
+        let guid = ObjectGuid::create_player(1, 0x0123_4567_89AB);
+        assert_eq!(guid.high_type(), Some(HighGuid::Player));
+        assert_eq!(guid.realm_id(), 1);
+        assert_eq!(guid.map_id(), 0);
+        assert_eq!(guid.counter(), 0x0123_4567_89AB);
+        assert_eq!(guid.high(), 0x0800_0400_0000_0000);
+        assert!(ObjectGuid::EMPTY.is_empty());
+        assert_eq!(ObjectGuid::EMPTY.high_type(), Some(HighGuid::Null));
+    }
+
+    #[test]
+    fn test_generators_realm_id() {
+        let redis = RedisClient::new("redis://127.0.0.1").unwrap();
+        let generators = GuidGenerators::for_realm(redis, 0x2401);
+        assert_eq!(generators.realm_id, 0x401);
+        let guid = ObjectGuid::create_player(generators.realm_id, 1);
+        assert_eq!(guid.realm_id(), 0x401);
+        assert_eq!(guid.counter(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server, set REDIS_URL"]
+    async fn test_release_unused_block() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_owned());
+        let redis = RedisClient::new(&url).unwrap();
+        // A realm id no other test uses, counters persist between runs.
+        let realm_id = 0x1000 | (unix_nanos() as u32 & 0xFFF);
+        let mut generator = ObjectGuidGenerator::new(HighGuid::Item);
+        let first = generator.generate(&redis, realm_id).await.unwrap();
+        assert!(generator.release(&redis, realm_id).await.unwrap());
+        let mut restarted = ObjectGuidGenerator::new(HighGuid::Item);
+        assert_eq!(
+            restarted.generate(&redis, realm_id).await.unwrap(),
+            first + 1
+        );
+
+        // Another block was reserved since, the released end would overlap it.
+        let mut other = ObjectGuidGenerator::new(HighGuid::Item);
+        other.generate(&redis, realm_id).await.unwrap();
+        assert!(!restarted.release(&redis, realm_id).await.unwrap());
+    }
+
+    fn unix_nanos() -> u128 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        let guid = ObjectGuid::create_player(1, 0x2A);
+        let mut output = BitVec::new();
+        guid.write(&mut output, ()).unwrap();
+        assert_eq!(
+            output.as_raw_slice(),
+            &[0b0000_0001, 0b1010_0000, 0x2A, 0x04, 0x08]
+        );
+        let (rest, read) = ObjectGuid::read(output.as_bitslice(), ()).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(read, guid);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::builds::WorldProtocol;
+use crate::opcodes::OpcodeClient;
+use crate::packets::auth::{
+    AuthContinuedSession, AuthSession, ConnectToFailed, ConnectToSerial, EnterEncryptedModeAck,
+    Ping, Pong,
+};
+use crate::packets::character::PlayerLogin;
+use crate::packets::hotfix::{DbQueryBulk, HotfixRequest};
+use crate::packets::misc::{LogDisconnect, LogStreamingError};
+use crate::packets::{ClientPacket, IntoClientPacket};
+use crate::world_server::WorldServer;
+use crate::world_session::WorldClientSession;
+use rustycraft_common::telemetry::TelemetryEvent;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+pub type SessionHandler = for<'a> fn(&'a mut WorldClientSession, ClientPacket) -> HandlerFuture<'a>;
+pub type WorldHandler =
+    for<'a> fn(&'a mut WorldServer, SocketAddr, ClientPacket) -> HandlerFuture<'a>;
+
+/// Where a session is. Packets are only accepted in the state their handler asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// Never accepted once the handshake is done, only used by handlers.
+    Never,
+    /// Any state once the handshake is done, only used by handlers.
+    Authed,
+    /// Where sessions start once the handshake is done.
+    CharSelect,
+    InWorld,
+    /// Between maps or instances, the player is not in any world.
+    Transferring,
+}
+
+impl SessionState {
+    /// Whether a handler registered for `self` accepts packets while the session is in `current`.
+    pub fn allows(self, current: SessionState) -> bool {
+        match self {
+            SessionState::Never => false,
+            SessionState::Authed => true,
+            state => state == current,
+        }
+    }
+}
+
+/// Why a client packet was skipped.
+#[derive(Debug)]
+pub enum PacketRefused {
+    /// Not an opcode of the client build.
+    Unknown(anyhow::Error),
+    /// No handler is registered for the opcode.
+    Unhandled(OpcodeClient),
+    NotAllowed(OpcodeClient, SessionState),
+    Malformed(OpcodeClient, anyhow::Error),
+}
+
+impl fmt::Display for PacketRefused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketRefused::Unknown(e) => write!(f, "{}", e),
+            PacketRefused::Unhandled(opcode) => write!(f, "Unhandled opcode {:?}", opcode),
+            PacketRefused::NotAllowed(opcode, state) => {
+                write!(f, "{:?} is not allowed while in {:?}", opcode, state)
+            }
+            PacketRefused::Malformed(opcode, e) => write!(f, "Malformed {:?}: {}", opcode, e),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// Read by the handshake itself and refused afterwards.
+    Handshake,
+    /// Runs on the session task, for packets that only concern the client.
+    Session(SessionHandler),
+    /// Forwarded to the world server task.
+    World(WorldHandler),
+}
+
+/// Who handles an opcode, `OpcodeHandler` in TrinityCore.
+pub struct OpcodeHandler {
+    pub opcode: OpcodeClient,
+    pub state: SessionState,
+    pub handler: Handler,
+}
+
+impl OpcodeHandler {
+    /// Entry of a `#[client_packet]` struct.
+    const fn packet<T: IntoClientPacket>(state: SessionState, handler: Handler) -> OpcodeHandler {
+        OpcodeHandler {
+            opcode: T::OPCODE,
+            state,
+            handler,
+        }
+    }
+
+    pub fn find(opcode: OpcodeClient) -> Option<&'static OpcodeHandler> {
+        HANDLERS.iter().find(|handler| handler.opcode == opcode)
+    }
+
+    /// Finds the handler of a whole decrypted packet and parses it, if the session is in a
+    /// state the handler accepts.
+    pub fn check(
+        data: &[u8],
+        protocol: &WorldProtocol,
+        state: SessionState,
+    ) -> Result<(&'static OpcodeHandler, ClientPacket), PacketRefused> {
+        let opcode = ClientPacket::opcode(data, protocol).map_err(PacketRefused::Unknown)?;
+        let handler = Self::find(opcode).ok_or(PacketRefused::Unhandled(opcode))?;
+        if !handler.state.allows(state) {
+            return Err(PacketRefused::NotAllowed(opcode, state));
+        }
+        let packet = handler
+            .parse(data)
+            .map_err(|e| PacketRefused::Malformed(opcode, e))?;
+        Ok((handler, packet))
+    }
+
+    /// Parses a whole decrypted packet, opcode included.
+    pub fn parse(&self, data: &[u8]) -> anyhow::Result<ClientPacket> {
+        let body = data
+            .get(2..)
+            .ok_or_else(|| anyhow!("Packet is too short"))?;
+        ClientPacket::parse_body(self.opcode, body)
+    }
+}
+
+static HANDLERS: &[OpcodeHandler] = &[
+    OpcodeHandler::packet::<AuthSession>(SessionState::Never, Handler::Handshake),
+    OpcodeHandler::packet::<AuthContinuedSession>(SessionState::Never, Handler::Handshake),
+    OpcodeHandler::packet::<EnterEncryptedModeAck>(SessionState::Never, Handler::Handshake),
+    OpcodeHandler::packet::<Ping>(SessionState::Authed, Handler::Session(handle_ping)),
+    // Only sent back for the ConnectTo of a login or transfer.
+    OpcodeHandler::packet::<ConnectToFailed>(
+        SessionState::Transferring,
+        Handler::Session(handle_connect_to_failed),
+    ),
+    OpcodeHandler::packet::<PlayerLogin>(
+        SessionState::CharSelect,
+        Handler::Session(handle_player_login),
+    ),
+    OpcodeHandler::packet::<LogDisconnect>(
+        SessionState::Authed,
+        Handler::Session(handle_log_disconnect),
+    ),
+    OpcodeHandler::packet::<LogStreamingError>(
+        SessionState::Authed,
+        Handler::Session(handle_log_streaming_error),
+    ),
+    OpcodeHandler::packet::<HotfixRequest>(
+        SessionState::Authed,
+        Handler::Session(handle_hotfix_request),
+    ),
+    OpcodeHandler::packet::<DbQueryBulk>(
+        SessionState::Authed,
+        Handler::Session(handle_db_query_bulk),
+    ),
+];
+
+/// Each connection is pinged on its own, so the answer goes back where the ping came from.
+fn handle_ping(session: &mut WorldClientSession, packet: ClientPacket) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        let ping = Ping::try_from(packet)?;
+        let connection = session.received_on;
+        session
+            .write_to(connection, Box::new(Pong::from(ping)))
+            .await
+    })
+}
+
+fn handle_connect_to_failed(
+    session: &mut WorldClientSession,
+    packet: ClientPacket,
+) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        let failed = ConnectToFailed::try_from(packet)?;
+        session.connect_to_failed(failed).await
+    })
+}
+
+/// Characters are not loaded yet, logging in stops once the instance connection is open,
+/// which puts the session in world.
+fn handle_player_login(
+    session: &mut WorldClientSession,
+    packet: ClientPacket,
+) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        let login = PlayerLogin::try_from(packet)?;
+        debug!(target: "WorldSession", "[{:?}] Logging in {}", session.addr, login.guid);
+        session.state = SessionState::Transferring;
+        session
+            .connect_to_instance(ConnectToSerial::WorldAttempt1)
+            .await
+    })
+}
+
+fn handle_log_disconnect(session: &mut WorldClientSession, _: ClientPacket) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        debug!(target: "WorldSession", "[{:?}] Client is disconnecting", session.addr);
+        Ok(())
+    })
+}
+
+fn handle_log_streaming_error(
+    session: &mut WorldClientSession,
+    packet: ClientPacket,
+) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        let report = LogStreamingError::try_from(packet)?;
+        session
+            .report(TelemetryEvent::LogStreamingError {
+                error: report.error.into(),
+            })
+            .await;
+        Ok(())
+    })
+}
+
+fn handle_hotfix_request(
+    session: &mut WorldClientSession,
+    packet: ClientPacket,
+) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        let request = HotfixRequest::try_from(packet)?;
+        session.send_hotfixes(request).await
+    })
+}
+
+fn handle_db_query_bulk(
+    session: &mut WorldClientSession,
+    packet: ClientPacket,
+) -> HandlerFuture<'_> {
+    Box::pin(async move {
+        let query = DbQueryBulk::try_from(packet)?;
+        session.send_db_replies(query).await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(opcode: OpcodeClient, body: &[u8]) -> Vec<u8> {
+        let mut data = (opcode as u16).to_le_bytes().to_vec();
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn check(data: &[u8], state: SessionState) -> Result<OpcodeClient, PacketRefused> {
+        OpcodeHandler::check(data, WorldProtocol::latest(), state)
+            .map(|(handler, _)| handler.opcode)
+    }
+
+    #[test]
+    fn test_allows() {
+        assert!(SessionState::Authed.allows(SessionState::InWorld));
+        assert!(SessionState::CharSelect.allows(SessionState::CharSelect));
+        assert!(!SessionState::CharSelect.allows(SessionState::Transferring));
+        assert!(!SessionState::Never.allows(SessionState::CharSelect));
+    }
+
+    #[test]
+    fn test_refused_out_of_state() {
+        let login = packet(OpcodeClient::PlayerLogin, &[0; 6]);
+        assert_eq!(
+            check(&login, SessionState::CharSelect).unwrap(),
+            OpcodeClient::PlayerLogin
+        );
+        for state in [SessionState::InWorld, SessionState::Transferring] {
+            assert!(matches!(
+                check(&login, state),
+                Err(PacketRefused::NotAllowed(OpcodeClient::PlayerLogin, _))
+            ));
+        }
+
+        let failed = packet(OpcodeClient::ConnectToFailed, &[0; 5]);
+        assert!(matches!(
+            check(&failed, SessionState::CharSelect),
+            Err(PacketRefused::NotAllowed(OpcodeClient::ConnectToFailed, _))
+        ));
+
+        // The handshake is over once packets reach the handlers.
+        let auth = packet(OpcodeClient::AuthSession, &[]);
+        for state in [SessionState::CharSelect, SessionState::InWorld] {
+            assert!(matches!(
+                check(&auth, state),
+                Err(PacketRefused::NotAllowed(OpcodeClient::AuthSession, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_accepted_in_any_state() {
+        let ping = packet(OpcodeClient::Ping, &[1, 0, 0, 0, 2, 0, 0, 0]);
+        for state in [
+            SessionState::CharSelect,
+            SessionState::InWorld,
+            SessionState::Transferring,
+        ] {
+            assert_eq!(check(&ping, state).unwrap(), OpcodeClient::Ping);
+        }
+    }
+
+    #[test]
+    fn test_handled_opcodes_mapped() {
+        for protocol in crate::builds::PROTOCOLS {
+            for handler in HANDLERS {
+                assert!(
+                    protocol
+                        .client_opcodes
+                        .iter()
+                        .any(|(opcode, _)| *opcode == handler.opcode),
+                    "{:?} has no value in {}",
+                    handler.opcode,
+                    protocol.build
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_unhandled_and_malformed() {
+        // Not in the 9.2.0.43206 table, the server doesn't read it.
+        let unmapped = packet(OpcodeClient::AcceptTrade, &[]);
+        assert!(matches!(
+            check(&unmapped, SessionState::InWorld),
+            Err(PacketRefused::Unknown(_))
+        ));
+        let short_ping = packet(OpcodeClient::Ping, &[1]);
+        assert!(matches!(
+            check(&short_ping, SessionState::InWorld),
+            Err(PacketRefused::Malformed(OpcodeClient::Ping, _))
+        ));
+        assert!(matches!(
+            check(&[0, 0], SessionState::InWorld),
+            Err(PacketRefused::Unknown(_))
+        ));
+        assert!(matches!(
+            check(&[0x68], SessionState::InWorld),
+            Err(PacketRefused::Unknown(_))
+        ));
+    }
+}
@@ -1,5 +1,10 @@
+pub mod builds;
+pub mod connect_to;
 pub mod constants;
 pub mod crypt;
+pub mod game_data;
+pub mod guid;
+pub mod handlers;
 pub mod opcodes;
 pub mod packets;
 mod session_modules;
@@ -13,8 +18,6 @@ pub mod world_session;
 extern crate log;
 #[macro_use]
 extern crate anyhow;
-#[macro_use]
-extern crate lazy_static;
 extern crate core;
 
 use crate::opcodes::OpcodeServer;
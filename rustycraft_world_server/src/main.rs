@@ -1,17 +1,54 @@
+use rustycraft_common::config;
+use rustycraft_common::sessions::{SessionKind, SessionRegistry};
+use rustycraft_common::shutdown::{self, ShutdownController};
+use rustycraft_world_server::crypt;
+use rustycraft_world_server::game_data::GameData;
 use rustycraft_world_server::world_listener::WorldSocketManagerBuilder;
 use rustycraft_world_server::world_server::WorldServerBuilder;
 use rustycraft_world_server::world_session::WorldClientSession;
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let _ = rustycraft_logging::init_logging();
+    let config = config::init()?;
+    crypt::init_signing_key(&config.world)?;
     let mut world_server_builder = WorldServerBuilder::new();
     let world_server_channel = world_server_builder.get_event_sender();
+    if let Some(data_dir) = &config.world.data_dir {
+        // Nothing reads the tables yet, loading them only checks the client files.
+        let game_data = GameData::load(data_dir)?;
+        log::info!("Loaded client tables: {:?}", game_data);
+    }
+    let sessions = SessionRegistry::new(SessionKind::World).await?;
+    world_server_builder.set_session_registry(sessions.clone());
     let world_server = world_server_builder.build()?;
     let mut world_socket_manager_builder = WorldSocketManagerBuilder::new();
-    world_socket_manager_builder.set_world_server_channel(world_server_channel);
+    world_socket_manager_builder
+        .set_bind_address(config.world.bind_address)
+        .set_world_server_channel(world_server_channel)
+        .set_session_registry(sessions);
     let world_socket_manager = world_socket_manager_builder.build()?;
-    tokio::spawn(world_socket_manager.run_forever::<WorldClientSession>());
-    world_server.run_forever().await;
+    let shutdown = ShutdownController::new();
+    if let Some(metrics_bind_address) = config.world.metrics_bind_address {
+        let mut metrics_shutdown = shutdown.subscribe();
+        tokio::spawn(rustycraft_metrics::serve(metrics_bind_address, async move {
+            metrics_shutdown.recv().await
+        })?);
+    }
+    let countdown = Duration::from_secs(config.world.shutdown_countdown_secs);
+    tokio::spawn(world_socket_manager.run_forever::<WorldClientSession>(shutdown.subscribe()));
+    let flush_timeout = Duration::from_secs(config.world.shutdown_timeout_secs);
+    let world = tokio::spawn(world_server.run_forever(
+        shutdown.subscribe(),
+        countdown,
+        flush_timeout,
+    ));
+    shutdown::wait_for_signal().await?;
+    let timeout = countdown + flush_timeout;
+    if !shutdown.shutdown(timeout).await {
+        log::warn!("Some sessions did not close in time");
+    }
+    let _ = world.await;
     Ok(())
 }
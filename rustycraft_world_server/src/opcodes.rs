@@ -1,6 +1,8 @@
 use deku::prelude::*;
 
-#[derive(Debug)]
+/// Which of the two sockets of a session a packet travels on. Instance connections are opened
+/// with `ConnectTo` and joined with `AuthContinuedSession`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i8)]
 pub enum ConnectionType {
     ConnectionTypeRealm = 0,
@@ -19,7 +21,7 @@ pub enum OpcodeMisc {
     NullOpcode = 0xBADD,
 }
 
-#[derive(Debug, Eq, PartialEq, DekuRead)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead)]
 #[deku(type = "u16", endian = "little")]
 #[repr(u16)]
 pub enum OpcodeClient {
@@ -814,7 +816,7 @@ pub enum OpcodeClient {
     WrapItem = 0x3994,
 }
 
-#[derive(Debug, DekuWrite)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, DekuWrite)]
 #[deku(type = "u16")]
 #[repr(u16)]
 pub enum OpcodeServer {
@@ -1,14 +1,19 @@
 use crate::constants::ENABLE_ENCRYPTION_SEED;
 use crate::crypt::RSA;
-use crate::packets::{ClientPacket, IntoServerPacket};
-use crate::OpcodeServer;
+use crate::opcodes::ConnectionType;
+use crate::packets::wire::{BitFlush, CountedVec, WireString};
 use deku::prelude::*;
 use hmac::{Hmac, Mac};
+use rustycraft_packet_macros::{client_packet, server_packet};
+use rustycraft_protocol::classes::Classes;
 use rustycraft_protocol::expansions::Expansions;
+use rustycraft_protocol::factions::FactionGroup;
 use rustycraft_protocol::races::Races;
 use rustycraft_protocol::rpc_responses::WowRpcResponse;
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
 
+#[client_packet(Ping)]
 #[derive(Debug, DekuRead)]
 pub struct Ping {
     #[deku(endian = "little")]
@@ -17,18 +22,18 @@ pub struct Ping {
     pub latency: u32,
 }
 
+/// Sent once the client switched to encrypted packets.
+#[client_packet(EnterEncryptedModeAck)]
+#[derive(Debug, DekuRead)]
+pub struct EnterEncryptedModeAck;
+
+#[server_packet(Pong)]
 #[derive(Debug, DekuWrite)]
 pub struct Pong {
     #[deku(endian = "little")]
     serial: u32,
 }
 
-impl IntoServerPacket for Pong {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::Pong
-    }
-}
-
 impl From<Ping> for Pong {
     fn from(ping: Ping) -> Self {
         Pong {
@@ -37,6 +42,7 @@ impl From<Ping> for Pong {
     }
 }
 
+#[server_packet(AuthChallenge)]
 #[derive(Debug, DekuWrite)]
 pub struct AuthChallenge {
     #[deku(endian = "little")]
@@ -45,12 +51,6 @@ pub struct AuthChallenge {
     dos_zero_bits: u8,
 }
 
-impl IntoServerPacket for AuthChallenge {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::AuthChallenge
-    }
-}
-
 impl AuthChallenge {
     pub fn new(challenge: [u8; 16], dos_challenge: [u32; 8]) -> AuthChallenge {
         AuthChallenge {
@@ -61,6 +61,7 @@ impl AuthChallenge {
     }
 }
 
+#[client_packet(AuthSession)]
 #[derive(Debug, DekuRead)]
 pub struct AuthSession {
     #[deku(endian = "little")]
@@ -77,11 +78,151 @@ pub struct AuthSession {
     pub use_ip_v6: bool,
     #[deku(endian = "little")]
     _realm_join_ticket_size: u32,
-    #[deku(count = "_realm_join_ticket_size")]
-    #[deku(map = "crate::utils::parse_string")]
-    pub realm_join_ticket: String,
+    #[deku(ctx = "*_realm_join_ticket_size as usize")]
+    pub realm_join_ticket: WireString,
+}
+
+/// `ConnectToKey` in TrinityCore, names the session an instance connection joins: an id in the
+/// low 32 bits, the connection type in bit 32 and a random 31 bit key above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DekuRead, DekuWrite)]
+pub struct ConnectToKey {
+    #[deku(endian = "little")]
+    pub raw: u64,
+}
+
+impl ConnectToKey {
+    pub fn new(id: u32, connection: ConnectionType, key: u32) -> ConnectToKey {
+        let instance = connection == ConnectionType::ConnectionTypeInstance;
+        ConnectToKey {
+            raw: u64::from(id) | u64::from(instance) << 32 | u64::from(key & 0x7FFF_FFFF) << 33,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.raw as u32
+    }
+
+    pub fn connection_type(&self) -> ConnectionType {
+        if self.raw >> 32 & 1 == 1 {
+            ConnectionType::ConnectionTypeInstance
+        } else {
+            ConnectionType::ConnectionTypeRealm
+        }
+    }
+}
+
+#[client_packet(AuthContinuedSession)]
+#[derive(Debug, DekuRead)]
+pub struct AuthContinuedSession {
+    #[deku(endian = "little")]
+    pub dos_response: u64,
+    pub key: ConnectToKey,
+    pub local_challenge: [u8; 16],
+    pub digest: [u8; 24],
+}
+
+/// Which `ConnectTo` attempt the client is answering. Attempts after the first are retries
+/// after a `ConnectToFailed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(type = "u32", endian = "little")]
+pub enum ConnectToSerial {
+    #[deku(id = "0")]
+    None,
+    #[deku(id = "14")]
+    Realm,
+    #[deku(id = "17")]
+    WorldAttempt1,
+    #[deku(id = "35")]
+    WorldAttempt2,
+    #[deku(id = "53")]
+    WorldAttempt3,
+    #[deku(id = "71")]
+    WorldAttempt4,
+    #[deku(id = "89")]
+    WorldAttempt5,
+}
+
+impl ConnectToSerial {
+    /// The attempt to make after this one failed, `None` once the client gave up five times.
+    pub fn next_attempt(self) -> Option<ConnectToSerial> {
+        match self {
+            ConnectToSerial::WorldAttempt1 => Some(ConnectToSerial::WorldAttempt2),
+            ConnectToSerial::WorldAttempt2 => Some(ConnectToSerial::WorldAttempt3),
+            ConnectToSerial::WorldAttempt3 => Some(ConnectToSerial::WorldAttempt4),
+            ConnectToSerial::WorldAttempt4 => Some(ConnectToSerial::WorldAttempt5),
+            _ => None,
+        }
+    }
+}
+
+/// `SocketAddress` in TrinityCore.
+#[derive(Debug, DekuWrite)]
+#[deku(type = "u8")]
+pub enum ConnectToAddress {
+    #[deku(id = "1")]
+    V4([u8; 4]),
+    #[deku(id = "2")]
+    V6([u8; 16]),
+}
+
+impl From<IpAddr> for ConnectToAddress {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => ConnectToAddress::V4(ip.octets()),
+            IpAddr::V6(ip) => ConnectToAddress::V6(ip.octets()),
+        }
+    }
+}
+
+/// Asks the client to open its instance connection to `address`. The client only follows
+/// addresses signed with the key it checks `EncryptedMode` with.
+#[server_packet(ConnectTo, connection = ConnectionTypeRealm)]
+#[derive(Debug, DekuWrite)]
+pub struct ConnectTo {
+    #[deku(count = "256")]
+    signature: Vec<u8>,
+    address: ConnectToAddress,
+    #[deku(endian = "little")]
+    port: u16,
+    serial: ConnectToSerial,
+    con: u8,
+    key: ConnectToKey,
+}
+
+impl ConnectTo {
+    pub fn new(
+        signer: &RSA,
+        address: SocketAddr,
+        serial: ConnectToSerial,
+        key: ConnectToKey,
+    ) -> anyhow::Result<ConnectTo> {
+        let where_address = ConnectToAddress::from(address.ip());
+        let mut hasher = Sha256::new();
+        hasher.update(where_address.to_bytes()?);
+        hasher.update(address.port().to_le_bytes());
+        Ok(ConnectTo {
+            signature: signer.sign(&hasher.finalize())?,
+            address: where_address,
+            port: address.port(),
+            serial,
+            con: ConnectionType::ConnectionTypeInstance as u8,
+            key,
+        })
+    }
+}
+
+#[client_packet(ConnectToFailed)]
+#[derive(Debug, DekuRead)]
+pub struct ConnectToFailed {
+    pub serial: ConnectToSerial,
+    pub con: u8,
 }
 
+/// First packet on a freshly joined instance connection.
+#[server_packet(ResumeComms, connection = ConnectionTypeInstance)]
+#[derive(Debug, Default, DekuWrite)]
+pub struct ResumeComms {}
+
 #[derive(Debug, DekuWrite)]
 pub struct AuthWaitInfo {
     ///position of the account in the login queue
@@ -106,20 +247,20 @@ impl AuthWaitInfo {
 }
 
 #[derive(Debug, DekuWrite)]
-#[deku(endian = "little")]
 pub struct VirtualRealmNameInfo {
     #[deku(bits = "1")]
     is_local: bool,
-    #[deku(bits = "1", pad_bits_after = "6")]
+    #[deku(bits = "1")]
     is_internal: bool,
+    #[deku(bits = "8")]
     realm_name_actual_len: u8,
+    #[deku(bits = "8")]
     realm_name_normalized_len: u8,
-    #[deku(count = "realm_name_actual_len")]
-    #[deku(writer = "crate::utils::string_writer(deku::output, &self.realm_name_actual)")]
-    realm_name_actual: String,
-    #[deku(count = "realm_name_normalized_len")]
-    #[deku(writer = "crate::utils::string_writer(deku::output, &self.realm_name_normalized)")]
-    realm_name_normalized: String,
+    flush: BitFlush,
+    #[deku(ctx = "usize::from(*realm_name_actual_len)")]
+    realm_name_actual: WireString,
+    #[deku(ctx = "usize::from(*realm_name_normalized_len)")]
+    realm_name_normalized: WireString,
 }
 
 impl VirtualRealmNameInfo {
@@ -134,8 +275,9 @@ impl VirtualRealmNameInfo {
             is_internal,
             realm_name_actual_len: realm_name_actual.len() as u8,
             realm_name_normalized_len: realm_name_normalized.len() as u8,
-            realm_name_actual,
-            realm_name_normalized,
+            flush: BitFlush,
+            realm_name_actual: realm_name_actual.into(),
+            realm_name_normalized: realm_name_normalized.into(),
         }
     }
 }
@@ -188,12 +330,12 @@ impl GameTime {
 
 #[derive(Debug, DekuWrite)]
 pub struct CharacterTemplateClass {
-    faction_group: u8,
-    class_id: u8,
+    faction_group: FactionGroup,
+    class_id: Classes,
 }
 
 impl CharacterTemplateClass {
-    pub fn new(faction_group: u8, class_id: u8) -> CharacterTemplateClass {
+    pub fn new(faction_group: FactionGroup, class_id: Classes) -> CharacterTemplateClass {
         CharacterTemplateClass {
             faction_group,
             class_id,
@@ -206,21 +348,16 @@ impl CharacterTemplateClass {
 pub struct CharacterTemplate {
     #[deku(endian = "little")]
     template_set_id: u32,
-    #[deku(update = "self.classes.len()", endian = "little")]
-    classes_size: u32,
-    #[deku(count = "classes_size")]
-    classes: Vec<CharacterTemplateClass>,
-    #[deku(bits = "7", update = "self.name.len()")]
-    name_len: u8, // Not actually 8 bits, only 7
-    #[deku(bits = "10", update = "self.description.len()", endian = "little")]
-    #[deku(pad_bits_after = "7")]
-    description_len: u16, // Not actually 16 bits, only 10
-    #[deku(count = "name_len")]
-    #[deku(writer = "crate::utils::string_writer(deku::output, &self.name)")]
-    name: String,
-    #[deku(count = "description_len")]
-    #[deku(writer = "crate::utils::string_writer(deku::output, &self.description)")]
-    description: String,
+    classes: CountedVec<CharacterTemplateClass>,
+    #[deku(bits = "7")]
+    name_len: u8,
+    #[deku(bits = "10", endian = "big")]
+    description_len: u16,
+    flush: BitFlush,
+    #[deku(ctx = "usize::from(*name_len)")]
+    name: WireString,
+    #[deku(ctx = "usize::from(*description_len)")]
+    description: WireString,
 }
 
 impl CharacterTemplate {
@@ -232,26 +369,26 @@ impl CharacterTemplate {
     ) -> CharacterTemplate {
         CharacterTemplate {
             template_set_id,
-            classes_size: classes.len() as u32,
-            classes,
+            classes: classes.into(),
             name_len: name.len() as u8,
             description_len: description.len() as u16,
-            name,
-            description,
+            flush: BitFlush,
+            name: name.into(),
+            description: description.into(),
         }
     }
 }
 
 #[derive(Debug, DekuWrite)]
 pub struct Class {
-    id: u8,
+    id: Classes,
     active_expansion_level: Expansions,
     account_expansion_level: Expansions,
 }
 
 impl Class {
     pub fn new(
-        id: u8,
+        id: Classes,
         active_expansion_level: Expansions,
         account_expansion_level: Expansions,
     ) -> Class {
@@ -267,22 +404,41 @@ impl Class {
 #[derive(Debug, DekuWrite)]
 pub struct RaceClassAvailability {
     race_id: Races,
-    #[deku(update = "self.classes.len()", endian = "little")]
-    classes_size: u32,
-    #[deku(count = "classes_size")]
-    classes: Vec<Class>,
+    classes: CountedVec<Class>,
 }
 
 impl RaceClassAvailability {
     pub fn new(race_id: Races, classes: Vec<Class>) -> RaceClassAvailability {
         RaceClassAvailability {
             race_id,
-            classes_size: classes.len() as u32,
-            classes,
+            classes: classes.into(),
         }
     }
+
+    /// Every class `race_id` can be created as, gated by the expansion each combination needs.
+    pub fn from_race(race_id: Races) -> RaceClassAvailability {
+        let classes = race_id
+            .classes()
+            .iter()
+            .map(|class| {
+                let expansion = race_id.min_expansion_for(*class);
+                Class::new(*class, expansion, expansion)
+            })
+            .collect();
+        RaceClassAvailability::new(race_id, classes)
+    }
+
+    pub fn all() -> Vec<RaceClassAvailability> {
+        Races::PLAYABLE
+            .into_iter()
+            .map(RaceClassAvailability::from_race)
+            .collect()
+    }
 }
 
+/// Expansion of the supported client builds, sent as both the server and account expansion.
+const SERVER_EXPANSION: Expansions = Expansions::ExpansionShadowlands;
+
 #[derive(Debug, DekuWrite)]
 pub struct AuthSuccessInfo {
     ///a special identifier made from the Index, BattleGroup and Region.
@@ -351,72 +507,58 @@ pub struct AuthSuccessInfo {
 }
 
 impl AuthSuccessInfo {
-    pub fn new(
-        virtual_realm_address: u32,
-        time_rested: u32,
-        active_expansion_level: Expansions,
-        account_expansion_level: Expansions,
-        time_seconds_until_pc_kick: u32,
-        currency_id: u32,
-        time: u64,
-        available_classes: Vec<RaceClassAvailability>,
-        is_expansion_trial: bool,
-        force_character_template: bool,
-        game_time_info: GameTime,
-        num_players_horde: Option<u16>,
-        num_players_alliance: Option<u16>,
-        expansion_trial_expiration: Option<i32>,
-        virtual_realms: Vec<VirtualRealmInfo>,
-        templates: Vec<CharacterTemplate>,
-    ) -> AuthSuccessInfo {
+    /// What a successful login gets: the realm it joined and every playable race and class
+    /// combination. Queues, trials and character templates are not implemented.
+    pub fn for_realm(virtual_realm_address: u32, realm_name: String, time: u64) -> AuthSuccessInfo {
+        let realm = VirtualRealmInfo::new(
+            virtual_realm_address,
+            VirtualRealmNameInfo::new(true, false, realm_name),
+        );
+        let available_classes = RaceClassAvailability::all();
+        // The update() of AuthResponse doesn't reach nested structs, so the counts are set here.
         AuthSuccessInfo {
             virtual_realm_address,
-            virtual_realms_size: virtual_realms.len() as u32,
-            time_rested,
-            active_expansion_level,
-            account_expansion_level,
-            time_seconds_until_pc_kick,
+            virtual_realms_size: 1,
+            time_rested: 0,
+            active_expansion_level: SERVER_EXPANSION,
+            account_expansion_level: SERVER_EXPANSION,
+            time_seconds_until_pc_kick: 0,
             available_classes_size: available_classes.len() as u32,
-            templates_size: templates.len() as u32,
-            currency_id,
+            templates_size: 0,
+            currency_id: 0,
             time,
             available_classes,
-            is_expansion_trial,
-            force_character_template,
-            has_num_players_horde: num_players_horde.is_some(),
-            has_num_players_alliance: num_players_alliance.is_some(),
-            has_expansion_trial_expiration: expansion_trial_expiration.is_some(),
-            game_time_info,
-            num_players_horde,
-            num_players_alliance,
-            expansion_trial_expiration,
-            virtual_realms,
-            templates,
+            is_expansion_trial: false,
+            force_character_template: false,
+            has_num_players_horde: false,
+            has_num_players_alliance: false,
+            has_expansion_trial_expiration: false,
+            game_time_info: GameTime::new(0, 0, false),
+            num_players_horde: None,
+            num_players_alliance: None,
+            expansion_trial_expiration: None,
+            virtual_realms: vec![realm],
+            templates: Vec::new(),
         }
     }
 }
 
+#[server_packet(AuthResponse, connection = ConnectionTypeRealm)]
 #[derive(Debug, DekuWrite)]
 pub struct AuthResponse {
     ///the result of the authentication process. Look at [rustycraft_protocol::errors::WowRpcResponse]
     result: WowRpcResponse,
-    #[deku(bits = "1")]
+    #[deku(bits = "1", update = "self.success_info.is_some()")]
     has_success_info: bool,
-    #[deku(bits = "1")]
-    #[deku(pad_bits_after = "6")]
+    #[deku(bits = "1", update = "self.wait_info.is_some()")]
     has_wait_info: bool,
+    flush: BitFlush,
     ///contains the packet data in case that it has account information (It is never set when WaitInfo is set), otherwise its contents are undefined.
     success_info: Option<AuthSuccessInfo>,
     ///contains the queue wait information in case the account is in the login queue.
     wait_info: Option<AuthWaitInfo>,
 }
 
-impl IntoServerPacket for AuthResponse {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::AuthResponse
-    }
-}
-
 impl AuthResponse {
     pub fn new(
         result: WowRpcResponse,
@@ -425,14 +567,16 @@ impl AuthResponse {
     ) -> AuthResponse {
         AuthResponse {
             result,
-            has_success_info: success_info.is_some(),
-            has_wait_info: wait_info.is_some(),
+            has_success_info: false,
+            has_wait_info: false,
+            flush: BitFlush,
             success_info,
             wait_info,
         }
     }
 }
 
+#[server_packet(EnterEncryptedMode)]
 #[derive(Debug, DekuWrite)]
 pub struct EncryptedMode {
     #[deku(count = "256")]
@@ -442,22 +586,56 @@ pub struct EncryptedMode {
     enabled: bool,
 }
 
-impl IntoServerPacket for EncryptedMode {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::EnterEncryptedMode
-    }
-}
-
 impl EncryptedMode {
-    pub fn new(encryptor: &RSA, encryption_key: &[u8]) -> EncryptedMode {
-        let mut hash = <Hmac<Sha256>>::new_from_slice(encryption_key).unwrap();
+    pub fn new(encryptor: &RSA, encryption_key: &[u8]) -> anyhow::Result<EncryptedMode> {
+        let mut hash = <Hmac<Sha256>>::new_from_slice(encryption_key)?;
         hash.update(&[true as u8]);
         hash.update(&ENABLE_ENCRYPTION_SEED);
         let key_hash = hash.finalize().into_bytes();
-        let signature = encryptor.sign(&key_hash);
-        EncryptedMode {
+        let signature = encryptor.sign(&key_hash)?;
+        Ok(EncryptedMode {
             hmac_sha_256: signature,
             enabled: true,
-        }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_race_class_availability() {
+        let all = RaceClassAvailability::all();
+        assert_eq!(all.len(), Races::PLAYABLE.len());
+        let human = RaceClassAvailability::from_race(Races::RaceHuman);
+        let classes = Races::RaceHuman.classes();
+        assert_eq!(human.classes.len(), classes.len());
+        let bytes = human.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 5 + 3 * classes.len());
+        assert_eq!(bytes[0], Races::RaceHuman as u8);
+        assert_eq!(bytes[1..5], (classes.len() as u32).to_le_bytes());
+        let expansion = Races::RaceHuman.min_expansion_for(classes[0]) as u8;
+        assert_eq!(bytes[5..8], [classes[0] as u8, expansion, expansion]);
+    }
+
+    #[test]
+    fn test_auth_success_info_layout() {
+        let info = AuthSuccessInfo::for_realm(0x0101_0400, "RustyCraft".into(), 1);
+        let mut response = AuthResponse::new(WowRpcResponse::Ok, Some(info), None);
+        response.update().unwrap();
+        let bytes = response.to_bytes().unwrap();
+        assert_eq!(bytes[..4], [0, 0, 0, 0]);
+        assert_eq!(bytes[4], 0x80);
+        // Address, one virtual realm, no rested time.
+        assert_eq!(
+            bytes[5..17],
+            [0x00, 0x04, 0x01, 0x01, 1, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let expansion = SERVER_EXPANSION as u8;
+        assert_eq!(bytes[17..19], [expansion, expansion]);
+        assert_eq!(bytes[23..27], (Races::PLAYABLE.len() as u32).to_le_bytes());
+        assert_eq!(bytes[27..31], [0, 0, 0, 0]);
+        assert!(bytes.ends_with(b"RustyCraftRustyCraft"));
     }
 }
@@ -0,0 +1,12 @@
+use crate::guid::ObjectGuid;
+use deku::prelude::*;
+use rustycraft_packet_macros::client_packet;
+
+#[client_packet(PlayerLogin)]
+#[derive(Debug, DekuRead)]
+pub struct PlayerLogin {
+    pub guid: ObjectGuid,
+    ///view distance the client is set to
+    #[deku(endian = "little")]
+    pub far_clip: f32,
+}
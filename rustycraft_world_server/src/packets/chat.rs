@@ -0,0 +1,47 @@
+use crate::packets::wire::BitString;
+use deku::prelude::*;
+use rustycraft_packet_macros::server_packet;
+
+/// `ServerMessageType` ids understood by the client, see `ServerMessages.dbc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ServerMessageType {
+    ShutdownTime = 1,
+    RestartTime = 2,
+    String = 3,
+    ShutdownCancelled = 4,
+    RestartCancelled = 5,
+}
+
+#[server_packet(ChatServerMessage)]
+#[derive(Debug, DekuWrite)]
+pub struct ChatServerMessage {
+    #[deku(endian = "little")]
+    message_id: i32,
+    string_param: BitString<11>,
+}
+
+impl ChatServerMessage {
+    pub fn new(message_type: ServerMessageType, string_param: String) -> ChatServerMessage {
+        ChatServerMessage {
+            message_id: message_type as i32,
+            string_param: string_param.into(),
+        }
+    }
+
+    /// "Server shutdown in ..." notice. The client formats the remaining time itself.
+    pub fn shutdown_time(remaining_secs: u64) -> ChatServerMessage {
+        ChatServerMessage::new(
+            ServerMessageType::ShutdownTime,
+            format_duration(remaining_secs),
+        )
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match (secs / 60, secs % 60) {
+        (0, s) => format!("{} sec", s),
+        (m, 0) => format!("{} min", m),
+        (m, s) => format!("{} min {} sec", m, s),
+    }
+}
@@ -1,7 +1,7 @@
-use crate::packets::IntoServerPacket;
-use crate::OpcodeServer;
 use deku::prelude::*;
+use rustycraft_packet_macros::server_packet;
 
+#[server_packet(CacheVersion)]
 #[derive(Debug, DekuWrite)]
 #[deku(endian = "little")]
 pub struct ClientCacheVersion {
@@ -9,13 +9,8 @@ pub struct ClientCacheVersion {
 }
 
 impl ClientCacheVersion {
-    pub fn new() -> ClientCacheVersion {
-        ClientCacheVersion { version: 0 }
-    }
-}
-
-impl IntoServerPacket for ClientCacheVersion {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::CacheVersion
+    /// See `rustycraft_common::hotfixes::cache_version`.
+    pub fn new(version: u32) -> ClientCacheVersion {
+        ClientCacheVersion { version }
     }
 }
@@ -0,0 +1,221 @@
+use crate::packets::wire::{BitFlush, CountedVec};
+use deku::prelude::*;
+use rustycraft_common::hotfixes::Hotfix;
+use rustycraft_packet_macros::{client_packet, server_packet};
+
+/// `DB2Manager::HotfixRecord::Status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DekuWrite)]
+#[deku(
+    type = "u8",
+    bits = "3",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
+#[repr(u8)]
+pub enum HotfixStatus {
+    Valid = 1,
+    RecordRemoved = 2,
+    Invalid = 3,
+}
+
+/// `CMSG_HOTFIX_REQUEST`, the pushes the client has not cached yet.
+#[client_packet(HotfixRequest)]
+#[derive(Debug, DekuRead)]
+#[deku(endian = "little")]
+pub struct HotfixRequest {
+    pub client_build: u32,
+    pub data_build: u32,
+    pub push_ids: CountedVec<i32>,
+}
+
+/// `CMSG_DB_QUERY_BULK`, records missing from the client files.
+#[client_packet(DbQueryBulk)]
+#[derive(Debug, DekuRead)]
+pub struct DbQueryBulk {
+    #[deku(endian = "little")]
+    pub table_hash: u32,
+    #[deku(bits = "13", endian = "big")]
+    _record_count: u16,
+    _flush: BitFlush,
+    #[deku(count = "_record_count", endian = "little")]
+    pub record_ids: Vec<i32>,
+}
+
+#[derive(Debug, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub struct HotfixId {
+    push_id: i32,
+    unique_id: u32,
+}
+
+#[server_packet(AvailableHotfixes)]
+#[derive(Debug, DekuWrite)]
+#[deku(endian = "little")]
+pub struct AvailableHotfixes {
+    virtual_realm_address: u32,
+    hotfixes: CountedVec<HotfixId>,
+}
+
+impl AvailableHotfixes {
+    pub fn new(virtual_realm_address: u32, hotfixes: &[Hotfix]) -> AvailableHotfixes {
+        let hotfixes = hotfixes
+            .iter()
+            .map(|hotfix| HotfixId {
+                push_id: hotfix.push_id,
+                unique_id: hotfix.unique_id(),
+            })
+            .collect::<Vec<_>>();
+        AvailableHotfixes {
+            virtual_realm_address,
+            hotfixes: hotfixes.into(),
+        }
+    }
+}
+
+#[derive(Debug, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub struct HotfixData {
+    id: HotfixId,
+    table_hash: u32,
+    record_id: i32,
+    /// Bytes of `HotfixConnect::content` belonging to this record.
+    size: u32,
+    #[deku(pad_bits_after = "5")]
+    status: HotfixStatus,
+}
+
+/// `SMSG_HOTFIX_CONNECT`, the requested records, their payloads concatenated in `content`.
+#[server_packet(HotfixConnect)]
+#[derive(Debug, DekuWrite)]
+#[deku(endian = "little")]
+pub struct HotfixConnect {
+    hotfixes: CountedVec<HotfixData>,
+    content: CountedVec<u8>,
+}
+
+impl HotfixConnect {
+    pub fn new<'a>(hotfixes: impl IntoIterator<Item = &'a Hotfix>) -> HotfixConnect {
+        let mut data = Vec::new();
+        let mut content = Vec::new();
+        for hotfix in hotfixes {
+            let (size, status) = match &hotfix.payload {
+                Some(payload) => {
+                    content.extend_from_slice(payload);
+                    (payload.len() as u32, HotfixStatus::Valid)
+                }
+                None => (0, HotfixStatus::RecordRemoved),
+            };
+            data.push(HotfixData {
+                id: HotfixId {
+                    push_id: hotfix.push_id,
+                    unique_id: hotfix.unique_id(),
+                },
+                table_hash: hotfix.table_hash,
+                record_id: hotfix.record_id,
+                size,
+                status,
+            });
+        }
+        HotfixConnect {
+            hotfixes: data.into(),
+            content: content.into(),
+        }
+    }
+}
+
+/// `SMSG_DB_REPLY`, one record asked for by `DbQueryBulk`.
+#[server_packet(DbReply)]
+#[derive(Debug, DekuWrite)]
+#[deku(endian = "little")]
+pub struct DbReply {
+    table_hash: u32,
+    record_id: i32,
+    timestamp: u32,
+    #[deku(pad_bits_after = "5")]
+    status: HotfixStatus,
+    data: CountedVec<u8>,
+}
+
+impl DbReply {
+    /// Answers from the hotfix of the record, if any. Records we know nothing about are
+    /// `Invalid`, so the client keeps its own.
+    pub fn new(
+        table_hash: u32,
+        record_id: i32,
+        hotfix: Option<&Hotfix>,
+        timestamp: u32,
+    ) -> DbReply {
+        let (status, data) = match hotfix.map(|hotfix| &hotfix.payload) {
+            Some(Some(payload)) => (HotfixStatus::Valid, payload.clone()),
+            Some(None) => (HotfixStatus::RecordRemoved, Vec::new()),
+            None => (HotfixStatus::Invalid, Vec::new()),
+        };
+        DbReply {
+            table_hash,
+            record_id,
+            timestamp,
+            status,
+            data: data.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hotfixes() -> [Hotfix; 2] {
+        [
+            Hotfix {
+                push_id: 3,
+                table_hash: 0x0C77_B3C2,
+                record_id: 17,
+                payload: Some(vec![1, 2, 3]),
+            },
+            Hotfix {
+                push_id: 4,
+                table_hash: 0x1122_3344,
+                record_id: -1,
+                payload: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_hotfix_connect_layout() {
+        let bytes = HotfixConnect::new(&hotfixes()).to_bytes().unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            2, 0, 0, 0,
+            3, 0, 0, 0, 3, 0, 0, 0, 0xC2, 0xB3, 0x77, 0x0C, 17, 0, 0, 0, 3, 0, 0, 0, 0x20,
+            4, 0, 0, 0, 4, 0, 0, 0, 0x44, 0x33, 0x22, 0x11, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0x40,
+            3, 0, 0, 0, 1, 2, 3,
+        ];
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn test_db_reply_layout() {
+        let [valid, removed] = hotfixes();
+        let reply = DbReply::new(valid.table_hash, valid.record_id, Some(&valid), 0x0102_0304);
+        assert_eq!(
+            reply.to_bytes().unwrap(),
+            [0xC2, 0xB3, 0x77, 0x0C, 17, 0, 0, 0, 4, 3, 2, 1, 0x20, 3, 0, 0, 0, 1, 2, 3]
+        );
+        let reply = DbReply::new(removed.table_hash, removed.record_id, Some(&removed), 0);
+        assert_eq!(reply.to_bytes().unwrap()[12..], [0x40, 0, 0, 0, 0]);
+        let reply = DbReply::new(1, 2, None, 0);
+        assert_eq!(reply.to_bytes().unwrap()[12..], [0x60, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_db_query_bulk_layout() {
+        let bytes = [
+            0xC2, 0xB3, 0x77, 0x0C, 0x00, 0x10, 17, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let (_, query) = DbQueryBulk::from_bytes((&bytes, 0)).unwrap();
+        assert_eq!(query.table_hash, 0x0C77_B3C2);
+        assert_eq!(query.record_ids, [17, -1]);
+        assert!(DbQueryBulk::from_bytes((&bytes[..10], 0)).is_err());
+    }
+}
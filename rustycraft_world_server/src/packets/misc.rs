@@ -0,0 +1,15 @@
+use crate::packets::wire::BitString;
+use deku::prelude::*;
+use rustycraft_packet_macros::client_packet;
+
+/// `CMSG_LOG_DISCONNECT`, sent before the client closes the connection. Its reason is not read.
+#[client_packet(LogDisconnect)]
+#[derive(Debug, DekuRead)]
+pub struct LogDisconnect;
+
+/// `CMSG_LOG_STREAMING_ERROR`, sent when the client fails to stream game data.
+#[client_packet(LogStreamingError)]
+#[derive(Debug, DekuRead)]
+pub struct LogStreamingError {
+    pub error: BitString<9>,
+}
@@ -1,20 +1,20 @@
-use crate::opcodes::OpcodeClient;
-use crate::packets::auth::{AuthSession, Ping, Pong};
+use crate::builds::WorldProtocol;
+use crate::opcodes::{ConnectionType, OpcodeClient};
 use crate::OpcodeServer;
 use bytes::{Bytes, BytesMut};
-use deku::bitvec::{BitVec, Msb0};
 use deku::prelude::*;
+use std::any::Any;
 use std::fmt::Debug;
-use std::marker::PhantomData;
 use std::mem::size_of_val;
 
 pub mod auth;
+pub mod character;
+pub mod chat;
 pub mod client_config;
+pub mod hotfix;
+pub mod misc;
 pub mod system;
-
-fn write(output: &mut BitVec<Msb0, u8>, packet_size: u32) -> Result<(), DekuError> {
-    packet_size.write(output, ())
-}
+pub mod wire;
 
 #[derive(Debug, DekuWrite, DekuRead)]
 pub struct PacketHeader {
@@ -51,825 +51,161 @@ pub struct RawClientPacket {
     pub payload: Bytes,
 }
 
-impl TryFrom<Vec<u8>> for ClientPacket {
-    type Error = anyhow::Error;
-    fn try_from(data: Vec<u8>) -> anyhow::Result<Self> {
-        let (_, opcode): (_, OpcodeClient) = OpcodeClient::from_bytes((&data, 0))?;
-        let rest_data = (data.as_slice(), 16);
-        Ok(match opcode {
-            OpcodeClient::Ping => ClientPacket::Ping(Ping::from_bytes(rest_data)?.1),
-            OpcodeClient::AuthSession => {
-                ClientPacket::AuthSession(AuthSession::from_bytes(rest_data)?.1)
-            }
-            OpcodeClient::EnterEncryptedModeAck => ClientPacket::EnterEncryptedModeAck,
-            OpcodeClient::LogDisconnect => ClientPacket::LogDisconnect,
-            _ => {
-                error!("Unhandled opcode: {:?}", opcode);
-                Err(anyhow!("Unhandled opcode"))?
-            }
-        })
+impl ClientPacket {
+    /// Wire opcode of a decrypted packet, which starts right after its `PacketHeader`.
+    pub fn opcode_value(data: &[u8]) -> anyhow::Result<u16> {
+        let value = data
+            .get(..2)
+            .ok_or_else(|| anyhow!("Packet is too short"))?;
+        Ok(u16::from_le_bytes([value[0], value[1]]))
+    }
+
+    /// Opcode of a decrypted packet, as the client build numbers it.
+    pub fn opcode(data: &[u8], protocol: &WorldProtocol) -> anyhow::Result<OpcodeClient> {
+        let value = Self::opcode_value(data)?;
+        let opcode = protocol
+            .client_opcode(value)
+            .ok_or_else(|| anyhow!("Unknown opcode {:#06x} for build {}", value, protocol.build))?;
+        rustycraft_metrics::WORLD_PACKETS
+            .with_label_values(&["client", &format!("{:?}", opcode)])
+            .inc();
+        Ok(opcode)
+    }
+
+    pub fn parse(data: &[u8], protocol: &WorldProtocol) -> anyhow::Result<Self> {
+        let opcode = Self::opcode(data, protocol)?;
+        Self::parse_body(opcode, &data[2..])
+    }
+
+    /// Reads the body of an `opcode` packet, which starts right after the opcode.
+    pub fn parse_body(opcode: OpcodeClient, body: &[u8]) -> anyhow::Result<Self> {
+        let parser = ClientPacketParser::find(opcode)
+            .ok_or_else(|| anyhow!("No packet reads opcode {:?}", opcode))?;
+        (parser.parse)(body)
+    }
+
+    pub fn new<T: IntoClientPacket + Debug + Send + Sync + 'static>(packet: T) -> ClientPacket {
+        ClientPacket {
+            opcode: T::OPCODE,
+            body: Box::new(packet),
+        }
+    }
+
+    /// The packet struct, if this is a `T` packet.
+    pub fn take<T: IntoClientPacket + 'static>(self) -> anyhow::Result<T> {
+        let opcode = self.opcode;
+        self.body
+            .into_any()
+            .downcast()
+            .map(|packet| *packet)
+            .map_err(|_| anyhow!("Expected {:?} packet, got {:?}", T::OPCODE, opcode))
     }
 }
 
-pub trait IntoServerPacket: DekuContainerWrite + Debug + Send {
+/// Implemented with `#[server_packet(Opcode)]`.
+pub trait IntoServerPacket: DekuContainerWrite + DekuUpdate + Debug + Send {
     fn get_opcode(&self) -> OpcodeServer;
-    fn serialize(&self) -> Result<Bytes, DekuError> {
-        let mut buf = BytesMut::with_capacity(2 + size_of_val(&self));
-        buf.extend(self.get_opcode().to_bytes()?);
+    /// `ConnectionTypeDefault` goes to the instance connection when there is one.
+    fn connection(&self) -> ConnectionType {
+        ConnectionType::ConnectionTypeDefault
+    }
+    /// Fills the length fields, then writes the opcode and the packet.
+    fn serialize(&mut self, protocol: &WorldProtocol) -> Result<Bytes, DekuError> {
+        self.update()?;
+        let mut buf = BytesMut::with_capacity(2 + size_of_val(self));
+        let opcode = protocol.server_opcode(self.get_opcode()).ok_or_else(|| {
+            DekuError::InvalidParam(format!(
+                "{:?} has no value in build {}",
+                self.get_opcode(),
+                protocol.build
+            ))
+        })?;
+        buf.extend(opcode.to_le_bytes());
         buf.extend(self.to_bytes()?);
         Ok(buf.into())
     }
 }
 
+/// Implemented with `#[client_packet(Opcode)]`, which also registers the packet as the
+/// `ClientPacketParser` of its opcode.
+pub trait IntoClientPacket {
+    const OPCODE: OpcodeClient;
+}
+
+/// A packet read by the `#[client_packet]` struct of its opcode. Handlers take the struct back
+/// out with `TryFrom`.
 #[derive(Debug)]
-pub enum ClientPacket {
-    AbandonNpeResponse,
-    AcceptGuildInvite,
-    AcceptTrade,
-    AcceptWargameInvite,
-    AccountNotificationAcknowledged,
-    ActivateSoulbind,
-    ActivateTaxi,
-    AddonList,
-    AddAccountCosmetic,
-    AddBattlenetFriend,
-    AddFriend,
-    AddIgnore,
-    AddToy,
-    AdventureJournalOpenQuest,
-    AdventureJournalUpdateSuggestions,
-    AdventureMapStartQuest,
-    AlterAppearance,
-    AreaSpiritHealerQuery,
-    AreaSpiritHealerQueue,
-    AreaTrigger,
-    ArtifactAddPower,
-    ArtifactSetAppearance,
-    AssignEquipmentSetSpec,
-    AttackStop,
-    AttackSwing,
-    AuctionableTokenSell,
-    AuctionableTokenSellAtMarketPrice,
-    AuctionBrowseQuery,
-    AuctionCancelCommoditiesPurchase,
-    AuctionConfirmCommoditiesPurchase,
-    AuctionGetCommodityQuote,
-    AuctionHelloRequest,
-    AuctionListBiddedItems,
-    AuctionListBucketsByBucketKeys,
-    AuctionListItemsByBucketKey,
-    AuctionListItemsByItemId,
-    AuctionListOwnedItems,
-    AuctionPlaceBid,
-    AuctionRemoveItem,
-    AuctionReplicateItems,
-    AuctionSellCommodity,
-    AuctionSellItem,
-    AuctionSetFavoriteItem,
-    AuthContinuedSession,
-    AuthSession(AuthSession),
-    AutobankItem,
-    AutobankReagent,
-    AutostoreBankItem,
-    AutostoreBankReagent,
-    AutoEquipItem,
-    AutoEquipItemSlot,
-    AutoGuildBankItem,
-    AutoStoreBagItem,
-    AutoStoreGuildBankItem,
-    AzeriteEmpoweredItemSelectPower,
-    AzeriteEmpoweredItemViewed,
-    AzeriteEssenceActivateEssence,
-    AzeriteEssenceUnlockMilestone,
-    BankerActivate,
-    BattlefieldLeave,
-    BattlefieldList,
-    BattlefieldPort,
-    BattlemasterHello,
-    BattlemasterJoin,
-    BattlemasterJoinArena,
-    BattlemasterJoinBrawl,
-    BattlemasterJoinSkirmish,
-    BattlenetChallengeResponse,
-    BattlenetRequest,
-    BattlePayAckFailedResponse,
-    BattlePayCancelOpenCheckout,
-    BattlePayConfirmPurchaseResponse,
-    BattlePayDistributionAssignToTarget,
-    BattlePayDistributionAssignVas,
-    BattlePayGetProductList,
-    BattlePayGetPurchaseList,
-    BattlePayOpenCheckout,
-    BattlePayRequestPriceInfo,
-    BattlePayStartPurchase,
-    BattlePayStartVasPurchase,
-    BattlePetClearFanfare,
-    BattlePetDeletePet,
-    BattlePetDeletePetCheat,
-    BattlePetModifyName,
-    BattlePetRequestJournal,
-    BattlePetRequestJournalLock,
-    BattlePetSetBattleSlot,
-    BattlePetSetFlags,
-    BattlePetSummon,
-    BattlePetUpdateDisplayNotify,
-    BattlePetUpdateNotify,
-    BeginTrade,
-    BinderActivate,
-    BlackMarketBidOnItem,
-    BlackMarketOpen,
-    BlackMarketRequestItems,
-    BonusRoll,
-    BugReport,
-    BusyTrade,
-    BuyBackItem,
-    BuyBankSlot,
-    BuyItem,
-    BuyReagentBank,
-    CageBattlePet,
-    CalendarAddEvent,
-    CalendarCommunityInvite,
-    CalendarComplain,
-    CalendarCopyEvent,
-    CalendarEventSignUp,
-    CalendarGet,
-    CalendarGetEvent,
-    CalendarGetNumPending,
-    CalendarInvite,
-    CalendarModeratorStatus,
-    CalendarRemoveEvent,
-    CalendarRemoveInvite,
-    CalendarRsvp,
-    CalendarStatus,
-    CalendarUpdateEvent,
-    CancelAura,
-    CancelAutoRepeatSpell,
-    CancelCast,
-    CancelChannelling,
-    CancelGrowthAura,
-    CancelMasterLootRoll,
-    CancelModSpeedNoControlAuras,
-    CancelMountAura,
-    CancelQueuedSpell,
-    CancelTempEnchantment,
-    CancelTrade,
-    CanDuel,
-    CanRedeemTokenForBalance,
-    CastSpell,
-    ChallengeModeRequestLeaders,
-    ChangeBagSlotFlag,
-    ChangeBankBagSlotFlag,
-    ChangeMonumentAppearance,
-    ChangeRealmTicket,
-    ChangeSubGroup,
-    CharacterCheckUpgrade,
-    CharacterRenameRequest,
-    CharacterUpgradeManualUnrevokeRequest,
-    CharacterUpgradeStart,
-    CharCustomize,
-    CharDelete,
-    CharRaceOrFactionChange,
-    ChatAddonMessage,
-    ChatAddonMessageTargeted,
-    ChatChannelAnnouncements,
-    ChatChannelBan,
-    ChatChannelDeclineInvite,
-    ChatChannelDisplayList,
-    ChatChannelInvite,
-    ChatChannelKick,
-    ChatChannelList,
-    ChatChannelModerator,
-    ChatChannelOwner,
-    ChatChannelPassword,
-    ChatChannelSetOwner,
-    ChatChannelSilenceAll,
-    ChatChannelUnban,
-    ChatChannelUnmoderator,
-    ChatChannelUnsilenceAll,
-    ChatJoinChannel,
-    ChatLeaveChannel,
-    ChatMessageAfk,
-    ChatMessageChannel,
-    ChatMessageDnd,
-    ChatMessageEmote,
-    ChatMessageGuild,
-    ChatMessageInstanceChat,
-    ChatMessageOfficer,
-    ChatMessageParty,
-    ChatMessageRaid,
-    ChatMessageRaidWarning,
-    ChatMessageSay,
-    ChatMessageWhisper,
-    ChatMessageYell,
-    ChatRegisterAddonPrefixes,
-    ChatReportFiltered,
-    ChatReportIgnored,
-    ChatUnregisterAllAddonPrefixes,
-    CheckCharacterNameAvailability,
-    CheckIsAdventureMapPoiValid,
-    ChoiceResponse,
-    ChromieTimeSelectExpansion,
-    ClaimWeeklyReward,
-    ClearNewAppearance,
-    ClearRaidMarker,
-    ClearTradeItem,
-    ClientPortGraveyard,
-    CloseInteraction,
-    CloseQuestChoice,
-    CloseRuneforgeInteraction,
-    ClubFinderApplicationResponse,
-    ClubFinderGetApplicantsList,
-    ClubFinderPost,
-    ClubFinderRequestClubsData,
-    ClubFinderRequestClubsList,
-    ClubFinderRequestMembershipToClub,
-    ClubFinderRequestPendingClubsList,
-    ClubFinderRequestSubscribedClubPostingIds,
-    ClubFinderRespondToApplicant,
-    ClubPresenceSubscribe,
-    CollectionItemSetFavorite,
-    CommentatorEnable,
-    CommentatorEnterInstance,
-    CommentatorExitInstance,
-    CommentatorGetMapInfo,
-    CommentatorGetPlayerCooldowns,
-    CommentatorGetPlayerInfo,
-    CommentatorSpectate,
-    CommentatorStartWargame,
-    CommerceTokenGetCount,
-    CommerceTokenGetLog,
-    CommerceTokenGetMarketPrice,
-    Complaint,
-    CompleteCinematic,
-    CompleteMovie,
-    ConfirmArtifactRespec,
-    ConfirmRespecWipe,
-    ConnectToFailed,
-    ConsumableTokenBuy,
-    ConsumableTokenBuyAtMarketPrice,
-    ConsumableTokenCanVeteranBuy,
-    ConsumableTokenRedeem,
-    ConsumableTokenRedeemConfirmation,
-    ContributionContribute,
-    ContributionLastUpdateRequest,
-    ConversationCinematicReady,
-    ConversationLineStarted,
-    ConvertRaid,
-    CovenantRenownRequestCatchupState,
-    CreateCharacter,
-    CreateShipment,
-    DbQueryBulk,
-    DeclineGuildInvites,
-    DeclinePetition,
-    DeleteEquipmentSet,
-    DelFriend,
-    DelIgnore,
-    DepositReagentBank,
-    DestroyItem,
-    DfBootPlayerVote,
-    DfConfirmExpandSearch,
-    DfGetJoinStatus,
-    DfGetSystemInfo,
-    DfJoin,
-    DfLeave,
-    DfProposalResponse,
-    DfReadyCheckResponse,
-    DfSetRoles,
-    DfTeleport,
-    DiscardedTimeSyncAcks,
-    DismissCritter,
-    DoCountdown,
-    DoMasterLootRoll,
-    DoReadyCheck,
-    DuelResponse,
-    EjectPassenger,
-    Emote,
-    EnableNagle,
-    EnableTaxiNode,
-    EngineSurvey,
-    EnterEncryptedModeAck,
-    EnumCharacters,
-    EnumCharactersDeletedByClient,
-    FarSight,
-    GameEventDebugDisable,
-    GameEventDebugEnable,
-    GameObjReportUse,
-    GameObjUse,
-    GarrisonAddFollowerHealth,
-    GarrisonAssignFollowerToBuilding,
-    GarrisonCancelConstruction,
-    GarrisonCheckUpgradeable,
-    GarrisonCompleteMission,
-    GarrisonFullyHealAllFollowers,
-    GarrisonGenerateRecruits,
-    GarrisonGetClassSpecCategoryInfo,
-    GarrisonGetMapData,
-    GarrisonGetMissionReward,
-    GarrisonLearnTalent,
-    GarrisonMissionBonusRoll,
-    GarrisonPurchaseBuilding,
-    GarrisonRecruitFollower,
-    GarrisonRemoveFollower,
-    GarrisonRemoveFollowerFromBuilding,
-    GarrisonRenameFollower,
-    GarrisonRequestBlueprintAndSpecializationData,
-    GarrisonRequestShipmentInfo,
-    GarrisonResearchTalent,
-    GarrisonSetBuildingActive,
-    GarrisonSetFollowerFavorite,
-    GarrisonSetFollowerInactive,
-    GarrisonSetRecruitmentPreferences,
-    GarrisonSocketTalent,
-    GarrisonStartMission,
-    GarrisonSwapBuildings,
-    GenerateRandomCharacterName,
-    GetAccountCharacterList,
-    GetAccountNotifications,
-    GetGarrisonInfo,
-    GetItemPurchaseData,
-    GetLandingPageShipments,
-    GetMirrorImageData,
-    GetPvpOptionsEnabled,
-    GetRafAccountInfo,
-    GetRemainingGameTime,
-    GetTrophyList,
-    GetUndeleteCharacterCooldownStatus,
-    GetVasAccountCharacterList,
-    GetVasTransferTargetRealmList,
-    GmTicketAcknowledgeSurvey,
-    GmTicketGetCaseStatus,
-    GmTicketGetSystemStatus,
-    GossipRefreshOptions,
-    GossipSelectOption,
-    GuildAddBattlenetFriend,
-    GuildAddRank,
-    GuildAssignMemberRank,
-    GuildAutoDeclineInvitation,
-    GuildBankActivate,
-    GuildBankBuyTab,
-    GuildBankDepositMoney,
-    GuildBankLogQuery,
-    GuildBankQueryTab,
-    GuildBankRemainingWithdrawMoneyQuery,
-    GuildBankSetTabText,
-    GuildBankTextQuery,
-    GuildBankUpdateTab,
-    GuildBankWithdrawMoney,
-    GuildChallengeUpdateRequest,
-    GuildChangeNameRequest,
-    GuildDeclineInvitation,
-    GuildDelete,
-    GuildDeleteRank,
-    GuildDemoteMember,
-    GuildEventLogQuery,
-    GuildGetAchievementMembers,
-    GuildGetRanks,
-    GuildGetRoster,
-    GuildInviteByName,
-    GuildLeave,
-    GuildNewsUpdateSticky,
-    GuildOfficerRemoveMember,
-    GuildPermissionsQuery,
-    GuildPromoteMember,
-    GuildQueryMembersForRecipe,
-    GuildQueryMemberRecipes,
-    GuildQueryNews,
-    GuildQueryRecipes,
-    GuildReplaceGuildMaster,
-    GuildSetAchievementTracking,
-    GuildSetFocusedAchievement,
-    GuildSetGuildMaster,
-    GuildSetMemberNote,
-    GuildSetRankPermissions,
-    GuildShiftRank,
-    GuildUpdateInfoText,
-    GuildUpdateMotdText,
-    HearthAndResurrect,
-    HideQuestChoice,
-    HotfixRequest,
-    IgnoreTrade,
-    InitiateRolePoll,
-    InitiateTrade,
-    Inspect,
-    InstanceLockResponse,
-    IslandQueue,
-    ItemPurchaseRefund,
-    ItemTextQuery,
-    JoinPetBattleQueue,
-    JoinRatedBattleground,
-    KeepAlive,
-    KeyboundOverride,
-    LatencyReport,
-    LearnPvpTalents,
-    LearnTalents,
-    LeaveGroup,
-    LeavePetBattleQueue,
-    LfgListApplyToGroup,
-    LfgListCancelApplication,
-    LfgListDeclineApplicant,
-    LfgListGetStatus,
-    LfgListInviteApplicant,
-    LfgListInviteResponse,
-    LfgListJoin,
-    LfgListLeave,
-    LfgListSearch,
-    LfgListUpdateRequest,
-    ListInventory,
-    LiveRegionAccountRestore,
-    LiveRegionCharacterCopy,
-    LiveRegionGetAccountCharacterList,
-    LiveRegionKeyBindingsCopy,
-    LoadingScreenNotify,
-    LoadSelectedTrophy,
-    LogoutCancel,
-    LogoutInstant,
-    LogoutRequest,
-    LogDisconnect,
-    LogStreamingError,
-    LootItem,
-    LootMoney,
-    LootRelease,
-    LootRoll,
-    LootUnit,
-    LowLevelRaid1,
-    LowLevelRaid2,
-    MailCreateTextItem,
-    MailDelete,
-    MailGetList,
-    MailMarkAsRead,
-    MailReturnToSender,
-    MailTakeItem,
-    MailTakeMoney,
-    MakeContitionalAppearancePermanent,
-    MasterLootItem,
-    MergeGuildBankItemWithGuildBankItem,
-    MergeGuildBankItemWithItem,
-    MergeItemWithGuildBankItem,
-    MinimapPing,
-    MissileTrajectoryCollision,
-    MountClearFanfare,
-    MountSetFavorite,
-    MountSpecialAnim,
-    MoveApplyInertiaAck,
-    MoveApplyMovementForceAck,
-    MoveChangeTransport,
-    MoveChangeVehicleSeats,
-    MoveCollisionDisableAck,
-    MoveCollisionEnableAck,
-    MoveDismissVehicle,
-    MoveDoubleJump,
-    MoveEnableDoubleJumpAck,
-    MoveEnableSwimToFlyTransAck,
-    MoveFallLand,
-    MoveFallReset,
-    MoveFeatherFallAck,
-    MoveForceFlightBackSpeedChangeAck,
-    MoveForceFlightSpeedChangeAck,
-    MoveForcePitchRateChangeAck,
-    MoveForceRootAck,
-    MoveForceRunBackSpeedChangeAck,
-    MoveForceRunSpeedChangeAck,
-    MoveForceSwimBackSpeedChangeAck,
-    MoveForceSwimSpeedChangeAck,
-    MoveForceTurnRateChangeAck,
-    MoveForceUnrootAck,
-    MoveForceWalkSpeedChangeAck,
-    MoveGravityDisableAck,
-    MoveGravityEnableAck,
-    MoveGuildBankItem,
-    MoveHeartbeat,
-    MoveHoverAck,
-    MoveInertiaDisableAck,
-    MoveInertiaEnableAck,
-    MoveInitActiveMoverComplete,
-    MoveJump,
-    MoveKnockBackAck,
-    MoveRemoveInertiaAck,
-    MoveRemoveMovementForces,
-    MoveRemoveMovementForceAck,
-    MoveSeamlessTransferComplete,
-    MoveSetCanFlyAck,
-    MoveSetCanTurnWhileFallingAck,
-    MoveSetCollisionHeightAck,
-    MoveSetFacing,
-    MoveSetFacingHeartbeat,
-    MoveSetFly,
-    MoveSetIgnoreMovementForcesAck,
-    MoveSetModMovementForceMagnitudeAck,
-    MoveSetPitch,
-    MoveSetRunMode,
-    MoveSetTurnRateCheat,
-    MoveSetVehicleRecIdAck,
-    MoveSetWalkMode,
-    MoveSplineDone,
-    MoveStartAscend,
-    MoveStartBackward,
-    MoveStartDescend,
-    MoveStartForward,
-    MoveStartPitchDown,
-    MoveStartPitchUp,
-    MoveStartStrafeLeft,
-    MoveStartStrafeRight,
-    MoveStartSwim,
-    MoveStartTurnLeft,
-    MoveStartTurnRight,
-    MoveStop,
-    MoveStopAscend,
-    MoveStopPitch,
-    MoveStopStrafe,
-    MoveStopSwim,
-    MoveStopTurn,
-    MoveTeleportAck,
-    MoveTimeSkipped,
-    MoveUpdateFallSpeed,
-    MoveWaterWalkAck,
-    MythicPlusRequestMapStats,
-    NeutralPlayerSelectFaction,
-    NextCinematicCamera,
-    ObjectUpdateFailed,
-    ObjectUpdateRescued,
-    OfferPetition,
-    OpeningCinematic,
-    OpenItem,
-    OpenMissionNpc,
-    OpenShipmentNpc,
-    OpenTradeskillNpc,
-    OptOutOfLoot,
-    OverrideScreenFlash,
-    PartyInvite,
-    PartyInviteResponse,
-    PartyUninvite,
-    PerformItemInteraction,
-    PetitionBuy,
-    PetitionRenameGuild,
-    PetitionShowList,
-    PetitionShowSignatures,
-    PetAbandon,
-    PetAction,
-    PetBattleFinalNotify,
-    PetBattleInput,
-    PetBattleQueueProposeMatchResult,
-    PetBattleQuitNotify,
-    PetBattleReplaceFrontPet,
-    PetBattleRequestPvp,
-    PetBattleRequestUpdate,
-    PetBattleRequestWild,
-    PetBattleScriptErrorNotify,
-    PetBattleWildLocationFail,
-    PetCancelAura,
-    PetCastSpell,
-    PetRename,
-    PetSetAction,
-    PetSpellAutocast,
-    PetStopAttack,
-    Ping(Ping),
-    PlayerLogin,
-    PushQuestToParty,
-    PvpLogData,
-    QueryBattlePetName,
-    QueryCorpseLocationFromClient,
-    QueryCorpseTransport,
-    QueryCountdownTimer,
-    QueryCreature,
-    QueryGameObject,
-    QueryGarrisonPetName,
-    QueryGuildInfo,
-    QueryInspectAchievements,
-    QueryNextMailTime,
-    QueryNpcText,
-    QueryPageText,
-    QueryPetition,
-    QueryPetName,
-    QueryPlayerNames,
-    QueryPlayerNamesForCommunity,
-    QueryPlayerNameByCommunityId,
-    QueryQuestCompletionNpcs,
-    QueryQuestInfo,
-    QueryRealmName,
-    QueryScenarioPoi,
-    QueryTime,
-    QueryTreasurePicker,
-    QueryVoidStorage,
-    QuestConfirmAccept,
-    QuestGiverAcceptQuest,
-    QuestGiverChooseReward,
-    QuestGiverCloseQuest,
-    QuestGiverCompleteQuest,
-    QuestGiverHello,
-    QuestGiverQueryQuest,
-    QuestGiverRequestReward,
-    QuestGiverStatusMultipleQuery,
-    QuestGiverStatusQuery,
-    QuestLogRemoveQuest,
-    QuestPoiQuery,
-    QuestPushResult,
-    QuestSessionBeginResponse,
-    QuestSessionRequestStart,
-    QuestSessionRequestStop,
-    QueuedMessagesEnd,
-    QuickJoinAutoAcceptRequests,
-    QuickJoinRequestInvite,
-    QuickJoinRequestInviteWithConfirmation,
-    QuickJoinRespondToInvite,
-    QuickJoinSignalToastDisplayed,
-    RafClaimActivityReward,
-    RafClaimNextReward,
-    RafGenerateRecruitmentLink,
-    RafUpdateRecruitmentInfo,
-    RandomRoll,
-    ReadyCheckResponse,
-    ReadItem,
-    ReclaimCorpse,
-    RemoveNewItem,
-    RemoveRafRecruit,
-    ReorderCharacters,
-    RepairItem,
-    ReplaceTrophy,
-    RepopRequest,
-    ReportClientVariables,
-    ReportEnabledAddons,
-    ReportKeybindingExecutionCounts,
-    ReportPvpPlayerAfk,
-    ReportServerLag,
-    ReportStuckInCombat,
-    RequestAccountData,
-    RequestAreaPoiUpdate,
-    RequestBattlefieldStatus,
-    RequestCategoryCooldowns,
-    RequestCemeteryList,
-    RequestCharacterGuildFollowInfo,
-    RequestConquestFormulaConstants,
-    RequestCovenantCallings,
-    RequestCrowdControlSpell,
-    RequestForcedReactions,
-    RequestGarrisonTalentWorldQuestUnlocks,
-    RequestGuildPartyState,
-    RequestGuildRewardsList,
-    RequestLatestSplashScreen,
-    RequestLfgListBlacklist,
-    RequestMythicPlusAffixes,
-    RequestMythicPlusSeasonData,
-    RequestPartyJoinUpdates,
-    RequestPartyMemberStats,
-    RequestPetInfo,
-    RequestPlayedTime,
-    RequestPvpRewards,
-    RequestRaidInfo,
-    RequestRatedPvpInfo,
-    RequestRealmGuildMasterInfo,
-    RequestResearchHistory,
-    RequestScheduledPvpInfo,
-    RequestStabledPets,
-    RequestVehicleExit,
-    RequestVehicleNextSeat,
-    RequestVehiclePrevSeat,
-    RequestVehicleSwitchSeat,
-    RequestWeeklyRewards,
-    RequestWorldQuestUpdate,
-    ResetChallengeMode,
-    ResetChallengeModeCheat,
-    ResetInstances,
-    ResurrectResponse,
-    RevertMonumentAppearance,
-    RideVehicleInteract,
-    SaveCufProfiles,
-    SaveEquipmentSet,
-    SaveGuildEmblem,
-    ScenePlaybackCanceled,
-    ScenePlaybackComplete,
-    SceneTriggerEvent,
-    SelfRes,
-    SellItem,
-    SendCharacterClubInvitation,
-    SendContactList,
-    SendMail,
-    SendTextEmote,
-    ServerTimeOffsetRequest,
-    SetAchievementsHidden,
-    SetActionBarToggles,
-    SetActionButton,
-    SetActiveMover,
-    SetAdvancedCombatLogging,
-    SetAssistantLeader,
-    SetBackpackAutosortDisabled,
-    SetBankAutosortDisabled,
-    SetChatDisabled,
-    SetContactNotes,
-    SetCurrencyFlags,
-    SetDifficultyId,
-    SetDungeonDifficulty,
-    SetEveryoneIsAssistant,
-    SetFactionAtWar,
-    SetFactionInactive,
-    SetFactionNotAtWar,
-    SetGameEventDebugViewState,
-    SetInsertItemsLeftToRight,
-    SetLootMethod,
-    SetLootSpecialization,
-    SetPartyAssignment,
-    SetPartyLeader,
-    SetPetSlot,
-    SetPlayerDeclinedNames,
-    SetPreferredCemetery,
-    SetPvp,
-    SetRaidDifficulty,
-    SetRole,
-    SetSavedInstanceExtend,
-    SetSelection,
-    SetSheathed,
-    SetSortBagsRightToLeft,
-    SetTaxiBenchmarkMode,
-    SetTitle,
-    SetTradeCurrency,
-    SetTradeGold,
-    SetTradeItem,
-    SetUsingPartyGarrison,
-    SetWarMode,
-    SetWatchedFaction,
-    ShowTradeSkill,
-    SignPetition,
-    SilencePartyTalker,
-    SocketGems,
-    SortBags,
-    SortBankBags,
-    SortReagentBankBags,
-    SpellClick,
-    SpiritHealerActivate,
-    SplitGuildBankItem,
-    SplitGuildBankItemToInventory,
-    SplitItem,
-    SplitItemToGuildBank,
-    StandStateChange,
-    StartChallengeMode,
-    StartSpectatorWarGame,
-    StartWarGame,
-    StoreGuildBankItem,
-    SubmitUserFeedback,
-    SubscriptionInterstitialResponse,
-    SummonResponse,
-    SupportTicketSubmitComplaint,
-    SurrenderArena,
-    SuspendCommsAck,
-    SuspendTokenResponse,
-    SwapGuildBankItemWithGuildBankItem,
-    SwapInvItem,
-    SwapItem,
-    SwapItemWithGuildBankItem,
-    SwapSubGroups,
-    SwapVoidItem,
-    TabardVendorActivate,
-    TalkToGossip,
-    TaxiNodeStatusQuery,
-    TaxiQueryAvailableNodes,
-    TaxiRequestEarlyLanding,
-    TimeAdjustmentResponse,
-    TimeSyncResponse,
-    TimeSyncResponseDropped,
-    TimeSyncResponseFailed,
-    ToggleDifficulty,
-    TogglePvp,
-    TotemDestroyed,
-    ToyClearFanfare,
-    TradeSkillSetFavorite,
-    TrainerBuySpell,
-    TrainerList,
-    TransmogrifyItems,
-    TurnInPetition,
-    Tutorial,
-    TwitterCheckStatus,
-    TwitterConnect,
-    TwitterDisconnect,
-    UiMapQuestLinesRequest,
-    UnacceptTrade,
-    UndeleteCharacter,
-    UnlearnSkill,
-    UnlearnSpecialization,
-    UnlockVoidStorage,
-    UpdateAccountData,
-    UpdateAreaTriggerVisual,
-    UpdateClientSettings,
-    UpdateMissileTrajectory,
-    UpdateRaidTarget,
-    UpdateSpellVisual,
-    UpdateVasPurchaseStates,
-    UpgradeGarrison,
-    UpgradeRuneforgeLegendary,
-    UsedFollow,
-    UseCritterItem,
-    UseEquipmentSet,
-    UseItem,
-    UseToy,
-    VasCheckTransferOk,
-    VasGetQueueMinutes,
-    VasGetServiceStatus,
-    ViolenceLevel,
-    VoiceChannelSttTokenRequest,
-    VoiceChatJoinChannel,
-    VoiceChatLogin,
-    VoidStorageTransfer,
-    Warden3Data,
-    Who,
-    WhoIs,
-    WorldPortResponse,
-    WrapItem,
+pub struct ClientPacket {
+    pub opcode: OpcodeClient,
+    body: Box<dyn ClientPacketBody>,
+}
+
+pub trait ClientPacketBody: Debug + Send + Sync {
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Debug + Send + Sync + 'static> ClientPacketBody for T {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Registered by `#[client_packet(Opcode)]` for every packet the server can read.
+pub struct ClientPacketParser {
+    pub opcode: OpcodeClient,
+    pub parse: fn(&[u8]) -> anyhow::Result<ClientPacket>,
+}
+
+inventory::collect!(ClientPacketParser);
+
+impl ClientPacketParser {
+    pub fn find(opcode: OpcodeClient) -> Option<&'static ClientPacketParser> {
+        inventory::iter::<ClientPacketParser>
+            .into_iter()
+            .find(|parser| parser.opcode == opcode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::auth::{AuthResponse, Ping};
+    use crate::packets::system::SetTimeZoneInformation;
+    use rustycraft_protocol::rpc_responses::WowRpcResponse;
+
+    #[test]
+    fn test_client_packet_round_trip() {
+        assert_eq!(<Ping as IntoClientPacket>::OPCODE, OpcodeClient::Ping);
+        let mut data = (OpcodeClient::Ping as u16).to_le_bytes().to_vec();
+        data.extend([7, 0, 0, 0, 30, 0, 0, 0]);
+        let packet = ClientPacket::parse(&data, WorldProtocol::latest()).unwrap();
+        assert_eq!(packet.opcode, OpcodeClient::Ping);
+        let ping = Ping::try_from(packet).unwrap();
+        assert_eq!((ping.serial, ping.latency), (7, 30));
+        let disconnect = ClientPacket::parse_body(OpcodeClient::LogDisconnect, &[0; 4]).unwrap();
+        assert!(Ping::try_from(disconnect).is_err());
+
+        // No struct reads it.
+        assert!(ClientPacket::parse_body(OpcodeClient::AcceptTrade, &[0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_server_packet_serialize() {
+        let mut time_zone = SetTimeZoneInformation::new("Europe/Paris".into(), "UTC".into());
+        assert_eq!(time_zone.get_opcode(), OpcodeServer::SetTimeZoneInformation);
+        // Constructors leave the length fields to serialize().
+        let serialized = time_zone.serialize(WorldProtocol::latest()).unwrap();
+        assert_eq!(
+            serialized[..2],
+            (OpcodeServer::SetTimeZoneInformation as u16).to_le_bytes()
+        );
+        assert_eq!(&serialized[2..4], &[0x18, 0x0C]);
+        assert_eq!(&serialized[4..], b"Europe/ParisUTC");
+
+        let mut response = AuthResponse::new(WowRpcResponse::Ok, None, None);
+        let constructed = response.to_bytes().unwrap();
+        let serialized = response.serialize(WorldProtocol::latest()).unwrap();
+        assert_eq!(serialized[2..], constructed[..]);
+        assert_eq!(response.connection(), ConnectionType::ConnectionTypeRealm);
+    }
 }
@@ -1,42 +1,34 @@
-use crate::packets::IntoServerPacket;
-use crate::OpcodeServer;
+use crate::packets::wire::{BitFlush, WireString};
 use deku::prelude::*;
+use rustycraft_packet_macros::server_packet;
 use rustycraft_protocol::expansions::Expansions;
 
+#[server_packet(SetTimeZoneInformation)]
 #[derive(Debug, DekuWrite)]
 pub struct SetTimeZoneInformation {
-    #[deku(bits = "7")]
+    #[deku(bits = "7", update = "self.server_tz.len()")]
     server_tz_len: u8,
-    #[deku(bits = "7")]
-    #[deku(pad_bits_after = "2")]
+    #[deku(bits = "7", update = "self.game_tz.len()")]
     game_tz_len: u8,
-    #[deku(count = "server_time_tz_len")]
-    #[deku(writer = "crate::utils::string_writer(deku::output, &self.server_tz)")]
-    server_tz: String,
-    #[deku(count = "game_time_tz_len")]
-    #[deku(writer = "crate::utils::string_writer(deku::output, &self.game_tz)")]
-    game_tz: String,
+    flush: BitFlush,
+    #[deku(ctx = "usize::from(*server_tz_len)")]
+    server_tz: WireString,
+    #[deku(ctx = "usize::from(*game_tz_len)")]
+    game_tz: WireString,
 }
 
 impl SetTimeZoneInformation {
-    pub fn default() -> SetTimeZoneInformation {
-        let server_tz = "Europe/Paris".to_owned();
-        let game_tz = "Europe/Paris".to_owned();
+    pub fn new(server_tz: String, game_tz: String) -> SetTimeZoneInformation {
         SetTimeZoneInformation {
-            server_tz_len: server_tz.len() as u8,
-            game_tz_len: game_tz.len() as u8,
-            server_tz,
-            game_tz,
+            server_tz_len: 0,
+            game_tz_len: 0,
+            flush: BitFlush,
+            server_tz: server_tz.into(),
+            game_tz: game_tz.into(),
         }
     }
 }
 
-impl IntoServerPacket for SetTimeZoneInformation {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::SetTimeZoneInformation
-    }
-}
-
 #[derive(Debug, DekuWrite)]
 #[deku(endian = "little")]
 pub struct SavedThrottleObjectState {
@@ -67,6 +59,7 @@ pub struct GameRuleValuePair {
     value: i32,
 }
 
+#[server_packet(FeatureSystemStatusGlueScreen)]
 #[derive(Debug, DekuWrite)]
 pub struct FeatureSystemStatusGlueScreen {
     #[deku(bits = "1")]
@@ -138,9 +131,9 @@ pub struct FeatureSystemStatusGlueScreen {
     game_rule_vals: Vec<GameRuleValuePair>,
 }
 
-impl IntoServerPacket for FeatureSystemStatusGlueScreen {
-    fn get_opcode(&self) -> OpcodeServer {
-        OpcodeServer::FeatureSystemStatusGlueScreen
+impl Default for FeatureSystemStatusGlueScreen {
+    fn default() -> Self {
+        FeatureSystemStatusGlueScreen::new()
     }
 }
 
@@ -0,0 +1,417 @@
+//! Encodings shared by world packets, the deku side of TrinityCore's `ByteBuffer`.
+//!
+//! Bit fields are written most significant bit first, like `ByteBuffer::WriteBits`. Packets are
+//! always read from whole bytes, so the distance to the next byte boundary is known from the
+//! length of what is left to read.
+
+use deku::bitvec::{BitSlice, BitVec, Msb0};
+use deku::ctx::{Endian, Limit, Size};
+use deku::error::NeedSize;
+use deku::prelude::*;
+use std::ops::Deref;
+
+fn flush_write(output: &mut BitVec<Msb0, u8>) {
+    let padding = (8 - output.len() % 8) % 8;
+    output.resize(output.len() + padding, false);
+}
+
+fn flush_read(input: &BitSlice<Msb0, u8>) -> &BitSlice<Msb0, u8> {
+    &input[input.len() % 8..]
+}
+
+fn read_bytes(
+    input: &BitSlice<Msb0, u8>,
+    len: usize,
+) -> Result<(&BitSlice<Msb0, u8>, Vec<u8>), DekuError> {
+    if input.len() < len * 8 {
+        return Err(DekuError::Incomplete(NeedSize::new(len * 8)));
+    }
+    Vec::read(input, Limit::new_count(len))
+}
+
+fn read_utf8(
+    input: &BitSlice<Msb0, u8>,
+    len: usize,
+) -> Result<(&BitSlice<Msb0, u8>, String), DekuError> {
+    let (rest, bytes) = read_bytes(input, len)?;
+    let string = String::from_utf8(bytes).map_err(|e| DekuError::Parse(e.to_string()))?;
+    Ok((rest, string))
+}
+
+/// `FlushBits()`, skips to the next byte boundary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BitFlush;
+
+impl DekuRead<'_> for BitFlush {
+    fn read(input: &BitSlice<Msb0, u8>, _: ()) -> Result<(&BitSlice<Msb0, u8>, Self), DekuError> {
+        Ok((flush_read(input), BitFlush))
+    }
+}
+
+impl DekuWrite for BitFlush {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, _: ()) -> Result<(), DekuError> {
+        flush_write(output);
+        Ok(())
+    }
+}
+
+/// Bytes of a string whose length is written elsewhere in the packet, usually in a bit field
+/// next to other lengths. The length is passed as context:
+///
+/// `#[deku(ctx = "usize::from(*name_len)")] name: WireString`
+///
+/// Writing checks the string still has that length.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WireString(pub String);
+
+impl DekuRead<'_, usize> for WireString {
+    fn read(
+        input: &BitSlice<Msb0, u8>,
+        len: usize,
+    ) -> Result<(&BitSlice<Msb0, u8>, Self), DekuError> {
+        let (rest, string) = read_utf8(input, len)?;
+        Ok((rest, WireString(string)))
+    }
+}
+
+impl DekuWrite<usize> for WireString {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, len: usize) -> Result<(), DekuError> {
+        if self.0.len() != len {
+            return Err(DekuError::InvalidParam(format!(
+                "string of {} bytes written with a length of {}",
+                self.0.len(),
+                len
+            )));
+        }
+        self.0.as_bytes().write(output, ())
+    }
+}
+
+impl From<String> for WireString {
+    fn from(string: String) -> Self {
+        WireString(string)
+    }
+}
+
+impl From<WireString> for String {
+    fn from(string: WireString) -> Self {
+        string.0
+    }
+}
+
+impl Deref for WireString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// `WriteBits(str.length(), BITS); FlushBits(); WriteString(str)`, for a string whose length
+/// comes right before it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitString<const BITS: usize>(pub String);
+
+impl<const BITS: usize> DekuRead<'_> for BitString<BITS> {
+    fn read(input: &BitSlice<Msb0, u8>, _: ()) -> Result<(&BitSlice<Msb0, u8>, Self), DekuError> {
+        let (rest, len) = u32::read(input, (Endian::Big, Size::Bits(BITS)))?;
+        let (rest, string) = read_utf8(flush_read(rest), len as usize)?;
+        Ok((rest, BitString(string)))
+    }
+}
+
+impl<const BITS: usize> DekuWrite for BitString<BITS> {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, _: ()) -> Result<(), DekuError> {
+        let len = self.0.len();
+        if len >= 1 << BITS {
+            return Err(DekuError::InvalidParam(format!(
+                "string of {} bytes does not fit a {} bit length",
+                len, BITS
+            )));
+        }
+        (len as u32).write(output, (Endian::Big, Size::Bits(BITS)))?;
+        flush_write(output);
+        self.0.as_bytes().write(output, ())
+    }
+}
+
+impl<const BITS: usize> From<String> for BitString<BITS> {
+    fn from(string: String) -> Self {
+        BitString(string)
+    }
+}
+
+impl<const BITS: usize> From<BitString<BITS>> for String {
+    fn from(string: BitString<BITS>) -> Self {
+        string.0
+    }
+}
+
+impl<const BITS: usize> Deref for BitString<BITS> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// `WriteBit(value.has_value()); if (value) data << *value;`, a presence bit with the value
+/// right after it.
+///
+/// When other bits sit between the flag and the value, use a one bit `bool` with
+/// `update = "self.value.is_some()"` and `cond` on the `Option` instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitOption<T>(pub Option<T>);
+
+impl<'a, T: DekuRead<'a, Ctx>, Ctx: Copy> DekuRead<'a, Ctx> for BitOption<T> {
+    fn read(
+        input: &'a BitSlice<Msb0, u8>,
+        ctx: Ctx,
+    ) -> Result<(&'a BitSlice<Msb0, u8>, Self), DekuError> {
+        let (rest, present) = bool::read(input, Size::Bits(1))?;
+        if !present {
+            return Ok((rest, BitOption(None)));
+        }
+        let (rest, value) = T::read(rest, ctx)?;
+        Ok((rest, BitOption(Some(value))))
+    }
+}
+
+impl<T: DekuWrite<Ctx>, Ctx: Copy> DekuWrite<Ctx> for BitOption<T> {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, ctx: Ctx) -> Result<(), DekuError> {
+        self.0.is_some().write(output, Size::Bits(1))?;
+        match &self.0 {
+            Some(value) => value.write(output, ctx),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T> From<Option<T>> for BitOption<T> {
+    fn from(value: Option<T>) -> Self {
+        BitOption(value)
+    }
+}
+
+/// `data << uint32(values.size()); for (value : values) data << value;`
+///
+/// The count is always a little endian `u32`, the context is handed to every element.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CountedVec<T>(pub Vec<T>);
+
+impl<'a, T: DekuRead<'a, Ctx>, Ctx: Copy> DekuRead<'a, Ctx> for CountedVec<T> {
+    fn read(
+        input: &'a BitSlice<Msb0, u8>,
+        ctx: Ctx,
+    ) -> Result<(&'a BitSlice<Msb0, u8>, Self), DekuError> {
+        let (rest, count) = u32::read(input, Endian::Little)?;
+        // Every element takes at least a bit, don't let a forged count reserve gigabytes.
+        if count as usize > rest.len() {
+            return Err(DekuError::Incomplete(NeedSize::new(count as usize)));
+        }
+        let (rest, values) = Vec::read(rest, (Limit::new_count(count as usize), ctx))?;
+        Ok((rest, CountedVec(values)))
+    }
+}
+
+impl<T: DekuWrite<Ctx>, Ctx: Copy> DekuWrite<Ctx> for CountedVec<T> {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, ctx: Ctx) -> Result<(), DekuError> {
+        u32::try_from(self.0.len())?.write(output, Endian::Little)?;
+        self.0.write(output, ctx)
+    }
+}
+
+impl<T> From<Vec<T>> for CountedVec<T> {
+    fn from(values: Vec<T>) -> Self {
+        CountedVec(values)
+    }
+}
+
+impl<T> Deref for CountedVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> IntoIterator for CountedVec<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// A 128 bit guid without its zero bytes: a mask of the non-zero bytes of each half, then those
+/// bytes, low half first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PackedGuid {
+    pub low: u64,
+    pub high: u64,
+}
+
+impl PackedGuid {
+    pub fn new(low: u64, high: u64) -> PackedGuid {
+        PackedGuid { low, high }
+    }
+
+    fn pack(value: u64) -> (u8, Vec<u8>) {
+        let mut mask = 0;
+        let mut bytes = Vec::new();
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            if byte != 0 {
+                mask |= 1 << i;
+                bytes.push(byte);
+            }
+        }
+        (mask, bytes)
+    }
+
+    fn unpack(
+        input: &BitSlice<Msb0, u8>,
+        mask: u8,
+    ) -> Result<(&BitSlice<Msb0, u8>, u64), DekuError> {
+        let (rest, bytes) = read_bytes(input, mask.count_ones() as usize)?;
+        let mut bytes = bytes.into_iter();
+        let mut value = [0; 8];
+        for (i, byte) in value.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 {
+                *byte = bytes.next().unwrap_or_default();
+            }
+        }
+        Ok((rest, u64::from_le_bytes(value)))
+    }
+}
+
+impl DekuRead<'_> for PackedGuid {
+    fn read(input: &BitSlice<Msb0, u8>, _: ()) -> Result<(&BitSlice<Msb0, u8>, Self), DekuError> {
+        let (rest, low_mask) = u8::read(input, ())?;
+        let (rest, high_mask) = u8::read(rest, ())?;
+        let (rest, low) = PackedGuid::unpack(rest, low_mask)?;
+        let (rest, high) = PackedGuid::unpack(rest, high_mask)?;
+        Ok((rest, PackedGuid { low, high }))
+    }
+}
+
+impl DekuWrite for PackedGuid {
+    fn write(&self, output: &mut BitVec<Msb0, u8>, _: ()) -> Result<(), DekuError> {
+        let (low_mask, low) = PackedGuid::pack(self.low);
+        let (high_mask, high) = PackedGuid::pack(self.high);
+        low_mask.write(output, ())?;
+        high_mask.write(output, ())?;
+        low.write(output, ())?;
+        high.write(output, ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deku::bitvec::BitView;
+
+    #[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+    struct Names {
+        #[deku(bits = "1")]
+        flag: bool,
+        #[deku(bits = "7")]
+        first_len: u8,
+        #[deku(bits = "4")]
+        second_len: u8,
+        flush: BitFlush,
+        #[deku(ctx = "usize::from(*first_len)")]
+        first: WireString,
+        #[deku(ctx = "usize::from(*second_len)")]
+        second: WireString,
+    }
+
+    #[test]
+    fn test_wire_strings() {
+        let names = Names {
+            flag: true,
+            first_len: 2,
+            second_len: 3,
+            flush: BitFlush,
+            first: WireString("ab".to_string()),
+            second: WireString("cde".to_string()),
+        };
+        let bytes = names.to_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            vec![0b1000_0010, 0b0011_0000, b'a', b'b', b'c', b'd', b'e']
+        );
+        assert_eq!(Names::from_bytes((&bytes, 0)).unwrap().1, names);
+
+        let stale = Names {
+            first_len: 1,
+            ..names
+        };
+        assert!(stale.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_bit_string() {
+        let string = BitString::<11>("hello".to_string());
+        let mut output = BitVec::new();
+        string.write(&mut output, ()).unwrap();
+        assert_eq!(output.as_raw_slice(), b"\x00\xa0hello");
+        let (rest, read) = BitString::<11>::read(output.as_bitslice(), ()).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(read, string);
+
+        let too_long = BitString::<2>("four".to_string());
+        assert!(too_long.write(&mut BitVec::new(), ()).is_err());
+    }
+
+    #[test]
+    fn test_bit_option() {
+        let mut output = BitVec::new();
+        BitOption(Some(0x0102u16))
+            .write(&mut output, Endian::Little)
+            .unwrap();
+        BitOption::<u16>(None)
+            .write(&mut output, Endian::Little)
+            .unwrap();
+        BitFlush.write(&mut output, ()).unwrap();
+        assert_eq!(output.as_raw_slice(), &[0x81, 0x00, 0x80]);
+
+        let (rest, some) = BitOption::<u16>::read(output.as_bitslice(), Endian::Little).unwrap();
+        let (rest, none) = BitOption::<u16>::read(rest, Endian::Little).unwrap();
+        assert_eq!(some, BitOption(Some(0x0102)));
+        assert_eq!(none, BitOption(None));
+        assert_eq!(BitFlush::read(rest, ()).unwrap().0.len(), 0);
+    }
+
+    #[test]
+    fn test_counted_vec() {
+        let values = CountedVec(vec![1u16, 0x0203]);
+        let mut output = BitVec::new();
+        values.write(&mut output, Endian::Little).unwrap();
+        assert_eq!(output.as_raw_slice(), &[2, 0, 0, 0, 1, 0, 3, 2]);
+        let (rest, read) = CountedVec::<u16>::read(output.as_bitslice(), Endian::Little).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(read, values);
+
+        let truncated = [3, 0, 0, 0, 1, 0];
+        assert!(CountedVec::<u16>::read(truncated.view_bits(), Endian::Little).is_err());
+    }
+
+    #[test]
+    fn test_packed_guid() {
+        let guid = PackedGuid::new(0x0000_0000_0012_0034, 0x0800_0000_0000_0000);
+        let mut output = BitVec::new();
+        guid.write(&mut output, ()).unwrap();
+        assert_eq!(
+            output.as_raw_slice(),
+            &[0b0000_0101, 0b1000_0000, 0x34, 0x12, 0x08]
+        );
+        let (rest, read) = PackedGuid::read(output.as_bitslice(), ()).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(read, guid);
+
+        let mut output = BitVec::new();
+        PackedGuid::default().write(&mut output, ()).unwrap();
+        assert_eq!(output.as_raw_slice(), &[0, 0]);
+    }
+}
@@ -1,101 +1,208 @@
+use crate::builds::WorldProtocol;
 use crate::constants::{
-    AUTH_CHECK_SEED, CLIENT_TO_SERVER_CONNECTION, ENCRYPTION_KEY_SEED, REALM_WIN_AUTH_SEED,
-    SERVER_TO_CLIENT_CONNECTION, SESSION_KEY_SEED,
+    AUTH_CHECK_SEED, CLIENT_TO_SERVER_CONNECTION, CONTINUED_SESSION_SEED, ENCRYPTION_KEY_SEED,
+    REALM_WIN_AUTH_SEED, SERVER_TO_CLIENT_CONNECTION, SESSION_KEY_SEED,
+};
+use crate::opcodes::{ConnectionType, OpcodeClient};
+use crate::packets::auth::{
+    AuthChallenge, AuthContinuedSession, AuthSession, EncryptedMode, EnterEncryptedModeAck,
 };
-use crate::opcodes::OpcodeClient;
-use crate::packets::auth::{AuthChallenge, AuthSession, EncryptedMode};
 use crate::packets::ClientPacket;
 use crate::utils::generate_session_key;
 use crate::world_session::WorldClientSession;
-use crate::OpcodeServer;
-use bytes::BytesMut;
-use deku::{DekuContainerRead, DekuContainerWrite};
 use hmac::{Hmac, Mac};
 use rand::Rng;
 use rustycraft_common::Account;
+use rustycraft_protocol::rpc_responses::{RpcError, WowRpcResponse};
 use sha2::{Digest, Sha256};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use zeroize::Zeroizing;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Fails the handshake, `status` is what the client gets told.
+fn reject(status: WowRpcResponse, reason: impl Into<String>) -> anyhow::Error {
+    RpcError::new(status, reason.into()).into()
+}
+
 impl WorldClientSession {
     pub(crate) async fn initial_packets(&mut self) -> anyhow::Result<()> {
         self.client_socket_writer
-            .write(SERVER_TO_CLIENT_CONNECTION)
+            .write_all(SERVER_TO_CLIENT_CONNECTION)
             .await?;
-        let mut buf = BytesMut::with_capacity(CLIENT_TO_SERVER_CONNECTION.len());
-        self.client_socket_reader.read_buf(&mut buf).await?;
-        if buf.to_vec() != CLIENT_TO_SERVER_CONNECTION.to_vec() {
+        let mut reader = self
+            .client_socket_reader
+            .take()
+            .ok_or_else(|| anyhow!("Socket reader is already running"))?;
+        let mut buf = [0; CLIENT_TO_SERVER_CONNECTION.len()];
+        reader.read_exact(&mut buf).await?;
+        if &buf != CLIENT_TO_SERVER_CONNECTION {
             return Err(anyhow!("Bad response from client."));
         };
+        self.start_frame_reader(reader);
         Ok(())
     }
 
-    pub(crate) async fn init_encryption_state(&mut self) -> anyhow::Result<()> {
+    /// Reads `AuthSession` for a new session, or `AuthContinuedSession` for the instance
+    /// connection of an existing one, and derives the encryption key.
+    pub(crate) async fn init_encryption_state(&mut self) -> anyhow::Result<ConnectionType> {
+        // Clients solve the proof of work, but how they hash it is unknown so the response
+        // is not checked, like TrinityCore does.
         let dos_challenge = rand::thread_rng().gen();
-        let challenge = AuthChallenge::new(self.server_challenge.clone(), dos_challenge);
+        let challenge = AuthChallenge::new(self.server_challenge, dos_challenge);
         self.write_to_socket(Box::new(challenge)).await?;
 
-        let packet = self.read_client_packet().await?;
-        if let ClientPacket::AuthSession(session_pkt) = packet {
-            let acc: Account = self
-                .redis
-                .get(&session_pkt.realm_join_ticket)
-                .await
-                .unwrap();
-            let mut session_secret = acc.client_secret;
-            session_secret.extend(acc.server_secret);
-
-            let mut key_hasher = sha2::Sha256::new();
-            key_hasher.update(&session_secret);
-            key_hasher.update(&REALM_WIN_AUTH_SEED);
-            let digest_key_hash = key_hasher.finalize();
-
-            let mut hmac_digester = HmacSha256::new_from_slice(&digest_key_hash).unwrap();
-            hmac_digester.update(&session_pkt.local_challenge);
-            hmac_digester.update(&self.server_challenge);
-            hmac_digester.update(&AUTH_CHECK_SEED);
-            let res = hmac_digester.finalize().into_bytes();
-            assert_eq!(session_pkt.digest.as_slice(), &res[..24]);
-
-            let mut key_data_hasher = sha2::Sha256::new();
-            key_data_hasher.update(&session_secret);
-            let key_data_hash = key_data_hasher.finalize();
-
-            let mut session_key_hasher = HmacSha256::new_from_slice(&key_data_hash).unwrap();
-            session_key_hasher.update(&self.server_challenge);
-            session_key_hasher.update(&session_pkt.local_challenge);
-            session_key_hasher.update(&SESSION_KEY_SEED);
-            let session_key_seed = session_key_hasher.finalize().into_bytes();
-
-            let session_key = generate_session_key::<Sha256>(&session_key_seed, 40);
-            for (i, byte) in session_key.iter().enumerate() {
-                self.session_key[i] = *byte;
+        // Any supported build may be on the other side, its ticket tells which one it is.
+        let data = self
+            .read_raw_packet()
+            .await?
+            .ok_or_else(|| anyhow!("Client disconnected"))?;
+        let opcode = ClientPacket::opcode_value(&data)?;
+        self.protocol = WorldProtocol::detect(opcode, OpcodeClient::AuthSession)
+            .or_else(|| WorldProtocol::detect(opcode, OpcodeClient::AuthContinuedSession))
+            .ok_or_else(|| {
+                reject(
+                    WowRpcResponse::Denied,
+                    format!("Expected AuthSession packet, got opcode {:#06x}", opcode),
+                )
+            })?;
+        let packet = ClientPacket::parse(&data, self.protocol)
+            .map_err(|e| reject(WowRpcResponse::Denied, e.to_string()))?;
+        match packet.opcode {
+            OpcodeClient::AuthSession => {
+                self.auth_session(opcode, packet.take()?).await?;
+                Ok(ConnectionType::ConnectionTypeRealm)
             }
-
-            let mut encryption_key_hasher = HmacSha256::new_from_slice(&self.session_key).unwrap();
-            encryption_key_hasher.update(&session_pkt.local_challenge);
-            encryption_key_hasher.update(&self.server_challenge);
-            encryption_key_hasher.update(&ENCRYPTION_KEY_SEED);
-            let encryption_key = encryption_key_hasher.finalize().into_bytes();
-            for (i, b) in encryption_key[..16].iter().enumerate() {
-                self.encryption_key[i] = *b;
+            OpcodeClient::AuthContinuedSession => {
+                self.auth_continued_session(opcode, packet.take()?)?;
+                Ok(ConnectionType::ConnectionTypeInstance)
             }
-            Ok(())
-        } else {
-            Err(anyhow!("Expected AuthSession packet, got: {:?}", packet))
+            other => Err(reject(
+                WowRpcResponse::Denied,
+                format!("Expected AuthSession packet, got: {:?}", other),
+            )),
+        }
+    }
+
+    async fn auth_session(&mut self, opcode: u16, session_pkt: AuthSession) -> anyhow::Result<()> {
+        // Only read for now, a forged digest must not burn the ticket of the real client.
+        let acc: Account = self
+            .redis
+            .peek(&session_pkt.realm_join_ticket)
+            .await?
+            .ok_or_else(|| reject(WowRpcResponse::NoGameAccount, "Unknown realm join ticket"))?;
+        self.protocol = WorldProtocol::find(acc.build)
+            .filter(|protocol| protocol.client_opcode(opcode) == Some(OpcodeClient::AuthSession))
+            .ok_or_else(|| {
+                reject(
+                    WowRpcResponse::BadVersion,
+                    format!("Client build {} is not supported", acc.build),
+                )
+            })?;
+        let mut session_secret = Zeroizing::new(acc.client_secret);
+        session_secret.extend(acc.server_secret);
+
+        let mut key_hasher = sha2::Sha256::new();
+        key_hasher.update(&*session_secret);
+        key_hasher.update(REALM_WIN_AUTH_SEED);
+        let digest_key_hash = key_hasher.finalize();
+
+        let mut hmac_digester = HmacSha256::new_from_slice(&digest_key_hash).unwrap();
+        hmac_digester.update(&session_pkt.local_challenge);
+        hmac_digester.update(&self.server_challenge);
+        hmac_digester.update(&AUTH_CHECK_SEED);
+        hmac_digester
+            .verify_truncated_left(&session_pkt.digest)
+            .map_err(|_| {
+                reject(
+                    WowRpcResponse::Denied,
+                    format!("Bad AuthSession digest for {}", acc.account_name),
+                )
+            })?;
+        // Tickets are single use, a replayed one is already gone.
+        self.redis
+            .take::<Account>(&session_pkt.realm_join_ticket)
+            .await?
+            .ok_or_else(|| {
+                reject(
+                    WowRpcResponse::NoGameAccount,
+                    "Realm join ticket already used",
+                )
+            })?;
+        self.start_session(acc.account_name).await?;
+
+        let mut key_data_hasher = sha2::Sha256::new();
+        key_data_hasher.update(&*session_secret);
+        let key_data_hash = key_data_hasher.finalize();
+
+        let mut session_key_hasher = HmacSha256::new_from_slice(&key_data_hash).unwrap();
+        session_key_hasher.update(&self.server_challenge);
+        session_key_hasher.update(&session_pkt.local_challenge);
+        session_key_hasher.update(&SESSION_KEY_SEED);
+        let session_key_seed = session_key_hasher.finalize().into_bytes();
+
+        let session_key = Zeroizing::new(generate_session_key::<Sha256>(&session_key_seed, 40));
+        self.session_key.copy_from_slice(&session_key);
+        self.init_encryption_key(&session_pkt.local_challenge);
+        Ok(())
+    }
+
+    /// Joins the session that sent the `ConnectTo` naming this key, proven with its session key.
+    fn auth_continued_session(
+        &mut self,
+        opcode: u16,
+        session_pkt: AuthContinuedSession,
+    ) -> anyhow::Result<()> {
+        if session_pkt.key.connection_type() != ConnectionType::ConnectionTypeInstance {
+            return Err(reject(
+                WowRpcResponse::Denied,
+                "AuthContinuedSession only opens instance connections",
+            ));
+        }
+        let pending = self
+            .connect_to
+            .take(session_pkt.key)
+            .ok_or_else(|| reject(WowRpcResponse::Denied, "Unknown ConnectTo key"))?;
+        self.account_name = pending.account_name;
+        if pending.protocol.client_opcode(opcode) != Some(OpcodeClient::AuthContinuedSession) {
+            return Err(reject(
+                WowRpcResponse::BadVersion,
+                "Instance connection from another client build",
+            ));
         }
+        self.protocol = pending.protocol;
+
+        let mut hmac_digester = HmacSha256::new_from_slice(&pending.session_key[..]).unwrap();
+        hmac_digester.update(&session_pkt.key.raw.to_le_bytes());
+        hmac_digester.update(&session_pkt.local_challenge);
+        hmac_digester.update(&self.server_challenge);
+        hmac_digester.update(&CONTINUED_SESSION_SEED);
+        hmac_digester
+            .verify_truncated_left(&session_pkt.digest)
+            .map_err(|_| reject(WowRpcResponse::Denied, "Bad AuthContinuedSession digest"))?;
+
+        self.session_key.copy_from_slice(&pending.session_key[..]);
+        self.init_encryption_key(&session_pkt.local_challenge);
+        self.joining = Some(pending.joined);
+        Ok(())
+    }
+
+    fn init_encryption_key(&mut self, local_challenge: &[u8]) {
+        let mut encryption_key_hasher = HmacSha256::new_from_slice(&self.session_key[..]).unwrap();
+        encryption_key_hasher.update(local_challenge);
+        encryption_key_hasher.update(&self.server_challenge);
+        encryption_key_hasher.update(&ENCRYPTION_KEY_SEED);
+        let encryption_key = encryption_key_hasher.finalize().into_bytes();
+        self.encryption_key.copy_from_slice(&encryption_key[..16]);
     }
 
     pub(crate) async fn enter_encrypted_mode(&mut self) -> anyhow::Result<()> {
-        let encrypted_mode = EncryptedMode::new(&self.rsa, &self.encryption_key);
+        let encrypted_mode = EncryptedMode::new(self.rsa, &self.encryption_key[..])?;
         self.write_to_socket(Box::new(encrypted_mode)).await?;
         let packet = self.read_client_packet().await?;
-        if let ClientPacket::EnterEncryptedModeAck = packet {
-            self.aes_companion.init(&self.encryption_key)?;
-            Ok(())
-        } else {
-            Err(anyhow!("Expected AuthSession packet, got: {:?}", packet))
-        }
+        EnterEncryptedModeAck::try_from(packet)
+            .map_err(|e| reject(WowRpcResponse::Denied, e.to_string()))?;
+        self.aes_companion.init(&self.encryption_key[..])?;
+        Ok(())
     }
 }
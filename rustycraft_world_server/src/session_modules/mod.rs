@@ -1 +1,2 @@
-mod crypt;
\ No newline at end of file
+mod crypt;
+pub(crate) mod reader;
@@ -0,0 +1,214 @@
+use crate::connect_to::InstanceConnection;
+use crate::constants::MAX_CLIENT_PACKET_SIZE;
+use crate::opcodes::ConnectionType;
+use crate::packets::{PacketHeader, RawClientPacket};
+use crate::world_session::WorldClientSession;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+/// Reads one whole frame, `None` when the client closed the connection between two frames.
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> anyhow::Result<Option<RawClientPacket>> {
+    let size = match reader.read_u32_le().await {
+        Ok(size) => size,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if size > MAX_CLIENT_PACKET_SIZE {
+        bail!("Packet of {} bytes exceeds the limit", size);
+    }
+    let mut tag = vec![0; 12];
+    reader.read_exact(&mut tag).await?;
+    let mut payload = vec![0; size as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(RawClientPacket {
+        header: PacketHeader { size, tag },
+        payload: payload.into(),
+    }))
+}
+
+/// Owns the read half of the socket, so nothing racing the session can drop half a frame.
+/// Stops after the first error or once the session is gone.
+fn spawn_frame_reader<R: AsyncRead + Unpin + Send + 'static>(
+    addr: SocketAddr,
+    mut reader: R,
+) -> mpsc::Receiver<anyhow::Result<RawClientPacket>> {
+    let (frames_tx, frames_rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        loop {
+            let frame = tokio::select! {
+                frame = read_frame(&mut reader) => frame,
+                _ = frames_tx.closed() => break,
+            };
+            let frame = match frame {
+                Ok(Some(frame)) => Ok(frame),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let failed = frame.is_err();
+            if frames_tx.send(frame).await.is_err() || failed {
+                break;
+            }
+        }
+        trace!(target: "WorldSession", "[{:?}] Socket reader stopped", addr);
+    });
+    frames_rx
+}
+
+/// What the session loop waits for from the client.
+pub(crate) enum ClientEvent {
+    /// A decrypted packet and the connection it came from.
+    Packet(ConnectionType, Vec<u8>),
+    /// The instance socket finished its handshake, or gave up when `None`.
+    InstanceJoined(Option<Box<InstanceConnection>>),
+    InstanceClosed,
+    /// The realm connection closed, which ends the session.
+    Closed,
+}
+
+async fn next_instance_frame(
+    instance: &mut Option<InstanceConnection>,
+) -> Option<anyhow::Result<RawClientPacket>> {
+    match instance {
+        Some(instance) => instance.frames.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn instance_joined(
+    joined: &mut Option<oneshot::Receiver<InstanceConnection>>,
+) -> Option<InstanceConnection> {
+    let connection = match joined {
+        Some(joined) => joined.await.ok(),
+        None => std::future::pending().await,
+    };
+    *joined = None;
+    connection
+}
+
+impl WorldClientSession {
+    /// Starts reading frames, from now on packets come from `read_raw_packet`.
+    pub(crate) fn start_frame_reader(&mut self, reader: ReadHalf<TcpStream>) {
+        self.client_frames = Some(spawn_frame_reader(self.addr, reader));
+    }
+
+    /// Next decrypted packet, `None` once the client disconnected. Cancellation safe, a packet
+    /// is either returned whole or left for the next call.
+    pub(crate) async fn read_raw_packet(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        let frames = self
+            .client_frames
+            .as_mut()
+            .ok_or_else(|| anyhow!("Socket reader is not running"))?;
+        match frames.recv().await {
+            Some(frame) => {
+                let frame = frame?;
+                Ok(Some(
+                    self.aes_companion
+                        .decrypt(&frame.payload, &frame.header.tag)?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Next event of the realm connection, the instance connection or the `ConnectTo` waiting
+    /// for it. Cancellation safe like `read_raw_packet`.
+    pub(crate) async fn read_client_event(&mut self) -> anyhow::Result<ClientEvent> {
+        let frames = self
+            .client_frames
+            .as_mut()
+            .ok_or_else(|| anyhow!("Socket reader is not running"))?;
+        tokio::select! {
+            frame = frames.recv() => match frame {
+                Some(frame) => {
+                    let frame = frame?;
+                    let data = self.aes_companion.decrypt(&frame.payload, &frame.header.tag)?;
+                    Ok(ClientEvent::Packet(ConnectionType::ConnectionTypeRealm, data))
+                }
+                None => Ok(ClientEvent::Closed),
+            },
+            frame = next_instance_frame(&mut self.instance) => match (frame, self.instance.as_mut()) {
+                (Some(frame), Some(instance)) => {
+                    let frame = frame?;
+                    let data = instance.aes_companion.decrypt(&frame.payload, &frame.header.tag)?;
+                    Ok(ClientEvent::Packet(ConnectionType::ConnectionTypeInstance, data))
+                }
+                _ => Ok(ClientEvent::InstanceClosed),
+            },
+            connection = instance_joined(&mut self.instance_joined) => {
+                Ok(ClientEvent::InstanceJoined(connection.map(Box::new)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+        data.extend([7; 12]);
+        data.extend(payload);
+        data
+    }
+
+    fn addr() -> SocketAddr {
+        ([127, 0, 0, 1], 0).into()
+    }
+
+    #[tokio::test]
+    async fn test_frames_split_from_one_write() {
+        let (mut client, server) = duplex(1024);
+        let mut frames = spawn_frame_reader(addr(), server);
+        let mut data = frame(&[1, 2, 3]);
+        data.extend(frame(&[]));
+        data.extend(frame(&[4]));
+        client.write_all(&data).await.unwrap();
+        drop(client);
+
+        let first = frames.recv().await.unwrap().unwrap();
+        assert_eq!(first.header.size, 3);
+        assert_eq!(first.header.tag, [7; 12]);
+        assert_eq!(first.payload[..], [1, 2, 3]);
+        assert!(frames.recv().await.unwrap().unwrap().payload.is_empty());
+        assert_eq!(frames.recv().await.unwrap().unwrap().payload[..], [4]);
+        // A close between two frames is not an error.
+        assert!(frames.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_partial_reads() {
+        let (mut client, server) = duplex(1024);
+        let mut frames = spawn_frame_reader(addr(), server);
+        let data = frame(&[9; 40]);
+        for chunk in data.chunks(3) {
+            client.write_all(chunk).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        let frame = frames.recv().await.unwrap().unwrap();
+        assert_eq!(frame.payload[..], [9; 40]);
+
+        // The connection closing in the middle of a frame is.
+        client.write_all(&data[..20]).await.unwrap();
+        drop(client);
+        assert!(frames.recv().await.unwrap().is_err());
+        assert!(frames.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame() {
+        let (mut client, server) = duplex(1024);
+        let mut frames = spawn_frame_reader(addr(), server);
+        client
+            .write_all(&(MAX_CLIENT_PACKET_SIZE + 1).to_le_bytes())
+            .await
+            .unwrap();
+        assert!(frames.recv().await.unwrap().is_err());
+    }
+}
@@ -1,15 +1,5 @@
-use deku::bitvec::{BitVec, Msb0};
-use deku::prelude::*;
 use sha2::Digest;
 
-pub fn parse_string(input: Vec<u8>) -> Result<String, DekuError> {
-    String::from_utf8(input).map_err(|e| DekuError::Parse(e.to_string()))
-}
-
-pub fn string_writer(output: &mut BitVec<Msb0, u8>, field: &str) -> Result<(), DekuError> {
-    field.as_bytes().write(output, ())
-}
-
 fn get_digested<'lt, T>(o1: &'lt [u8], o0: &'lt [u8], o2: &'lt [u8]) -> Vec<u8>
 where
     T: Digest,
@@ -36,7 +26,7 @@ where
             res.push(*b);
             i += 1;
             if i >= size {
-                break
+                break;
             }
         }
         o0 = get_digested::<T>(&o1, &o0, &o2);
@@ -1,6 +1,11 @@
+use crate::connect_to::ConnectToRegistry;
 use crate::world_server::ServerEventEnum;
 use anyhow::anyhow;
 use bytes::Bytes;
+use rustycraft_common::sessions::SessionRegistry;
+use rustycraft_common::shutdown::Shutdown;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 
@@ -11,15 +16,30 @@ pub enum SocketEvents {
 }
 
 pub struct WorldSocketManagerBuilder {
+    bind_address: Option<SocketAddr>,
     world_server_channel: Option<mpsc::Sender<ServerEventEnum>>,
+    sessions: Option<Arc<SessionRegistry>>,
+}
+
+impl Default for WorldSocketManagerBuilder {
+    fn default() -> Self {
+        WorldSocketManagerBuilder::new()
+    }
 }
 
 impl WorldSocketManagerBuilder {
     pub fn new() -> WorldSocketManagerBuilder {
         WorldSocketManagerBuilder {
+            bind_address: None,
             world_server_channel: None,
+            sessions: None,
         }
     }
+    pub fn set_bind_address(&mut self, bind_address: SocketAddr) -> &mut Self {
+        self.bind_address = Some(bind_address);
+        self
+    }
+
     pub fn set_world_server_channel(
         &mut self,
         channel: mpsc::Sender<ServerEventEnum>,
@@ -28,36 +48,64 @@ impl WorldSocketManagerBuilder {
         self
     }
 
+    pub fn set_session_registry(&mut self, sessions: Arc<SessionRegistry>) -> &mut Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
     pub fn build(self) -> anyhow::Result<WorldSocketManager> {
-        let bind_address = "0.0.0.0:9900";
         Ok(WorldSocketManager {
-            bind_address,
+            bind_address: self
+                .bind_address
+                .ok_or_else(|| anyhow!("Bind address did not set"))?,
             world_server_channel: self
                 .world_server_channel
                 .ok_or_else(|| anyhow!("World server sender did not set"))?,
+            sessions: self
+                .sessions
+                .ok_or_else(|| anyhow!("Session registry did not set"))?,
+            connect_to: Arc::new(ConnectToRegistry::new()),
         })
     }
 }
 
 pub struct WorldSocketManager {
-    bind_address: &'static str,
+    bind_address: SocketAddr,
     world_server_channel: mpsc::Sender<ServerEventEnum>,
+    sessions: Arc<SessionRegistry>,
+    connect_to: Arc<ConnectToRegistry>,
 }
 
 impl WorldSocketManager {
-    pub async fn run_forever<T>(self) -> anyhow::Result<()>
+    /// Accepts connections until `shutdown` fires. Sessions keep a clone of `shutdown`
+    /// until they are closed, so the caller can wait for them to drain.
+    pub async fn run_forever<T>(self, mut shutdown: Shutdown) -> anyhow::Result<()>
     where
         T: WorldSessionHandler,
     {
-        let listener = TcpListener::bind(self.bind_address).await.unwrap();
+        let listener = TcpListener::bind(self.bind_address).await?;
 
         info!(target: "WorldSocketManager", "World server listening on: {}", self.bind_address);
 
         loop {
-            if let Ok((stream, _)) = listener.accept().await {
-                tokio::spawn(T::new(stream, self.world_server_channel.clone())?.handle());
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.recv() => break,
+            };
+            if let Ok((stream, _)) = accepted {
+                tokio::spawn(
+                    T::new(
+                        stream,
+                        self.world_server_channel.clone(),
+                        self.sessions.clone(),
+                        self.connect_to.clone(),
+                    )?
+                    .handle(shutdown.clone()),
+                );
             }
         }
+        info!(target: "WorldSocketManager", "World server stopped accepting connections");
+        Ok(())
     }
 }
 
@@ -66,8 +114,10 @@ pub trait WorldSessionHandler: 'static {
     fn new(
         socket: TcpStream,
         world_server_tx: mpsc::Sender<ServerEventEnum>, // Channel for communicate with world server
+        sessions: Arc<SessionRegistry>,
+        connect_to: Arc<ConnectToRegistry>, // Instance connections waiting for their socket
     ) -> anyhow::Result<Self>
     where
         Self: Sized;
-    async fn handle(mut self) -> anyhow::Result<()>;
+    async fn handle(mut self, shutdown: Shutdown) -> anyhow::Result<()>;
 }
@@ -1,39 +1,60 @@
+use crate::guid::GuidGenerators;
+use crate::handlers::{Handler, OpcodeHandler};
 use crate::opcodes::OpcodeClient;
-use crate::packets::auth::{Ping, Pong};
-use crate::packets::{ClientPacket, IntoServerPacket, RawClientPacket};
-use crate::OpcodeServer;
+use crate::packets::chat::ChatServerMessage;
+use crate::packets::{ClientPacket, IntoServerPacket};
 use anyhow::anyhow;
-use deku::DekuContainerRead;
+use rustycraft_common::sessions::SessionRegistry;
+use rustycraft_common::shutdown::Shutdown;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::{sleep_until, timeout, Instant};
+
+/// Remaining seconds at which players are reminded of a pending shutdown.
+const SHUTDOWN_NOTICES: &[u64] = &[600, 300, 120, 60, 30, 15, 10, 5, 4, 3, 2, 1];
 
 #[derive(Debug)]
 pub struct NewSession {
     pub addr: SocketAddr,
     pub sender: mpsc::Sender<Box<dyn IntoServerPacket>>,
+    /// Entry of the session in the `SessionRegistry`, as `(account name, session id)`.
+    pub registered: Option<(String, String)>,
 }
 
 #[derive(Debug)]
 pub enum ServerEventEnum {
     NewSession(NewSession),
-    NewClientPacket(SocketAddr, ClientPacket),
+    NewClientPacket(SocketAddr, OpcodeClient, ClientPacket),
+    SessionClosed(SocketAddr),
 }
 
-#[derive(Debug)]
 pub struct WorldServer {
-    connections: HashMap<SocketAddr, mpsc::Sender<Box<dyn IntoServerPacket>>>,
+    connections: HashMap<SocketAddr, NewSession>,
     events: mpsc::Receiver<ServerEventEnum>,
+    guids: GuidGenerators,
+    sessions: Arc<SessionRegistry>,
 }
 
-#[derive(Debug)]
 pub struct WorldServerBuilder {
     events: Option<mpsc::Receiver<ServerEventEnum>>,
+    sessions: Option<Arc<SessionRegistry>>,
+}
+
+impl Default for WorldServerBuilder {
+    fn default() -> Self {
+        WorldServerBuilder::new()
+    }
 }
 
 impl WorldServerBuilder {
     pub fn new() -> WorldServerBuilder {
-        WorldServerBuilder { events: None }
+        WorldServerBuilder {
+            events: None,
+            sessions: None,
+        }
     }
 
     pub fn get_event_sender(&mut self) -> mpsc::Sender<ServerEventEnum> {
@@ -42,36 +63,128 @@ impl WorldServerBuilder {
         tx
     }
 
+    pub fn set_session_registry(&mut self, sessions: Arc<SessionRegistry>) -> &mut Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
     pub fn build(self) -> anyhow::Result<WorldServer> {
         Ok(WorldServer {
             connections: Default::default(),
             events: self
                 .events
                 .ok_or_else(|| anyhow!("Events channel did not set"))?,
+            guids: GuidGenerators::new()?,
+            sessions: self
+                .sessions
+                .ok_or_else(|| anyhow!("Session registry did not set"))?,
         })
     }
 }
 
 impl WorldServer {
-    pub async fn run_forever(mut self) {
+    /// Ids for the characters, items and objects created by this world server.
+    pub fn guids(&mut self) -> &mut GuidGenerators {
+        &mut self.guids
+    }
+
+    /// Processes events until `shutdown` fires, then keeps the world running for
+    /// `countdown` while announcing the shutdown to players. Once it is over, flushes the
+    /// server state for at most `flush_timeout` and drops every session.
+    pub async fn run_forever(
+        mut self,
+        mut shutdown: Shutdown,
+        countdown: Duration,
+        flush_timeout: Duration,
+    ) {
+        let mut deadline: Option<Instant> = None;
+        let mut notices = SHUTDOWN_NOTICES
+            .iter()
+            .copied()
+            .filter(|&n| n < countdown.as_secs())
+            .peekable();
         loop {
-            let message = self.events.recv().await;
+            let next_notice = deadline.map(|deadline| match notices.peek() {
+                Some(&remaining) => deadline - Duration::from_secs(remaining),
+                None => deadline,
+            });
+            let message = tokio::select! {
+                message = self.events.recv() => message,
+                _ = shutdown.recv(), if deadline.is_none() => {
+                    info!(target: "WorldServer", "Shutting down in {} seconds", countdown.as_secs());
+                    deadline = Some(Instant::now() + countdown);
+                    self.announce_shutdown(countdown.as_secs()).await;
+                    continue;
+                }
+                _ = sleep_until(next_notice.unwrap_or_else(Instant::now)), if next_notice.is_some() => {
+                    match notices.next() {
+                        Some(remaining) => self.announce_shutdown(remaining).await,
+                        None => break,
+                    }
+                    continue;
+                }
+            };
             match message {
                 Some(ServerEventEnum::NewSession(session)) => {
-                    self.connections.insert(session.addr, session.sender);
+                    self.connections.insert(session.addr, session);
+                }
+                Some(ServerEventEnum::SessionClosed(addr)) => {
+                    self.connections.remove(&addr);
                 }
-                Some(ServerEventEnum::NewClientPacket(sender, packet)) => {
-                    let conn = self.connections.get(&sender).unwrap();
-                    match packet {
-                        ClientPacket::Ping(ping) => {
-                            let pong = Pong::from(ping);
-                            conn.send(Box::new(pong)).await.unwrap();
-                        },
-                        _ => ()
-                    };
+                Some(ServerEventEnum::NewClientPacket(sender, opcode, packet)) => {
+                    let handler = OpcodeHandler::find(opcode).map(|handler| handler.handler);
+                    if let Some(Handler::World(handle)) = handler {
+                        if let Err(e) = handle(&mut self, sender, packet).await {
+                            error!(target: "WorldServer", "[{:?}] Failed to handle {:?}: {}", sender, opcode, e);
+                        }
+                    }
                 }
                 None => break,
             }
         }
+        match timeout(flush_timeout, self.flush()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!(target: "WorldServer", "Failed to flush the server state: {}", e),
+            Err(_) => error!(target: "WorldServer", "Flushing the server state timed out"),
+        }
+        info!(target: "WorldServer", "Closing {} sessions", self.connections.len());
+        self.connections.clear();
+    }
+
+    /// Stores what must survive a restart: the unused guids go back to their counters, and
+    /// the sessions leave the `SessionRegistry` so players can log in again right away.
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        self.guids.flush().await?;
+        for session in self.connections.values() {
+            if let Some((account_name, session_id)) = &session.registered {
+                self.sessions.unregister(account_name, session_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues a packet to the session of `addr`.
+    pub async fn send(
+        &self,
+        addr: SocketAddr,
+        packet: Box<dyn IntoServerPacket>,
+    ) -> anyhow::Result<()> {
+        let conn = self
+            .connections
+            .get(&addr)
+            .ok_or_else(|| anyhow!("No session for {:?}", addr))?;
+        conn.sender
+            .send(packet)
+            .await
+            .map_err(|_| anyhow!("Session of {:?} is closed", addr))
+    }
+
+    async fn announce_shutdown(&self, remaining_secs: u64) {
+        for conn in self.connections.values() {
+            let _ = conn
+                .sender
+                .send(Box::new(ChatServerMessage::shutdown_time(remaining_secs)))
+                .await;
+        }
     }
 }
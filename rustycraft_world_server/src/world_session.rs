@@ -1,124 +1,489 @@
-use crate::crypt::{AES128Companion, INITIALIZED_RSA, RSA};
-use crate::opcodes::OpcodeClient;
-use crate::packets::auth::{AuthResponse, Ping, Pong};
+use crate::builds::WorldProtocol;
+use crate::connect_to::{ConnectToRegistry, InstanceConnection, PendingConnectTo};
+use crate::crypt::{self, AES128Companion, RSA};
+use crate::handlers::{Handler, OpcodeHandler, PacketRefused, SessionState};
+use crate::opcodes::ConnectionType;
+use crate::packets::auth::{
+    AuthResponse, AuthSuccessInfo, ConnectTo, ConnectToFailed, ConnectToKey, ConnectToSerial,
+    ResumeComms,
+};
 use crate::packets::client_config::ClientCacheVersion;
+use crate::packets::hotfix::{
+    AvailableHotfixes, DbQueryBulk, DbReply, HotfixConnect, HotfixRequest,
+};
 use crate::packets::system::{FeatureSystemStatusGlueScreen, SetTimeZoneInformation};
-use crate::packets::{ClientPacket, IntoServerPacket, PacketHeader, RawClientPacket, ServerPacket};
+use crate::packets::{ClientPacket, IntoServerPacket, RawClientPacket, ServerPacket};
+use crate::session_modules::reader::ClientEvent;
 use crate::world_listener::WorldSessionHandler;
 use crate::world_server::{NewSession, ServerEventEnum};
-use crate::OpcodeServer;
-use bytes::{Bytes, BytesMut};
-use deku::prelude::*;
 use rand::Rng;
+use rustycraft_common::accounts::unix_now;
+use rustycraft_common::audit::{AuditLog, AuditRecord};
+use rustycraft_common::config;
+use rustycraft_common::hotfixes::{self, HotfixStore};
+use rustycraft_common::sessions::SessionRegistry;
+use rustycraft_common::shutdown::Shutdown;
+use rustycraft_common::telemetry::{TelemetryEvent, TelemetryRecord, TelemetryStore};
+use rustycraft_common::Realm;
 use rustycraft_database::redis::RedisClient;
-use rustycraft_protocol::rpc_responses::WowRpcResponse;
+use rustycraft_protocol::rpc_responses::{RpcError, WowRpcResponse};
 use std::net::SocketAddr;
-use tokio::io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use std::sync::Arc;
+use tokio::io::{split, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{mpsc, oneshot};
+use zeroize::Zeroizing;
 
 pub struct WorldClientSession {
     pub(crate) addr: SocketAddr,
+    /// Where the client reached us, the default `ConnectTo` address.
+    pub(crate) local_addr: SocketAddr,
     pub(crate) redis: RedisClient,
+    pub(crate) telemetry: TelemetryStore,
+    pub(crate) hotfixes: HotfixStore,
+    pub(crate) audit: AuditLog,
+    pub(crate) sessions: Arc<SessionRegistry>,
+    pub(crate) account_name: Option<String>,
+    pub(crate) session_id: Option<String>,
+    pub(crate) kicks_tx: Sender<WowRpcResponse>,
+    pub(crate) kicks_rx: Option<Receiver<WowRpcResponse>>,
     pub(crate) rsa: &'static RSA,
     pub(crate) aes_companion: AES128Companion,
-    pub(crate) client_socket_reader: ReadHalf<TcpStream>,
+    /// Latest build until `AuthSession` tells otherwise.
+    pub(crate) protocol: &'static WorldProtocol,
+    /// Character selection once the handshake is done.
+    pub(crate) state: SessionState,
+    /// Handed over to the frame reader once the connection strings are exchanged.
+    pub(crate) client_socket_reader: Option<ReadHalf<TcpStream>>,
+    pub(crate) client_frames: Option<Receiver<anyhow::Result<RawClientPacket>>>,
     pub(crate) client_socket_writer: WriteHalf<TcpStream>,
     pub(crate) world_server_events: Sender<ServerEventEnum>,
     pub(crate) server_challenge: [u8; 16],
-    pub(crate) encryption_key: [u8; 16],
-    pub(crate) session_key: [u8; 40],
+    pub(crate) encryption_key: Zeroizing<[u8; 16]>,
+    pub(crate) session_key: Zeroizing<[u8; 40]>,
+    pub(crate) connect_to: Arc<ConnectToRegistry>,
+    /// Key of the `ConnectTo` the client was last sent, until its socket joins.
+    pub(crate) pending_connect_to: Option<ConnectToKey>,
+    pub(crate) instance_joined: Option<oneshot::Receiver<InstanceConnection>>,
+    pub(crate) instance: Option<InstanceConnection>,
+    /// Set on instance sockets, which hand themselves over once their handshake is done.
+    pub(crate) joining: Option<oneshot::Sender<InstanceConnection>>,
+    /// Connection the packet being handled came from.
+    pub(crate) received_on: ConnectionType,
 }
 
 impl WorldClientSession {
     pub(crate) async fn read_client_packet(&mut self) -> anyhow::Result<ClientPacket> {
-        let pkt_size = self.client_socket_reader.read_u32_le().await?;
-        let mut aes_token = Vec::with_capacity(12);
-        self.client_socket_reader.read_buf(&mut aes_token).await?;
-        let mut packet = BytesMut::with_capacity(pkt_size as usize);
-        self.client_socket_reader.read_buf(&mut packet).await?;
-
-        let decrypted = self.aes_companion.decrypt(&packet, &aes_token)?;
-        let result = ClientPacket::try_from(decrypted)?;
+        let decrypted = self
+            .read_raw_packet()
+            .await?
+            .ok_or_else(|| anyhow!("Client disconnected"))?;
+        let result = ClientPacket::parse(&decrypted, self.protocol)?;
         trace!("New packet from client: {:?}", &result);
         Ok(result)
     }
 
+    /// Sends the packet on the connection its opcode belongs to.
     pub(crate) async fn write_to_socket(
         &mut self,
         data: Box<dyn IntoServerPacket>,
+    ) -> anyhow::Result<()> {
+        let connection = data.connection();
+        self.write_to(connection, data).await
+    }
+
+    pub(crate) async fn write_to(
+        &mut self,
+        connection: ConnectionType,
+        mut data: Box<dyn IntoServerPacket>,
     ) -> anyhow::Result<()> {
         trace!("Plain packet: {:?}", &data);
-        let result = data.serialize()?;
-        let encrypted = self.aes_companion.encrypt(&result)?;
+        let (writer, aes_companion) = match (connection, self.instance.as_mut()) {
+            (
+                ConnectionType::ConnectionTypeInstance | ConnectionType::ConnectionTypeDefault,
+                Some(instance),
+            ) => (&mut instance.writer, &mut instance.aes_companion),
+            (ConnectionType::ConnectionTypeInstance, None) => {
+                error!(target: "WorldSession", "[{:?}] Prevented sending {:?} without an instance connection", self.addr, data.get_opcode());
+                return Ok(());
+            }
+            _ => (&mut self.client_socket_writer, &mut self.aes_companion),
+        };
+        rustycraft_metrics::WORLD_PACKETS
+            .with_label_values(&["server", &format!("{:?}", data.get_opcode())])
+            .inc();
+        let result = data.serialize(self.protocol)?;
+        let encrypted = aes_companion.encrypt(&result)?;
         let pkt = ServerPacket::new(encrypted.aes_tag, encrypted.cipher_text);
-        self.client_socket_writer.write(&pkt.serialize()?).await?;
+        writer.write_all(&pkt.serialize()?).await?;
         Ok(())
     }
 
-    pub(crate) async fn init_connection(&mut self) -> anyhow::Result<()> {
+    /// Handshake of either a new session or the instance connection of an existing one.
+    pub(crate) async fn init_connection(&mut self) -> anyhow::Result<ConnectionType> {
         self.initial_packets().await?;
-        self.init_encryption_state().await?;
+        let connection = self.init_encryption_state().await?;
         self.enter_encrypted_mode().await?;
-        self.init_session().await?;
+        if connection == ConnectionType::ConnectionTypeRealm {
+            self.init_session().await?;
+        }
+        Ok(connection)
+    }
 
-        Ok(())
+    /// Sends the client to open its instance connection. A previous attempt still waiting for
+    /// its socket is forgotten.
+    pub(crate) async fn connect_to_instance(
+        &mut self,
+        serial: ConnectToSerial,
+    ) -> anyhow::Result<()> {
+        if let Some(previous) = self.pending_connect_to.take() {
+            self.connect_to.cancel(previous);
+        }
+        let (joined, joined_rx) = oneshot::channel();
+        let key = self.connect_to.register(PendingConnectTo {
+            account_name: self.account_name.clone(),
+            protocol: self.protocol,
+            session_key: self.session_key.clone(),
+            joined,
+        });
+        self.pending_connect_to = Some(key);
+        self.instance_joined = Some(joined_rx);
+        let address = config::get()
+            .world
+            .instance_address
+            .unwrap_or(self.local_addr);
+        debug!(target: "WorldSession", "[{:?}] Sending {:?} to {}", self.addr, serial, address);
+        let connect_to = ConnectTo::new(self.rsa, address, serial, key)?;
+        self.write_to_socket(Box::new(connect_to)).await
+    }
+
+    /// Retries `ConnectTo` up to five times, like the client expects, then gives up.
+    pub(crate) async fn connect_to_failed(
+        &mut self,
+        failed: ConnectToFailed,
+    ) -> anyhow::Result<()> {
+        if self.pending_connect_to.is_none() {
+            debug!(target: "WorldSession", "[{:?}] Ignoring {:?}, no ConnectTo is pending", self.addr, failed);
+            return Ok(());
+        }
+        match failed.serial.next_attempt() {
+            Some(serial) => {
+                warn!(target: "WorldSession", "[{:?}] Instance connection {:?} failed, retrying", self.addr, failed.serial);
+                self.connect_to_instance(serial).await
+            }
+            None => bail!(
+                "Client could not open its instance connection ({:?})",
+                failed.serial
+            ),
+        }
+    }
+
+    /// The instance socket finished its handshake, or gave up when `None`.
+    async fn instance_joined(
+        &mut self,
+        connection: Option<Box<InstanceConnection>>,
+    ) -> anyhow::Result<()> {
+        self.pending_connect_to = None;
+        match connection {
+            Some(connection) => {
+                info!(target: "WorldSession", "[{:?}] Instance connection joined from {:?}", self.addr, connection.addr);
+                self.instance = Some(*connection);
+                self.state = SessionState::InWorld;
+                self.write_to_socket(Box::new(ResumeComms::default())).await
+            }
+            None => {
+                debug!(target: "WorldSession", "[{:?}] Instance connection failed its handshake", self.addr);
+                self.state = SessionState::CharSelect;
+                Ok(())
+            }
+        }
+    }
+
+    /// Moves the socket of this instance connection into the session that asked for it.
+    fn join_session(self, joined: oneshot::Sender<InstanceConnection>) -> anyhow::Result<()> {
+        let connection = InstanceConnection {
+            addr: self.addr,
+            frames: self
+                .client_frames
+                .ok_or_else(|| anyhow!("Socket reader is not running"))?,
+            writer: self.client_socket_writer,
+            aes_companion: self.aes_companion,
+        };
+        joined
+            .send(connection)
+            .map_err(|_| anyhow!("Session closed before its instance connection joined"))
     }
 
     async fn init_session(&mut self) -> anyhow::Result<()> {
-        let auth_response = AuthResponse::new(WowRpcResponse::Ok, None, None);
+        let virtual_realm_address = config::get().world.virtual_realm_address();
+        let realm = Realm::list(&self.redis)
+            .await?
+            .into_iter()
+            .find(|realm| realm.id == virtual_realm_address)
+            .unwrap_or_else(Realm::fallback);
+        let success_info =
+            AuthSuccessInfo::for_realm(virtual_realm_address, realm.name, unix_now());
+        let auth_response = AuthResponse::new(WowRpcResponse::Ok, Some(success_info), None);
         self.write_to_socket(Box::new(auth_response)).await?;
-        let tz_info = SetTimeZoneInformation::default();
+        let timezone = &config::get().world.timezone;
+        let tz_info = SetTimeZoneInformation::new(timezone.clone(), timezone.clone());
         self.write_to_socket(Box::new(tz_info)).await?;
         let features_glue_screen = FeatureSystemStatusGlueScreen::new();
         self.write_to_socket(Box::new(features_glue_screen)).await?;
-        let cache_version = ClientCacheVersion::new();
+        let hotfixes = self.hotfixes.list().await?;
+        let cache_version = ClientCacheVersion::new(hotfixes::cache_version(&hotfixes));
         self.write_to_socket(Box::new(cache_version)).await?;
+        let available_hotfixes =
+            AvailableHotfixes::new(config::get().world.virtual_realm_address(), &hotfixes);
+        self.write_to_socket(Box::new(available_hotfixes)).await?;
         Ok(())
     }
-}
 
-#[async_trait::async_trait]
-impl WorldSessionHandler for WorldClientSession {
-    fn new(socket: TcpStream, world_server_tx: Sender<ServerEventEnum>) -> anyhow::Result<Self> {
-        let peer_addr = socket.peer_addr()?;
-        let (reader, writer) = split(socket);
-        Ok(WorldClientSession {
-            rsa: &INITIALIZED_RSA,
-            redis: RedisClient::new().unwrap(),
-            addr: peer_addr,
-            client_socket_reader: reader,
-            client_socket_writer: writer,
-            world_server_events: world_server_tx,
-            server_challenge: rand::thread_rng().gen(),
-            encryption_key: [0; 16],
-            session_key: [0; 40],
-            aes_companion: AES128Companion::new(),
-        })
+    /// Sends the records of every push the client asked for.
+    pub(crate) async fn send_hotfixes(&mut self, request: HotfixRequest) -> anyhow::Result<()> {
+        let requested = self.hotfixes.pushes(&request.push_ids).await?;
+        self.write_to_socket(Box::new(HotfixConnect::new(&requested)))
+            .await
+    }
+
+    pub(crate) async fn send_db_replies(&mut self, query: DbQueryBulk) -> anyhow::Result<()> {
+        let timestamp = unix_now() as u32;
+        for record_id in query.record_ids {
+            let hotfix = self.hotfixes.get(query.table_hash, record_id).await?;
+            let reply = DbReply::new(query.table_hash, record_id, hotfix.as_ref(), timestamp);
+            self.write_to_socket(Box::new(reply)).await?;
+        }
+        Ok(())
     }
 
-    async fn handle(mut self) -> anyhow::Result<()> {
-        self.init_connection().await?;
+    /// Makes this connection the active world session of the account, kicking any previous one.
+    pub(crate) async fn start_session(&mut self, account_name: String) -> anyhow::Result<()> {
+        let session_id = self
+            .sessions
+            .register(&account_name, self.kicks_tx.clone())
+            .await?;
+        self.account_name = Some(account_name);
+        self.session_id = Some(session_id);
+        Ok(())
+    }
+
+    async fn end_session(&mut self) {
+        if let Some(key) = self.pending_connect_to.take() {
+            self.connect_to.cancel(key);
+        }
+        if let (Some(account_name), Some(session_id)) = (&self.account_name, &self.session_id) {
+            if let Err(e) = self.sessions.unregister(account_name, session_id).await {
+                error!(target: "WorldSession", "[{:?}] Failed to release session: {}", self.addr, e);
+            }
+        }
+        self.session_id = None;
+    }
+
+    /// Closes the realm socket, and the instance socket if one joined.
+    async fn close(&mut self) {
+        let _ = self.client_socket_writer.shutdown().await;
+        if let Some(instance) = self.instance.as_mut() {
+            let _ = instance.writer.shutdown().await;
+        }
+    }
+
+    /// Stores a client report. Failing to do so never ends the session.
+    pub(crate) async fn report(&self, event: TelemetryEvent) {
+        let record = TelemetryRecord::new(
+            self.addr,
+            self.account_name.clone(),
+            Some(config::get().world.realm_id),
+            event,
+        );
+        if let Err(e) = self.telemetry.record(&record).await {
+            error!(target: "WorldSession", "[{:?}] Failed to store telemetry: {}", self.addr, e);
+        }
+    }
+
+    /// Runs the registered handler of the packet. Packets without one, not allowed in the
+    /// current state or malformed are skipped, only failing handlers end the session.
+    async fn handle_client_packet(
+        &mut self,
+        connection: ConnectionType,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let (handler, client_event) = match OpcodeHandler::check(data, self.protocol, self.state) {
+            Ok(packet) => packet,
+            Err(refused @ (PacketRefused::Unknown(_) | PacketRefused::Unhandled(_))) => {
+                info!(target: "WorldSession", "[{:?}] Skipping client packet: {}", self.addr, refused);
+                return Ok(());
+            }
+            Err(refused) => {
+                warn!(target: "WorldSession", "[{:?}] Refusing client packet: {}", self.addr, refused);
+                return Ok(());
+            }
+        };
+        debug!(target: "WorldSession", "[{:?}] New packet received from client: {:?}", self.addr, client_event);
+        self.received_on = connection;
+        match handler.handler {
+            Handler::Handshake => {
+                warn!(target: "WorldSession", "[{:?}] {:?} is only expected during the handshake", self.addr, handler.opcode);
+                Ok(())
+            }
+            Handler::Session(handle) => handle(self, client_event).await,
+            Handler::World(_) => {
+                let event =
+                    ServerEventEnum::NewClientPacket(self.addr, handler.opcode, client_event);
+                self.world_server_events.send(event).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Tells the client why its handshake failed, audits it and closes the socket.
+    async fn reject(&mut self, error: &anyhow::Error) {
+        let status = error
+            .downcast_ref::<RpcError>()
+            .map_or(WowRpcResponse::WowServicesCantConnect, RpcError::status);
+        let record = AuditRecord::new(
+            self.addr,
+            self.account_name.clone(),
+            status,
+            error.to_string(),
+        );
+        if let Err(e) = self.audit.record(&record).await {
+            error!(target: "WorldSession", "[{:?}] Failed to audit handshake: {}", self.addr, e);
+        }
+        let response = AuthResponse::new(status, None, None);
+        if let Err(e) = self.write_to_socket(Box::new(response)).await {
+            debug!(target: "WorldSession", "[{:?}] Failed to send handshake failure: {}", self.addr, e);
+        }
+        let _ = self.client_socket_writer.shutdown().await;
+    }
+
+    async fn serve(&mut self, shutdown: &mut Shutdown) -> anyhow::Result<()> {
+        let connection = tokio::select! {
+            connection = self.init_connection() => connection,
+            _ = shutdown.recv() => return Ok(()),
+        };
+        match connection {
+            Ok(ConnectionType::ConnectionTypeRealm) => {}
+            // Served by the session it joins from now on.
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                rustycraft_metrics::HANDSHAKE_FAILURES
+                    .with_label_values(&["world"])
+                    .inc();
+                self.reject(&e).await;
+                return Err(e);
+            }
+        }
         let (world_tx, mut world_rx) = mpsc::channel(2048);
+        let mut kicks_rx = self
+            .kicks_rx
+            .take()
+            .ok_or_else(|| anyhow!("Session is already running"))?;
         self.world_server_events
             .send(ServerEventEnum::NewSession(NewSession {
                 addr: self.addr,
                 sender: world_tx,
+                registered: self.account_name.clone().zip(self.session_id.clone()),
             }))
             .await?;
         loop {
             tokio::select! {
-                Some(server_event) = world_rx.recv() => {
-                    debug!(target: "WorldSession", "[{:?}] New packet received from server: {:?}", self.addr, server_event);
-                    self.write_to_socket(server_event).await?;
+                server_event = world_rx.recv() => match server_event {
+                    Some(server_event) => {
+                        debug!(target: "WorldSession", "[{:?}] New packet received from server: {:?}", self.addr, server_event);
+                        self.write_to_socket(server_event).await?;
+                    }
+                    // The world server dropped us once its shutdown countdown ran out.
+                    None => {
+                        info!(target: "WorldSession", "[{:?}] Server is shutting down", self.addr);
+                        break;
+                    }
+                },
+                event = self.read_client_event() => match event? {
+                    ClientEvent::Packet(connection, data) => {
+                        self.handle_client_packet(connection, &data).await?
+                    }
+                    ClientEvent::InstanceJoined(connection) => self.instance_joined(connection).await?,
+                    ClientEvent::InstanceClosed => {
+                        info!(target: "WorldSession", "[{:?}] Instance connection closed", self.addr);
+                        self.instance = None;
+                    }
+                    ClientEvent::Closed => {
+                        info!(target: "WorldSession", "[{:?}] Client disconnected", self.addr);
+                        break;
+                    }
                 },
-                Ok(client_event) = self.read_client_packet() => {
-                    debug!(target: "WorldSession", "[{:?}] New packet received from client: {:?}", self.addr, client_event);
-                    self.world_server_events.send(ServerEventEnum::NewClientPacket(self.addr, client_event)).await?;
+                Some(reason) = kicks_rx.recv() => {
+                    info!(target: "WorldSession", "[{:?}] Session kicked: {:?}", self.addr, reason);
+                    self.write_to_socket(Box::new(AuthResponse::new(reason, None, None))).await?;
+                    break;
                 }
             };
         }
         Ok(())
     }
 }
+
+#[async_trait::async_trait]
+impl WorldSessionHandler for WorldClientSession {
+    fn new(
+        socket: TcpStream,
+        world_server_tx: Sender<ServerEventEnum>,
+        sessions: Arc<SessionRegistry>,
+        connect_to: Arc<ConnectToRegistry>,
+    ) -> anyhow::Result<Self> {
+        let peer_addr = socket.peer_addr()?;
+        let local_addr = socket.local_addr()?;
+        let (reader, writer) = split(socket);
+        let (kicks_tx, kicks_rx) = mpsc::channel(1);
+        Ok(WorldClientSession {
+            rsa: crypt::signing_key()?,
+            redis: RedisClient::new(&config::get().redis.url)?,
+            telemetry: TelemetryStore::new()?,
+            hotfixes: HotfixStore::new()?,
+            audit: AuditLog::new()?,
+            sessions,
+            account_name: None,
+            session_id: None,
+            kicks_tx,
+            kicks_rx: Some(kicks_rx),
+            addr: peer_addr,
+            local_addr,
+            client_socket_reader: Some(reader),
+            client_frames: None,
+            client_socket_writer: writer,
+            world_server_events: world_server_tx,
+            server_challenge: rand::thread_rng().gen(),
+            encryption_key: Zeroizing::new([0; 16]),
+            session_key: Zeroizing::new([0; 40]),
+            connect_to,
+            pending_connect_to: None,
+            instance_joined: None,
+            instance: None,
+            joining: None,
+            received_on: ConnectionType::ConnectionTypeRealm,
+            aes_companion: AES128Companion::new(),
+            protocol: WorldProtocol::latest(),
+            state: SessionState::CharSelect,
+        })
+    }
+
+    async fn handle(mut self, mut shutdown: Shutdown) -> anyhow::Result<()> {
+        let connected = rustycraft_metrics::SESSIONS.with_label_values(&["world"]);
+        connected.inc();
+        let result = self.serve(&mut shutdown).await;
+        let _ = self
+            .world_server_events
+            .send(ServerEventEnum::SessionClosed(self.addr))
+            .await;
+        self.end_session().await;
+        connected.dec();
+        match self.joining.take() {
+            Some(joined) if result.is_ok() => self.join_session(joined),
+            _ => {
+                self.close().await;
+                result
+            }
+        }
+    }
+}